@@ -0,0 +1,168 @@
+use ::*;
+
+/// Send an account change. `done` is shown once the server confirms it with a new token.
+fn send_update(app: &Rc<App>, window: &Window, id: ServerId, update: common::LoginUpdate, done: &'static str) -> bool {
+    let mut sent = false;
+    app.connections.execute(id, |result| match result {
+        Ok(synac) => match synac.write(&Packet::LoginUpdate(update)) {
+            Ok(()) => sent = true,
+            Err(err) => alert(window, MessageType::Error, &format!("failed to send: {}", err))
+        },
+        Err(_) => alert(window, MessageType::Error, "Not connected to this server")
+    });
+    if sent {
+        app.account_pending.borrow_mut().insert(id, done);
+    }
+    sent
+}
+
+/// Handle the reply to an account change, on any server
+pub(crate) fn packet(app: &Rc<App>, id: ServerId, packet: &Packet) {
+    match *packet {
+        // After logging in, this is only sent when the token changed
+        Packet::LoginSuccess(ref login) => {
+            if let Err(err) = app.connections.vault.lock().unwrap().save_token(&app.db, id, &login.token) {
+                alert(&app.window, MessageType::Warning, &format!("failed to save the new token: {}", err));
+            }
+            if let Some(done) = app.account_pending.borrow_mut().remove(&id) {
+                alert(&app.window, MessageType::Info, done);
+            }
+        },
+        Packet::Err(common::ERR_LOGIN_INVALID) => if app.account_pending.borrow_mut().remove(&id).is_some() {
+            alert(&app.window, MessageType::Error, "Wrong current password");
+        },
+        _ => ()
+    }
+}
+
+/// Forget the saved token and connect again, asking for the password
+pub(crate) fn relogin(app: &Rc<App>, id: ServerId) {
+    app.connections.remove(id);
+    app.db.execute("UPDATE servers SET token = NULL, token_sealed = NULL WHERE id = ?", &[&id]).unwrap();
+    app.account_pending.borrow_mut().remove(&id);
+    app.dirty.borrow_mut().servers = true;
+    select_server(app, id);
+}
+
+pub(crate) fn show(app: &Rc<App>, id: ServerId) {
+    let label = app.db.query_row(
+        "SELECT name, account FROM servers WHERE id = ?",
+        &[&id],
+        |row| server_label(&row.get::<_, String>(0), &row.get::<_, String>(1))
+    );
+    let label = match label {
+        Ok(label) => label,
+        Err(err) => {
+            alert(&app.window, MessageType::Error, &err.to_string());
+            return;
+        }
+    };
+    let mut name = None;
+    app.connections.execute(id, |result| if let Ok(synac) = result {
+        name = synac.state.users.get(&synac.user).map(|user| user.name.clone());
+    });
+
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title(&format!("Synac: Account on {}", label));
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(400, 0);
+
+    let container = GtkBox::new(Orientation::Vertical, 6);
+    container.set_property_margin(10);
+
+    add_text(&container, &match name {
+        Some(ref name) => format!("Logged in to {} as {}.", label, name),
+        None => format!("Not connected to {}.", label)
+    });
+
+    if name.is_some() {
+        add_heading(&container, "Change password");
+
+        let current = password_entry("Current password...");
+        container.add(&current);
+        let new = password_entry("New password...");
+        container.add(&new);
+        let confirmation = password_entry("Confirm new password...");
+        container.add(&confirmation);
+
+        let change = Button::new_with_mnemonic("_Change password");
+        change.set_halign(Align::End);
+
+        let app_clone = Rc::clone(app);
+        let window_clone = window.clone();
+        change.connect_clicked(move |_| {
+            let current_text = current.get_text().unwrap_or_default();
+            let new_text = new.get_text().unwrap_or_default();
+            if new_text.is_empty() {
+                alert(&window_clone, MessageType::Error, "The new password can't be empty");
+                return;
+            }
+            if confirmation.get_text().unwrap_or_default() != new_text {
+                alert(&window_clone, MessageType::Error, "The new passwords don't match");
+                return;
+            }
+            let sent = send_update(&app_clone, &window_clone, id, common::LoginUpdate {
+                name: None,
+                password_current: Some(current_text),
+                password_new: Some(new_text),
+                reset_token: false
+            }, "Password changed. Other devices need to log in again.");
+            if sent {
+                window_clone.destroy();
+            }
+        });
+        container.add(&change);
+
+        add_heading(&container, "Log out everywhere");
+        add_text(&container, "Get a new login token. Everywhere else, the old one stops working \
+                              and a password is needed to log in again.");
+
+        let reset = Button::new_with_mnemonic("_Reset token");
+        reset.set_halign(Align::End);
+
+        let app_clone = Rc::clone(app);
+        let window_clone = window.clone();
+        reset.connect_clicked(move |_| {
+            let app_clone = Rc::clone(&app_clone);
+            let window_clone2 = window_clone.clone();
+            confirm(&window_clone, "Log out all other devices?", move || {
+                let sent = send_update(&app_clone, &window_clone2, id, common::LoginUpdate {
+                    name: None,
+                    password_current: None,
+                    password_new: None,
+                    reset_token: true
+                }, "Logged out everywhere else.");
+                if sent {
+                    window_clone2.destroy();
+                }
+            });
+        });
+        container.add(&reset);
+    }
+
+    add_heading(&container, "Log in again");
+    add_text(&container, "Forget the saved token and log in with your password.");
+
+    let relogin_button = Button::new_with_mnemonic("_Log in...");
+    relogin_button.set_halign(Align::End);
+
+    let app_clone = Rc::clone(app);
+    let window_clone = window.clone();
+    relogin_button.connect_clicked(move |_| {
+        window_clone.destroy();
+        relogin(&app_clone, id);
+    });
+    container.add(&relogin_button);
+
+    let close = Button::new_with_mnemonic("C_lose");
+    close.set_halign(Align::End);
+    close.set_margin_top(10);
+
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    container.add(&close);
+
+    window.add(&container);
+    window.show_all();
+}
@@ -0,0 +1,100 @@
+use getopts::Options as GetOpts;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+#[derive(Debug, Fail)]
+pub enum CliError {
+    #[fail(display = "{}", _0)]
+    Parse(String),
+    #[fail(display = "--profile and --db can't be used together")]
+    ProfileAndDb,
+    #[fail(display = "invalid profile name {:?}: only letters, digits, - and _ are allowed", _0)]
+    InvalidProfile(String),
+    #[fail(display = "--connect requires --hash")]
+    MissingHash,
+    #[fail(display = "--hash is only used with --connect")]
+    StrayHash
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub help: bool,
+    pub profile: Option<String>,
+    pub db: Option<PathBuf>,
+    pub connect: Option<String>,
+    pub hash: Option<String>,
+    pub nick: Option<String>,
+    pub log: Option<String>,
+    pub verbose: bool
+}
+
+fn getopts() -> GetOpts {
+    let mut opts = GetOpts::new();
+    opts.optflag("h", "help", "print this help and exit");
+    opts.optopt("p", "profile", "use a separate database for this profile", "NAME");
+    opts.optopt("", "db", "use the database at this path", "PATH");
+    opts.optopt("c", "connect", "connect to this server on startup", "HOST[:PORT]");
+    opts.optopt("", "hash", "the certificate hash of the --connect server", "HEX");
+    opts.optopt("n", "nick", "use this nickname for this session", "NICK");
+    opts.optopt("", "log", "log filter, such as info,client_gtk::connections=debug", "FILTER");
+    opts.optflag("v", "verbose", "log every sent and received packet");
+    opts
+}
+pub fn usage(program: &str) -> String {
+    getopts().usage(&format!("Usage: {} [options]", program))
+}
+pub fn parse<C>(args: C) -> Result<Options, CliError>
+    where C: IntoIterator,
+          C::Item: AsRef<OsStr>
+{
+    let matches = getopts().parse(args).map_err(|err| CliError::Parse(err.to_string()))?;
+
+    let options = Options {
+        help: matches.opt_present("help"),
+        profile: matches.opt_str("profile"),
+        db: matches.opt_str("db").map(PathBuf::from),
+        connect: matches.opt_str("connect"),
+        hash: matches.opt_str("hash"),
+        nick: matches.opt_str("nick"),
+        log: matches.opt_str("log"),
+        verbose: matches.opt_present("verbose")
+    };
+
+    if options.profile.is_some() && options.db.is_some() {
+        return Err(CliError::ProfileAndDb);
+    }
+    if let Some(ref profile) = options.profile {
+        if profile.is_empty() || !profile.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            return Err(CliError::InvalidProfile(profile.clone()));
+        }
+    }
+    match (&options.connect, &options.hash) {
+        (&Some(_), &None) => return Err(CliError::MissingHash),
+        (&None, &Some(_)) => return Err(CliError::StrayHash),
+        _ => ()
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    assert_eq!(parse(&[] as &[&str]).unwrap(), Options::default());
+    assert_eq!(parse(&["--profile", "work", "-v"]).unwrap(), Options {
+        profile: Some(String::from("work")),
+        verbose: true,
+        ..Default::default()
+    });
+    assert_eq!(parse(&["-c", "krake.one", "--hash", "C9F6", "--nick", "jD91mZM2"]).unwrap(), Options {
+        connect: Some(String::from("krake.one")),
+        hash: Some(String::from("C9F6")),
+        nick: Some(String::from("jD91mZM2")),
+        ..Default::default()
+    });
+    assert!(parse(&["--profile", "work", "--db", "/tmp/x.sqlite"]).is_err());
+    assert!(parse(&["--profile", "../escape"]).is_err());
+    assert!(parse(&["--connect", "krake.one"]).is_err());
+    assert!(parse(&["--hash", "C9F6"]).is_err());
+    assert!(parse(&["--bogus"]).is_err());
+}
@@ -1,18 +1,74 @@
 use failure::Error;
+use inspector::{Capture, Direction};
+use logger;
 use messages::Messages;
-use rusqlite::Connection as SqlConnection;
+use proxy::{self, Proxy};
+use rusqlite::{Connection as SqlConnection, Row};
 use std::collections::HashMap;
 use std::mem;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{self, JoinHandle};
 use synac::common::{self, Packet};
 use synac::{Listener, Session, State};
 use typing::Typing;
+use unread::Unread;
+use vault::Vault;
+
+/// The row id of a server entry.
+/// Entries are unique by address and account, so one server can be joined as several users.
+pub type ServerId = i64;
+
+/// A saved server, as stored in the `servers` table
+#[derive(Clone, Debug, Default)]
+pub struct ServerEntry {
+    pub id: ServerId,
+    pub host: String,
+    pub hash: String,
+    /// The token, if saved in plain text
+    pub token: Option<String>,
+    /// The token, if saved in the vault
+    pub token_sealed: Option<Vec<u8>>,
+    /// The nick for this server, or `None` for the default nick
+    pub nick: Option<String>,
+    /// The proxy URL for this server, or `None` for the global proxy
+    pub proxy: Option<String>
+}
+impl ServerEntry {
+    const COLUMNS: &'static str = "id, ip, hash, token, token_sealed, nick, proxy";
+
+    fn from_row(row: &Row) -> Self {
+        ServerEntry {
+            id: row.get(0),
+            host: row.get(1),
+            hash: row.get(2),
+            token: row.get(3),
+            token_sealed: row.get(4),
+            nick: row.get(5),
+            proxy: row.get(6)
+        }
+    }
+    pub fn load(db: &SqlConnection, id: ServerId) -> Result<Self, Error> {
+        let query = format!("SELECT {} FROM servers WHERE id = ?", Self::COLUMNS);
+        Ok(db.query_row(&query, &[&id], Self::from_row)?)
+    }
+    pub fn all(db: &SqlConnection) -> Result<Vec<Self>, Error> {
+        let mut stmt = db.prepare(&format!("SELECT {} FROM servers", Self::COLUMNS))?;
+        let rows = stmt.query_map(&[], Self::from_row)?;
+
+        let mut servers = Vec::new();
+        for row in rows {
+            servers.push(row?);
+        }
+        Ok(servers)
+    }
+}
 
 #[derive(Debug, Fail)]
 pub enum ConnectionError {
+    #[fail(display = "invalid address: {}", _0)]
+    InvalidAddress(String),
     #[fail(display = "invalid packet: {:?}", _0)]
     InvalidPacket(Packet),
     #[fail(display = "invalid token: password authentication needed")]
@@ -22,20 +78,34 @@ pub enum ConnectionError {
 }
 
 pub struct Synac {
+    pub id: ServerId,
+    /// The address as saved, like `example.com:8439`
+    pub host: String,
+    /// The address this session is connected to, which is the proxy's if one is used
     pub addr: SocketAddr,
+    /// The server's address as shown in logs and the packet inspector.
+    /// Through a proxy, that's the host and port the proxy was asked for.
+    pub peer: String,
     pub session: Session,
     pub listener: Listener,
     pub state: State,
 
+    pub capture: Arc<Mutex<Capture>>,
     pub current_channel: Option<usize>,
     pub messages: Messages,
     pub typing: Typing,
+    pub unread: Unread,
     pub user: usize
 }
 impl Synac {
-    pub fn new(addr: SocketAddr, session: Session, user: usize) -> Self {
+    pub fn new(id: ServerId, host: String, addr: SocketAddr, peer: String, session: Session, user: usize,
+            capture: Arc<Mutex<Capture>>) -> Self {
         Synac {
+            id: id,
+            host: host,
             addr: addr,
+            peer: peer,
+            capture: capture,
             listener: Listener::new(),
             session: session,
             state: State::new(),
@@ -43,9 +113,16 @@ impl Synac {
             current_channel: None,
             messages: Messages::new(),
             typing: Typing::new(),
+            unread: Unread::new(),
             user: user
         }
     }
+    /// Send a packet, recording it for logs and the packet inspector
+    pub fn write(&mut self, packet: &Packet) -> Result<(), Error> {
+        trace!(target: logger::PACKETS, "sent to {}: {}", self.peer, logger::packet(packet));
+        self.capture.lock().unwrap().record(&self.peer, Direction::Sent, packet);
+        self.session.write(packet)
+    }
 }
 
 pub enum Connection {
@@ -69,57 +146,121 @@ impl Connection {
 }
 
 pub struct Connections {
-    pub current_server: Mutex<Option<SocketAddr>>,
+    pub capture: Arc<Mutex<Capture>>,
+    pub current_server: Mutex<Option<ServerId>>,
+    /// The nick used by servers without their own
     pub nick: RwLock<String>,
-    pub servers: Arc<Mutex<HashMap<SocketAddr, Connection>>>
+    /// The proxy used by servers without their own
+    pub proxy: RwLock<Option<Proxy>>,
+    pub servers: Arc<Mutex<HashMap<ServerId, Connection>>>,
+    pub vault: Mutex<Vault>
 }
 impl Connections {
-    pub fn new(db: &SqlConnection, nick: String) -> Arc<Self> {
+    pub fn new(db: &SqlConnection, nick: String, proxy: Option<Proxy>, vault: Vault) -> Arc<Self> {
         let me = Arc::new(Connections {
+            capture: Arc::new(Mutex::new(Capture::new())),
             current_server: Mutex::new(None),
             nick: RwLock::new(nick),
-            servers: Arc::new(Mutex::new(HashMap::new()))
+            proxy: RwLock::new(proxy),
+            servers: Arc::new(Mutex::new(HashMap::new())),
+            vault: Mutex::new(vault)
         });
         {
             let mut servers = me.servers.lock().unwrap();
 
-            let mut stmt = db.prepare("SELECT ip, hash, token FROM servers").unwrap();
-            let mut rows = stmt.query(&[]).unwrap();
-
-            while let Some(row) = rows.next() {
-                let row = row.unwrap();
-                let addr  = match parse_addr(&row.get::<_, String>(0)) {
-                    Some(addr) => addr,
-                    None => {
-                        eprintln!("invalid socket address, skipping");
-                        continue;
-                    }
-                };
-                let hash  = row.get(1);
-                let token = row.get(2);
+            for server in ServerEntry::all(db).unwrap() {
+                if parse_host(&server.host).is_none() {
+                    warn!("invalid server address {:?}, skipping", server.host);
+                    continue;
+                }
+                let id = server.id;
 
                 let me_clone = Arc::clone(&me);
-                servers.insert(addr, Connection::Connecting(thread::spawn(move || {
-                    me_clone.connect(addr, hash, token, || None)
-                        .map_err(|err| { eprintln!("connect error: {}", err); err })
+                servers.insert(id, Connection::Connecting(thread::spawn(move || {
+                    me_clone.connect(server, || None)
+                        .map_err(|err| { error!("connect error: {}", err); err })
                 })));
             }
         }
 
         me
     }
-    pub fn connect<F>(&self, addr: SocketAddr, hash: String, token: Option<String>, password: F)
-        -> Result<Synac, Error>
+    /// Open a session, through the server's proxy or the global one if it has none.
+    /// Without a proxy, the host is resolved again every time and each address is tried in turn.
+    /// Returns the address connected to and the server's address to show for it.
+    fn open(&self, server: &ServerEntry) -> Result<(SocketAddr, String, Session), Error> {
+        let proxy = match server.proxy {
+            Some(ref url) => Proxy::parse(url)?,
+            None => self.proxy.read().unwrap().clone()
+        };
+        if let Some(proxy) = proxy {
+            let (host, port) = parse_host(&server.host)
+                .ok_or_else(|| ConnectionError::InvalidAddress(server.host.clone()))?;
+            debug!("connecting to {} through {}", server.host, proxy);
+            let stream = proxy.connect(host, port)?;
+            let addr = stream.peer_addr()?;
+            let relay = proxy::relay(stream)?;
+            let mut session = Session::new(relay.addr, server.hash.clone())?;
+            relay.check(session.inner_stream().get_ref().local_addr()?)?;
+            return Ok((addr, proxy::join_host(host, port), session));
+        }
+
+        let mut last_err = None;
+        for addr in resolve(&server.host)? {
+            match Session::new(addr, server.hash.clone()) {
+                Ok(session) => return Ok((addr, addr.to_string(), session)),
+                Err(err) => {
+                    debug!("failed to connect to {} ({}): {}", server.host, addr, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| ConnectionError::InvalidAddress(server.host.clone()).into()))
+    }
+    /// Send a login packet and wait for the reply.
+    /// This is before there's a `Synac`, so it does the logging and recording `Synac::write` would.
+    fn login(&self, session: &mut Session, peer: &str, login: common::Login) -> Result<Packet, Error> {
+        let packet = Packet::Login(login);
+        trace!(target: logger::PACKETS, "sent to {}: {}", peer, logger::packet(&packet));
+        self.capture.lock().unwrap().record(peer, Direction::Sent, &packet);
+        session.write(&packet)?;
+
+        let reply = session.read()?;
+        trace!(target: logger::PACKETS, "received from {}: {}", peer, logger::packet(&reply));
+        self.capture.lock().unwrap().record(peer, Direction::Received, &reply);
+        Ok(reply)
+    }
+    /// Connect and log in, as the server's nick or the default nick if it has none
+    pub fn connect<F>(&self, server: ServerEntry, password: F) -> Result<Synac, Error>
         where F: FnOnce() -> Option<(String, Rc<SqlConnection>)>
     {
-        let mut session = Session::new(addr, hash)?;
+        let (addr, peer, mut session) = self.open(&server)?;
+        let ServerEntry { id, host, token, token_sealed, nick, .. } = server;
+        let nick = nick.unwrap_or_else(|| self.nick.read().unwrap().clone());
+
+        let token = match (token, token_sealed) {
+            (Some(token), _) => Some(token),
+            (None, Some(sealed)) => match self.vault.lock().unwrap().open_token(id, &sealed) {
+                Ok(token) => Some(token),
+                Err(err) => {
+                    debug!("can't use the saved token for {}: {}", host, err);
+                    None
+                }
+            },
+            (None, None) => None
+        };
 
         if let Some(token) = token {
-            session.login_with_token(false, self.nick.read().unwrap().clone(), token)?;
-            match session.read()? {
+            let reply = self.login(&mut session, &peer, common::Login {
+                bot: false,
+                name: nick.clone(),
+                password: None,
+                token: Some(token)
+            })?;
+            match reply {
                 Packet::LoginSuccess(login) => {
                     session.set_nonblocking(true)?;
-                    return Ok(Synac::new(addr, session, login.id));
+                    return Ok(Synac::new(id, host, addr, peer, session, login.id, Arc::clone(&self.capture)));
                 },
                 Packet::Err(common::ERR_UNKNOWN_USER) |
                 Packet::Err(common::ERR_LOGIN_INVALID) => {},
@@ -127,12 +268,19 @@ impl Connections {
             }
         }
         if let Some((password, db)) = password() {
-            session.login_with_password(false, self.nick.read().unwrap().clone(), password)?;
-            match session.read()? {
+            let reply = self.login(&mut session, &peer, common::Login {
+                bot: false,
+                name: nick,
+                password: Some(password),
+                token: None
+            })?;
+            match reply {
                 Packet::LoginSuccess(login) => {
-                    db.execute("UPDATE servers SET token = ? WHERE ip = ?", &[&login.token, &addr.to_string()]).unwrap();
+                    if let Err(err) = self.vault.lock().unwrap().save_token(&db, id, &login.token) {
+                        warn!("token for {} not saved: {}", host, err);
+                    }
                     session.set_nonblocking(true)?;
-                    return Ok(Synac::new(addr, session, login.id));
+                    return Ok(Synac::new(id, host, addr, peer, session, login.id, Arc::clone(&self.capture)));
                 },
                 Packet::Err(common::ERR_LOGIN_INVALID) =>
                      return Err(ConnectionError::InvalidPassword.into()),
@@ -142,22 +290,22 @@ impl Connections {
 
         Err(ConnectionError::InvalidToken.into())
     }
-    pub fn insert(&self, addr: SocketAddr, result: Synac) {
+    pub fn insert(&self, id: ServerId, result: Synac) {
         self.servers.lock().unwrap()
-            .insert(addr, Connection::Connected(Box::new(Ok(result))));
+            .insert(id, Connection::Connected(Box::new(Ok(result))));
     }
-    pub fn remove(&self, addr: SocketAddr) {
+    pub fn remove(&self, id: ServerId) {
         self.servers.lock().unwrap()
-            .remove(&addr);
+            .remove(&id);
     }
-    pub fn set_current(&self, addr: Option<SocketAddr>) {
-        *self.current_server.lock().unwrap() = addr;
+    pub fn set_current(&self, id: Option<ServerId>) {
+        *self.current_server.lock().unwrap() = id;
     }
-    pub fn execute<F>(&self, addr: SocketAddr, callback: F)
+    pub fn execute<F>(&self, id: ServerId, callback: F)
         where F: FnOnce(Result<&mut Synac, &mut Error>)
     {
         let mut servers = self.servers.lock().unwrap();
-        let server = servers.get_mut(&addr);
+        let server = servers.get_mut(&id);
 
         if let Some(inner) = server {
             callback(inner.join());
@@ -181,11 +329,17 @@ impl Connections {
                 if let Ok(ref mut synac) = server.join() {
                     let read = synac.listener.try_read(synac.session.inner_stream())?;
                     if let Some(packet) = read {
+                        trace!(target: logger::PACKETS, "received from {}: {}", synac.peer, logger::packet(&packet));
+                        synac.capture.lock().unwrap().record(&synac.peer, Direction::Received, &packet);
                         synac.state.update(&packet);
                         let channel = match packet {
                             Packet::MessageReceive(ref event) => {
-                                synac.messages.add(event.inner.clone());
-                                Some(event.inner.channel)
+                                let msg = &event.inner;
+                                if event.new && msg.author != synac.user && Some(msg.channel) != synac.current_channel {
+                                    synac.unread.add(msg.channel);
+                                }
+                                synac.messages.add(msg.clone());
+                                Some(msg.channel)
                             }
                             Packet::MessageDeleteReceive(ref msg) =>
                                 synac.messages.remove(msg.id),
@@ -204,15 +358,59 @@ impl Connections {
     }
 }
 
-pub fn parse_addr(input: &str) -> Option<SocketAddr> {
+/// Split a server address into host and port, without resolving it.
+/// Accepts `host`, `host:port`, IPv6 addresses and `[ipv6]:port`.
+pub fn parse_host(input: &str) -> Option<(&str, u16)> {
+    split_host(input, common::DEFAULT_PORT)
+}
+/// Like `parse_host`, with `default_port` for addresses without one
+pub fn split_host(input: &str, default_port: u16) -> Option<(&str, u16)> {
+    let input = input.trim();
+    if input.starts_with('[') {
+        let end = input.find(']')?;
+        let host = &input[1..end];
+        let port = match &input[end+1..] {
+            "" => default_port,
+            rest if rest.starts_with(':') => rest[1..].parse().ok()?,
+            _ => return None
+        };
+        return Some((host, port));
+    }
+    if input.matches(':').count() > 1 {
+        // A bare IPv6 address
+        return Some((input, default_port));
+    }
+
     let mut parts = input.rsplitn(2, ':');
-    let addr = match (parts.next()?, parts.next()) {
-        (port, Some(addr)) => (addr, port.parse().ok()?),
-        (addr,   None)     => (addr, common::DEFAULT_PORT)
+    let (host, port) = match (parts.next()?, parts.next()) {
+        (port, Some(host)) => (host, port.parse().ok()?),
+        (host,   None)     => (host, default_port)
     };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host, port))
+}
+/// Look up all addresses of a server
+pub fn resolve(input: &str) -> Result<Vec<SocketAddr>, Error> {
+    let (host, port) = parse_host(input).ok_or_else(|| ConnectionError::InvalidAddress(input.to_string()))?;
+    Ok((host, port).to_socket_addrs()?.collect())
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    assert_eq!(parse_host("example.com"), Some(("example.com", common::DEFAULT_PORT)));
+    assert_eq!(parse_host(" example.com:1234 "), Some(("example.com", 1234)));
+    assert_eq!(parse_host("127.0.0.1:1234"), Some(("127.0.0.1", 1234)));
+    assert_eq!(parse_host("::1"), Some(("::1", common::DEFAULT_PORT)));
+    assert_eq!(parse_host("[::1]"), Some(("::1", common::DEFAULT_PORT)));
+    assert_eq!(parse_host("[::1]:1234"), Some(("::1", 1234)));
+    assert_eq!(parse_host("example.com:port"), None);
+    assert_eq!(parse_host("[::1]1234"), None);
+    assert_eq!(parse_host(":1234"), None);
+    assert_eq!(split_host("localhost", 1080), Some(("localhost", 1080)));
 
-    use std::net::ToSocketAddrs;
-    addr.to_socket_addrs()
-        .ok()
-        .and_then(|mut addrs| addrs.next())
+    let addrs = resolve("127.0.0.1:1234").unwrap();
+    assert_eq!(addrs, vec!["127.0.0.1:1234".parse::<SocketAddr>().unwrap()]);
 }
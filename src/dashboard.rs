@@ -0,0 +1,142 @@
+//! Statistics for the admin dashboard, from what the client knows about a server.
+
+use std::collections::HashMap;
+use synac::common::{Message, User};
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    /// Everyone who isn't a bot
+    pub users: usize,
+    pub bots: usize,
+    pub admins: usize,
+    pub banned: usize
+}
+impl Counts {
+    pub fn of<'a, I>(users: I) -> Self
+        where I: IntoIterator<Item = &'a User>
+    {
+        let mut counts = Counts::default();
+        for user in users {
+            if user.bot {
+                counts.bots += 1;
+            } else {
+                counts.users += 1;
+            }
+            if user.admin {
+                counts.admins += 1;
+            }
+            if user.ban {
+                counts.banned += 1;
+            }
+        }
+        counts
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Activity {
+    pub user: usize,
+    pub messages: usize,
+    /// When they last sent a message
+    pub last: i64
+}
+
+/// Who wrote `messages`, most recently active first
+pub fn activity<'a, I>(messages: I) -> Vec<Activity>
+    where I: IntoIterator<Item = &'a Message>
+{
+    let mut users: HashMap<usize, Activity> = HashMap::new();
+    for msg in messages {
+        let activity = users.entry(msg.author).or_insert(Activity {
+            user: msg.author,
+            messages: 0,
+            last: msg.timestamp
+        });
+        activity.messages += 1;
+        activity.last = activity.last.max(msg.timestamp);
+    }
+
+    let mut activity: Vec<_> = users.values().cloned().collect();
+    activity.sort_by(|a, b| b.last.cmp(&a.last).then(a.user.cmp(&b.user)));
+    activity
+}
+/// Count messages in `buckets` periods of `width` seconds that end at `now`, oldest first.
+/// Older messages aren't counted.
+pub fn volume<'a, I>(messages: I, now: i64, width: i64, buckets: usize) -> Vec<usize>
+    where I: IntoIterator<Item = &'a Message>
+{
+    let mut counts = vec![0; buckets];
+    for msg in messages {
+        // Clocks differ, so count messages from the future as new
+        let age = (now - msg.timestamp).max(0);
+        let i = (age / width) as usize;
+        if i < buckets {
+            counts[buckets - 1 - i] += 1;
+        }
+    }
+    counts
+}
+/// Draw counts as a line of bars, relative to the largest one
+pub fn sparkline(counts: &[usize]) -> String {
+    let max = counts.iter().cloned().max().unwrap_or(0);
+    let top = BARS.len() - 1;
+    counts.iter()
+        .map(|&count| if max == 0 {
+            BARS[0]
+        } else {
+            // Round up, so any message at all shows
+            BARS[(count * top + max - 1) / max]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let user = |id, admin, ban, bot| User {
+        admin: admin,
+        ban: ban,
+        bot: bot,
+        id: id,
+        modes: HashMap::new(),
+        name: format!("user {}", id)
+    };
+    let users = [
+        user(1, true, false, false),
+        user(2, false, true, false),
+        user(3, false, false, true),
+        user(4, false, false, false)
+    ];
+    assert_eq!(Counts::of(&users), Counts {
+        users: 3,
+        bots: 1,
+        admins: 1,
+        banned: 1
+    });
+
+    let message = |id, author, timestamp| Message {
+        author: author,
+        channel: 1,
+        id: id,
+        text: Vec::new(),
+        timestamp: timestamp,
+        timestamp_edit: None
+    };
+    let messages = [message(1, 1, 10), message(2, 4, 95), message(3, 1, 50), message(4, 4, 120)];
+
+    let activity = activity(&messages);
+    assert_eq!(activity, vec![
+        Activity { user: 4, messages: 2, last: 120 },
+        Activity { user: 1, messages: 2, last: 50 }
+    ]);
+
+    // Counting back from 100 in steps of 40, with the message from the future as new
+    assert_eq!(volume(&messages, 100, 40, 3), vec![1, 1, 2]);
+    assert_eq!(volume(&messages, 1000, 40, 3), vec![0, 0, 0]);
+
+    assert_eq!(sparkline(&[0, 1, 2, 8]), "▁▂▃█");
+    assert_eq!(sparkline(&[0, 0]), "▁▁");
+    assert_eq!(sparkline(&[]), "");
+}
@@ -0,0 +1,226 @@
+use ::*;
+use dashboard::{self, Activity, Counts};
+use gtk::Grid;
+use synac::common::Channel;
+
+/// How far back message volume goes, in days
+const VOLUME_DAYS: usize = 14;
+const DAY: i64 = 60 * 60 * 24;
+/// How many recently active users to list
+const ACTIVE_USERS: usize = 15;
+
+struct Overview {
+    counts: Counts,
+    channels: Vec<(Channel, Vec<usize>)>,
+    private: Vec<String>,
+    active: Vec<(Activity, String)>
+}
+
+fn overview(synac: &Synac, now: i64) -> Overview {
+    let mut channels = Vec::new();
+    let mut private = Vec::new();
+    for channel in synac.state.channels.values() {
+        if channel.private {
+            let recipient = synac.state.get_recipient_unchecked(channel.id, synac.user);
+            private.push(format!("@{}", recipient.map(|user| &*user.name).unwrap_or("unknown")));
+        } else {
+            let volume = dashboard::volume(synac.messages.get(channel.id), now, DAY, VOLUME_DAYS);
+            channels.push((channel.clone(), volume));
+        }
+    }
+    channels.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    private.sort();
+
+    let messages = synac.state.channels.keys().flat_map(|&channel| synac.messages.get(channel));
+    let active = dashboard::activity(messages).into_iter()
+        .take(ACTIVE_USERS)
+        .map(|activity| {
+            let name = synac.state.users.get(&activity.user).map(|user| user.name.clone());
+            (activity, name.unwrap_or_else(|| String::from("unknown")))
+        })
+        .collect();
+
+    Overview {
+        counts: Counts::of(synac.state.users.values()),
+        channels: channels,
+        private: private,
+        active: active
+    }
+}
+
+fn add_row(grid: &Grid, row: i32, cells: &[&str]) {
+    for (column, text) in cells.iter().enumerate() {
+        let label = Label::new(*text);
+        label.set_xalign(0.0);
+        grid.attach(&label, column as i32, row, 1, 1);
+    }
+}
+fn new_grid() -> Grid {
+    let grid = Grid::new();
+    grid.set_row_spacing(2);
+    grid.set_column_spacing(10);
+    grid
+}
+
+/// Switch to the server, since the editors act on the current one
+fn focus_server(app: &Rc<App>, id: ServerId) {
+    if *app.connections.current_server.lock().unwrap() != Some(id) {
+        select_server(app, id);
+    }
+}
+
+fn fill(app: &Rc<App>, id: ServerId, container: &GtkBox) {
+    for child in container.get_children() {
+        container.remove(&child);
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut data = None;
+    app.connections.execute(id, |result| if let Ok(synac) = result {
+        data = Some(overview(synac, now));
+    });
+    let data = match data {
+        Some(data) => data,
+        None => {
+            add_text(container, "Not connected to this server.");
+            container.show_all();
+            return;
+        }
+    };
+
+    add_heading(container, "Members");
+    add_text(container, &format!(
+        "{} users and {} bots. {} admins, {} banned.",
+        data.counts.users, data.counts.bots, data.counts.admins, data.counts.banned
+    ));
+
+    add_heading(container, "Channels");
+    add_text(container, &format!(
+        "Messages per day over the last {} days. Only messages loaded in this client count.",
+        VOLUME_DAYS
+    ));
+    let grid = new_grid();
+    add_row(&grid, 0, &["Channel", "Users", "Bots", "Activity"]);
+    for (row, &(ref channel, ref volume)) in data.channels.iter().enumerate() {
+        let row = row as i32 + 1;
+        add_row(&grid, row, &[
+            &*format!("#{}", channel.name),
+            &*permissions::describe(channel.default_mode_user),
+            &*permissions::describe(channel.default_mode_bot)
+        ]);
+
+        let activity = Label::new(&*dashboard::sparkline(volume));
+        let counts: Vec<_> = volume.iter().map(|count| count.to_string()).collect();
+        activity.set_tooltip_text(&*format!("Oldest first: {}", counts.join(", ")));
+        grid.attach(&activity, 3, row, 1, 1);
+
+        let edit = Button::new_with_label("Edit");
+        let app_clone = Rc::clone(app);
+        let channel_clone = channel.clone();
+        edit.connect_clicked(move |_| {
+            focus_server(&app_clone, id);
+            edit_channel(&app_clone, Some(&channel_clone));
+        });
+        grid.attach(&edit, 4, row, 1, 1);
+
+        let modes = Button::new_with_label("Permissions");
+        let app_clone = Rc::clone(app);
+        let channel_id = channel.id;
+        modes.connect_clicked(move |_| permission_window::show(&app_clone, id, channel_id));
+        grid.attach(&modes, 5, row, 1, 1);
+    }
+    container.add(&grid);
+
+    let add = Button::new_with_mnemonic("Add _channel");
+    add.set_halign(Align::Start);
+    let app_clone = Rc::clone(app);
+    add.connect_clicked(move |_| {
+        focus_server(&app_clone, id);
+        edit_channel(&app_clone, None);
+    });
+    container.add(&add);
+
+    add_heading(container, "Private channels");
+    if data.private.is_empty() {
+        add_text(container, "None.");
+    } else {
+        add_text(container, &format!("{}: {}", data.private.len(), data.private.join(", ")));
+    }
+
+    add_heading(container, "Recently active");
+    if data.active.is_empty() {
+        add_text(container, "Nobody, as far as the loaded messages go.");
+    }
+    let grid = new_grid();
+    for (row, &(ref activity, ref name)) in data.active.iter().enumerate() {
+        let row = row as i32;
+
+        let mut last = String::new();
+        messages::format_timestamp(&mut last, activity.last);
+        add_row(&grid, row, &[&**name, &*format!("{} message(s)", activity.messages), &*last]);
+
+        let user_id = activity.user;
+
+        let explain = Button::new_with_label("Permissions");
+        let app_clone = Rc::clone(app);
+        explain.connect_clicked(move |_| explain_window::show(&app_clone, id, user_id, None));
+        grid.attach(&explain, 3, row, 1, 1);
+
+        let moderate = Button::new_with_label("Moderate");
+        let app_clone = Rc::clone(app);
+        moderate.connect_clicked(move |_| {
+            focus_server(&app_clone, id);
+            moderation_window::show(&app_clone, id, Some(user_id));
+        });
+        grid.attach(&moderate, 4, row, 1, 1);
+    }
+    container.add(&grid);
+
+    container.show_all();
+}
+
+/// An overview of a server, for its admins
+pub(crate) fn show(app: &Rc<App>, id: ServerId) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Server dashboard");
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(700, 550);
+
+    let outer = GtkBox::new(Orientation::Vertical, 6);
+    outer.set_property_margin(10);
+
+    let container = GtkBox::new(Orientation::Vertical, 4);
+    fill(app, id, &container);
+
+    let scroll = ScrolledWindow::new(None, None);
+    scroll.set_vexpand(true);
+    scroll.add(&container);
+    outer.add(&scroll);
+
+    let controls = GtkBox::new(Orientation::Horizontal, 2);
+    controls.set_halign(Align::End);
+
+    let refresh = Button::new_with_mnemonic("_Refresh");
+    let app_clone = Rc::clone(app);
+    let container_clone = container.clone();
+    refresh.connect_clicked(move |_| fill(&app_clone, id, &container_clone));
+    controls.add(&refresh);
+
+    let moderation = Button::new_with_mnemonic("_Moderation");
+    let app_clone = Rc::clone(app);
+    moderation.connect_clicked(move |_| {
+        focus_server(&app_clone, id);
+        moderation_window::show(&app_clone, id, None);
+    });
+    controls.add(&moderation);
+
+    let close = Button::new_with_mnemonic("_Close");
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    controls.add(&close);
+    outer.add(&controls);
+
+    window.add(&outer);
+    window.show_all();
+}
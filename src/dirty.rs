@@ -0,0 +1,81 @@
+use connections::ServerId;
+use model::Render;
+use std::collections::HashMap;
+use std::mem;
+
+/// The views of one server that need redrawing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Views {
+    pub channels: bool,
+    pub messages: bool,
+    pub users: bool,
+    pub typing: bool,
+    pub scroll: bool
+}
+
+/// Collects redraws between frames, so a burst of packets
+/// redraws every affected view exactly once
+#[derive(Debug, Default)]
+pub struct Dirty {
+    pub servers: bool,
+    views: HashMap<ServerId, Views>
+}
+impl Dirty {
+    pub fn new() -> Self {
+        Dirty::default()
+    }
+    /// Mark the view a render command refers to.
+    /// Returns false for commands that aren't redraws and should be carried out right away.
+    pub fn mark(&mut self, id: ServerId, render: &Render) -> bool {
+        match *render {
+            Render::Channels => self.view(id).channels = true,
+            Render::Messages => self.view(id).messages = true,
+            Render::Users => self.view(id).users = true,
+            Render::Typing => self.view(id).typing = true,
+            Render::ScrollToBottom => self.view(id).scroll = true,
+            Render::Edit(_) | Render::CloseEdit | Render::Notify { .. } => return false
+        }
+        true
+    }
+    fn view(&mut self, id: ServerId) -> &mut Views {
+        self.views.entry(id).or_insert_with(Views::default)
+    }
+    pub fn take_servers(&mut self) -> bool {
+        mem::replace(&mut self.servers, false)
+    }
+    /// Take the views to redraw for the shown server.
+    /// Other servers are dropped, since selecting a server redraws it completely anyway.
+    pub fn take(&mut self, current: Option<ServerId>) -> Views {
+        let views = current.and_then(|id| self.views.remove(&id)).unwrap_or_default();
+        self.views.clear();
+        views
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let a = 1;
+    let b = 2;
+
+    let mut dirty = Dirty::new();
+    assert!(dirty.mark(a, &Render::Channels));
+    assert!(dirty.mark(a, &Render::Messages));
+    assert!(dirty.mark(a, &Render::Messages));
+    assert!(dirty.mark(b, &Render::Users));
+    assert!(!dirty.mark(a, &Render::CloseEdit));
+    assert!(!dirty.mark(3, &Render::CloseEdit));
+    assert!(!dirty.views.contains_key(&3));
+
+    assert_eq!(dirty.take(Some(a)), Views {
+        channels: true,
+        messages: true,
+        ..Views::default()
+    });
+    assert_eq!(dirty.take(Some(a)), Views::default());
+    assert_eq!(dirty.take(Some(b)), Views::default());
+
+    dirty.servers = true;
+    assert!(dirty.take_servers());
+    assert!(!dirty.take_servers());
+}
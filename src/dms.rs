@@ -0,0 +1,143 @@
+//! Private channels: finding the one with a user, ordering them, and closing them locally.
+
+use failure::Error;
+use messages::Messages;
+use rusqlite::Connection as SqlConnection;
+use std::collections::{HashMap, HashSet};
+use synac::State;
+use unread::Unread;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dm {
+    pub channel: usize,
+    pub recipient: Option<usize>,
+    pub name: String,
+    /// When the last loaded message was sent
+    pub last: Option<i64>,
+    pub unread: usize
+}
+impl Dm {
+    /// A closed conversation stays closed until there's something new in it
+    pub fn is_visible(&self, hidden_at: Option<i64>) -> bool {
+        match hidden_at {
+            None => true,
+            Some(hidden_at) => self.unread > 0 || self.last.map(|last| last > hidden_at).unwrap_or(false)
+        }
+    }
+}
+
+/// Order conversations by latest activity, newest first, and those without loaded messages by name.
+/// Only the most active conversation with each user is kept, plus any other with unread messages.
+pub fn sort(mut dms: Vec<Dm>) -> Vec<Dm> {
+    dms.sort_by(|a, b| {
+        b.last.cmp(&a.last)
+            .then_with(|| a.name.cmp(&b.name))
+            .then(a.channel.cmp(&b.channel))
+    });
+    let mut seen = HashSet::new();
+    dms.retain(|dm| {
+        let first = dm.recipient.map(|recipient| seen.insert(recipient)).unwrap_or(true);
+        first || dm.unread > 0
+    });
+    dms
+}
+/// Every private channel on the server, see `sort`
+pub fn list(state: &State, messages: &Messages, unread: &Unread, me: usize) -> Vec<Dm> {
+    let dms = state.channels.values()
+        .filter(|channel| channel.private)
+        .map(|channel| {
+            let recipient = state.get_recipient_unchecked(channel.id, me);
+            Dm {
+                channel: channel.id,
+                recipient: recipient.map(|user| user.id),
+                name: recipient.map(|user| user.name.clone()).unwrap_or_else(|| String::from("unknown")),
+                last: messages.get(channel.id).last().map(|msg| msg.timestamp),
+                unread: unread.get(channel.id)
+            }
+        })
+        .collect();
+    sort(dms)
+}
+/// The conversation with `user`, if there is one
+pub fn find(dms: &[Dm], user: usize) -> Option<usize> {
+    dms.iter().find(|dm| dm.recipient == Some(user)).map(|dm| dm.channel)
+}
+
+/// When each closed conversation on `server` was closed
+pub fn hidden(db: &SqlConnection, server: &str) -> Result<HashMap<usize, i64>, Error> {
+    let mut stmt = db.prepare_cached("SELECT channel, hidden_at FROM hidden_dms WHERE server = ?")?;
+    let rows = stmt.query_map(&[&server], |row| (row.get::<_, i64>(0) as usize, row.get(1)))?;
+
+    let mut hidden = HashMap::new();
+    for row in rows {
+        let (channel, hidden_at) = row?;
+        hidden.insert(channel, hidden_at);
+    }
+    Ok(hidden)
+}
+pub fn hide(db: &SqlConnection, server: &str, channel: usize, now: i64) -> Result<(), Error> {
+    db.execute(
+        "REPLACE INTO hidden_dms (server, channel, hidden_at) VALUES (?, ?, ?)",
+        &[&server, &(channel as i64), &now]
+    )?;
+    Ok(())
+}
+pub fn unhide(db: &SqlConnection, server: &str, channel: usize) -> Result<(), Error> {
+    db.execute("DELETE FROM hidden_dms WHERE server = ? AND channel = ?", &[&server, &(channel as i64)])?;
+    Ok(())
+}
+/// Drop the closed conversations of a server address, unless another saved account still uses it
+pub fn forget(db: &SqlConnection, server: &str) -> Result<(), Error> {
+    db.execute(
+        "DELETE FROM hidden_dms WHERE server = ? AND NOT EXISTS (SELECT 1 FROM servers WHERE ip = ?)",
+        &[&server, &server]
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let dm = |channel, recipient, name: &str, last| Dm {
+        channel: channel,
+        recipient: Some(recipient),
+        name: name.to_string(),
+        last: last,
+        unread: 0
+    };
+    let dms = sort(vec![
+        dm(1, 10, "bob", None),
+        dm(2, 11, "alice", None),
+        dm(3, 12, "carol", Some(50)),
+        dm(4, 13, "dave", Some(90)),
+        // An older duplicate with dave
+        dm(5, 13, "dave", Some(20)),
+        // One that isn't dropped, because there's something to read in it
+        Dm { unread: 2, ..dm(6, 13, "dave", Some(10)) }
+    ]);
+    assert_eq!(dms.iter().map(|dm| dm.channel).collect::<Vec<_>>(), vec![4, 3, 6, 2, 1]);
+    assert_eq!(find(&dms, 13), Some(4));
+    assert_eq!(find(&dms, 14), None);
+
+    let mut carol = dms[1].clone();
+    assert!(carol.is_visible(None));
+    assert!(!carol.is_visible(Some(60)));
+    assert!(carol.is_visible(Some(40)));
+    carol.unread = 1;
+    assert!(carol.is_visible(Some(60)));
+
+    let mut db = SqlConnection::open_in_memory().unwrap();
+    ::migrations::migrate(&mut db, None).unwrap();
+
+    hide(&db, "a", 3, 60).unwrap();
+    hide(&db, "a", 3, 70).unwrap();
+    hide(&db, "b", 4, 80).unwrap();
+    assert_eq!(hidden(&db, "a").unwrap().into_iter().collect::<Vec<_>>(), vec![(3, 70)]);
+
+    unhide(&db, "a", 3).unwrap();
+    assert!(hidden(&db, "a").unwrap().is_empty());
+    assert_eq!(hidden(&db, "b").unwrap().len(), 1);
+
+    forget(&db, "b").unwrap();
+    assert!(hidden(&db, "b").unwrap().is_empty());
+}
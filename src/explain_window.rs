@@ -0,0 +1,126 @@
+use ::*;
+use gtk::{ComboBoxText, Grid};
+use permissions::{self, Source};
+use synac::common::{Channel, User};
+
+fn channel_name(channel: &Channel, synac: &Synac) -> String {
+    if channel.private {
+        match synac.state.get_recipient_unchecked(channel.id, synac.user) {
+            Some(recipient) => format!("@{}", recipient.name),
+            None => String::from("@unknown")
+        }
+    } else {
+        format!("#{}", channel.name)
+    }
+}
+
+fn fill_steps(container: &GtkBox, channel: &Channel, user: &User) {
+    for child in container.get_children() {
+        container.remove(&child);
+    }
+    for step in permissions::explain(channel, user) {
+        let label = Label::new(&*step);
+        label.set_xalign(0.0);
+        label.set_line_wrap(true);
+        container.add(&label);
+    }
+    container.show_all();
+}
+
+/// Explain why a user can or can't do things, in `channel` and every other channel
+pub(crate) fn show(app: &Rc<App>, id: ServerId, user_id: usize, channel: Option<usize>) {
+    let mut data = None;
+    app.connections.execute(id, |result| {
+        if let Ok(synac) = result {
+            if let Some(user) = synac.state.users.get(&user_id) {
+                let mut channels: Vec<_> = synac.state.channels.values()
+                    .map(|channel| (channel_name(channel, synac), channel.clone()))
+                    .collect();
+                channels.sort_by(|a, b| (a.1.private, &a.0).cmp(&(b.1.private, &b.0)));
+                data = Some((user.clone(), channels));
+            }
+        }
+    });
+    let (user, channels) = match data {
+        Some(data) => data,
+        None => return
+    };
+
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title(&format!("Synac: Permissions of {}", user.name));
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(500, 500);
+
+    let container = GtkBox::new(Orientation::Vertical, 6);
+    container.set_property_margin(10);
+
+    let heading = Label::new(&*format!("Why can or can't {} do something in", user.name));
+    add_class(&heading, "bold");
+    heading.set_xalign(0.0);
+    container.add(&heading);
+
+    let picker = ComboBoxText::new();
+    for &(ref name, _) in &channels {
+        picker.append_text(name);
+    }
+    let selected = channel.and_then(|channel| channels.iter().position(|&(_, ref inner)| inner.id == channel));
+    container.add(&picker);
+
+    let steps = GtkBox::new(Orientation::Vertical, 2);
+    steps.set_margin_left(10);
+    container.add(&steps);
+
+    let channels = Rc::new(channels);
+    let channels_clone = Rc::clone(&channels);
+    let user_clone = user.clone();
+    let steps_clone = steps.clone();
+    picker.connect_changed(move |picker| {
+        let index = picker.get_active();
+        if let Some(&(_, ref channel)) = channels_clone.get(index as usize) {
+            fill_steps(&steps_clone, channel, &user_clone);
+        }
+    });
+    picker.set_active(selected.unwrap_or(0) as i32);
+
+    let heading = Label::new("All channels");
+    add_class(&heading, "bold");
+    heading.set_xalign(0.0);
+    heading.set_margin_top(10);
+    container.add(&heading);
+
+    let grid = Grid::new();
+    grid.set_row_spacing(2);
+    grid.set_column_spacing(10);
+    for (row, &(ref name, ref channel)) in channels.iter().enumerate() {
+        let row = row as i32;
+
+        let name = Label::new(&**name);
+        name.set_xalign(0.0);
+        grid.attach(&name, 0, row, 1, 1);
+
+        let mode = Label::new(&*permissions::describe(permissions::mode(channel, &user)));
+        mode.set_xalign(0.0);
+        mode.set_hexpand(true);
+        mode.set_line_wrap(true);
+        grid.attach(&mode, 1, row, 1, 1);
+
+        let source = Label::new(Source::of(channel, &user).describe());
+        source.set_xalign(0.0);
+        grid.attach(&source, 2, row, 1, 1);
+    }
+
+    let scroll = ScrolledWindow::new(None, None);
+    scroll.set_vexpand(true);
+    scroll.add(&grid);
+    container.add(&scroll);
+
+    let close = Button::new_with_mnemonic("_Close");
+    close.set_halign(Align::End);
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    container.add(&close);
+
+    window.add(&container);
+    window.show_all();
+}
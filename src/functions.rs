@@ -10,6 +10,29 @@ pub(crate) fn add_class<T: WidgetExt>(widget: &T, class: &str) {
         Some(())
     });
 }
+/// A bold label that starts a section of a window
+pub(crate) fn add_heading(container: &GtkBox, text: &str) {
+    let label = Label::new(text);
+    add_class(&label, "bold");
+    label.set_xalign(0.0);
+    label.set_margin_top(10);
+    container.add(&label);
+}
+/// A paragraph of text in a window
+pub(crate) fn add_text(container: &GtkBox, text: &str) {
+    let label = Label::new(text);
+    label.set_xalign(0.0);
+    label.set_line_wrap(true);
+    container.add(&label);
+}
+/// An entry that hides what's typed into it
+pub(crate) fn password_entry(placeholder: &str) -> Entry {
+    let entry = Entry::new();
+    entry.set_input_purpose(InputPurpose::Password);
+    entry.set_visibility(false);
+    entry.set_placeholder_text(placeholder);
+    entry
+}
 pub(crate) fn alert(window: &Window, kind: MessageType, message: &str) {
     let dialog = MessageDialog::new(
         Some(window),
@@ -40,10 +63,23 @@ pub(crate) fn confirm<F>(window: &Window, message: &str, callback: F)
     });
     dialog.show_all();
 }
-pub(crate) fn connect(app: &Rc<App>, addr: SocketAddr, hash: String, token: Option<String>)
-    -> Option<Error>
-{
-    let result = app.connections.connect(addr, hash, token, || {
+/// Connect to a saved server entry, asking for a password if the token doesn't work
+pub(crate) fn connect(app: &Rc<App>, id: ServerId) -> Option<Error> {
+    let server = match ServerEntry::load(&app.db, id) {
+        Ok(server) => server,
+        Err(err) => return Some(err)
+    };
+    let ask = server.token_sealed.is_some() && app.connections.vault.lock().unwrap().should_ask();
+    if ask {
+        let unlocked = vault_window::unlock(&app.window, |passphrase| {
+            app.connections.vault.lock().unwrap().unlock(&app.db, passphrase)
+        });
+        if !unlocked {
+            app.connections.vault.lock().unwrap().skip();
+        }
+    }
+
+    let result = app.connections.connect(server, || {
         let dialog = Dialog::new_with_buttons(
             Some("Synac: Password dialog"),
             Some(&app.window),
@@ -53,9 +89,7 @@ pub(crate) fn connect(app: &Rc<App>, addr: SocketAddr, hash: String, token: Opti
 
         let content = dialog.get_content_area();
         content.add(&Label::new("Password:"));
-        let entry = Entry::new();
-        entry.set_input_purpose(InputPurpose::Password);
-        entry.set_visibility(false);
+        let entry = password_entry("Password");
         content.add(&entry);
 
         dialog.show_all();
@@ -68,8 +102,8 @@ pub(crate) fn connect(app: &Rc<App>, addr: SocketAddr, hash: String, token: Opti
         Ok(mut synac) => {
             render_channels(app, Some(&mut synac));
 
-            app.connections.insert(addr, synac);
-            app.connections.set_current(Some(addr));
+            app.connections.insert(id, synac);
+            app.connections.set_current(Some(id));
             app.message_edit.set_reveal_child(false);
             None
         },
@@ -80,63 +114,156 @@ pub(crate) fn connect(app: &Rc<App>, addr: SocketAddr, hash: String, token: Opti
         }
     }
 }
-pub(crate) fn deselect_server(app: &Rc<App>) {
-    app.connections.set_current(None);
-    app.message_edit.set_reveal_child(false);
-    app.message_input.set_reveal_child(false);
-    app.server_name.set_text("");
-    app.typing.set_text("");
-    render_channels(app, None);
+/// The server's own nick, if it doesn't use the default
+pub(crate) fn server_nick(app: &Rc<App>, id: ServerId) -> Option<String> {
+    app.db.query_row("SELECT nick FROM servers WHERE id = ?", &[&id], |row| row.get(0))
+        .unwrap_or(None)
 }
-pub(crate) fn render_mode(container: &GtkBox, bitmask: u8) {
-    for child in container.get_children() {
-        container.remove(&child);
+/// The name to show for a server entry, including the account if it has one
+pub(crate) fn server_label(name: &str, account: &str) -> String {
+    if account.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} ({})", name, account)
     }
+}
+/// Show a server, connecting to it first if needed
+pub(crate) fn select_server(app: &Rc<App>, id: ServerId) {
+    debug!("server {} was selected", id);
+    deselect_server(app);
+    let mut err = true;
+    app.connections.execute(id, |result| {
+        err = result.is_err();
+
+        if let Ok(synac) = result {
+            render_channels(app, Some(synac));
+            app.connections.set_current(Some(id));
+            app.message_edit.set_reveal_child(false);
 
-    let mut check = CheckButton::new_with_label("Read messages");
-    check.set_active(bitmask & common::PERM_READ == common::PERM_READ);
-    container.add(&check);
-
-    check = CheckButton::new_with_label("Write messages");
-    check.set_active(bitmask & common::PERM_WRITE == common::PERM_WRITE);
-    container.add(&check);
+            let channel_id = {
+                let mut channels: Vec<_> = synac.state.channels.values().collect();
+                channels.sort_by_key(|channel| &channel.name);
 
-    check = CheckButton::new_with_label("Manage channel");
-    check.set_active(bitmask & common::PERM_MANAGE_CHANNELS == common::PERM_MANAGE_CHANNELS);
-    container.add(&check);
+                channels.iter().find(|channel| !channel.private).map(|channel| channel.id)
+            };
 
-    check = CheckButton::new_with_label("Manage messages");
-    check.set_active(bitmask & common::PERM_MANAGE_MESSAGES == common::PERM_MANAGE_MESSAGES);
-    container.add(&check);
+            if let Some(channel_id) = channel_id {
+                select_channel(app, synac, channel_id);
+            }
+        }
+    });
+    if err {
+        err = connect(app, id).is_some();
+        render_channels(app, None);
+    }
+    if !err {
+        let label = app.db.query_row(
+            "SELECT name, account FROM servers WHERE id = ?",
+            &[&id],
+            |row| server_label(&row.get::<_, String>(0), &row.get::<_, String>(1))
+        );
+        if let Ok(label) = label {
+            app.server_name.set_text(&label);
+        }
+        render_accounts(app, id);
+    }
+}
+/// Fill the header's account switcher with the other accounts on the same server
+pub(crate) fn render_accounts(app: &Rc<App>, id: ServerId) {
+    for child in app.accounts_menu.get_children() {
+        app.accounts_menu.remove(&child);
+    }
 
-    check = CheckButton::new_with_label("Manage user modes");
-    check.set_active(bitmask & common::PERM_MANAGE_MODES == common::PERM_MANAGE_MODES);
-    container.add(&check);
+    let mut stmt = app.db.prepare(
+        "SELECT id, account, nick FROM servers WHERE ip = (SELECT ip FROM servers WHERE id = ?) ORDER BY account"
+    ).unwrap();
+    let accounts: Vec<(ServerId, String, Option<String>)> = stmt.query_map(&[&id], |row| (row.get(0), row.get(1), row.get(2)))
+        .unwrap()
+        .filter_map(|row| row.ok())
+        .collect();
 
-    container.show_all();
-    container.queue_draw();
-}
-pub(crate) fn get_mode(container: &GtkBox) -> Option<u8> {
-    let mut children = container.get_children().into_iter();
-    let mut bitmask = 0;
+    app.accounts.set_visible(accounts.len() > 1);
 
-    if children.next()?.downcast::<CheckButton>().ok()?.get_active() {
-        bitmask |= common::PERM_READ;
-    }
-    if children.next()?.downcast::<CheckButton>().ok()?.get_active() {
-        bitmask |= common::PERM_WRITE;
+    for (other, account, nick) in accounts {
+        let mut label = if account.is_empty() { String::from("Main account") } else { account };
+        if let Some(nick) = nick {
+            write!(label, " - {}", nick).unwrap();
+        }
+        let item = CheckMenuItem::new_with_label(&label);
+        item.set_draw_as_radio(true);
+        item.set_active(other == id);
+
+        let app = Rc::clone(app);
+        item.connect_activate(move |_| {
+            if *app.connections.current_server.lock().unwrap() != Some(other) {
+                select_server(&app, other);
+            }
+        });
+        app.accounts_menu.add(&item);
     }
-    if children.next()?.downcast::<CheckButton>().ok()?.get_active() {
-        bitmask |= common::PERM_MANAGE_CHANNELS;
+    app.accounts_menu.show_all();
+}
+pub(crate) fn quick_connect(app: &Rc<App>, server: &str, hash: String) {
+    if connections::parse_host(server).is_none() {
+        alert(&app.window, MessageType::Error, "Invalid server address. Format: <host[:port]>");
+        return;
     }
-    if children.next()?.downcast::<CheckButton>().ok()?.get_active() {
-        bitmask |= common::PERM_MANAGE_MESSAGES;
+    let server = server.trim();
+
+    // Keep the token and name of a server we already know, using the main account
+    app.db.execute(
+        "INSERT OR IGNORE INTO servers (name, ip, hash) VALUES (?, ?, ?)",
+        &[&server, &server, &hash]
+    ).unwrap();
+    app.db.execute("UPDATE servers SET hash = ? WHERE ip = ? AND account = ''", &[&hash, &server]).unwrap();
+    render_servers(app);
+
+    let id: ServerId = app.db.query_row(
+        "SELECT id FROM servers WHERE ip = ? AND account = ''",
+        &[&server],
+        |row| row.get(0)
+    ).unwrap();
+
+    select_server(app, id);
+}
+pub(crate) fn deselect_server(app: &Rc<App>) {
+    app.connections.set_current(None);
+    app.accounts.set_visible(false);
+    app.message_edit.set_reveal_child(false);
+    set_selecting(app, false);
+    app.message_input.set_reveal_child(false);
+    app.server_name.set_text("");
+    app.typing.set_text("");
+    render_channels(app, None);
+}
+/// Jump to a private channel that was just created with "Message", if this is it
+pub(crate) fn open_pending_dm(app: &Rc<App>, synac: &mut Synac, channel: &common::Channel) {
+    let user = match *app.dm_pending.borrow() {
+        Some((id, user)) if id == synac.id && channel.private => user,
+        _ => return
+    };
+    if synac.state.get_recipient_unchecked(channel.id, synac.user).map(|recipient| recipient.id) == Some(user) {
+        *app.dm_pending.borrow_mut() = None;
+        select_channel(app, synac, channel.id);
     }
-    if children.next()?.downcast::<CheckButton>().ok()?.get_active() {
-        bitmask |= common::PERM_MANAGE_MODES;
+}
+/// Show the channel editor, for a new channel if `channel` is `None`
+pub(crate) fn edit_channel(app: &Rc<App>, channel: Option<&common::Channel>) {
+    match channel {
+        Some(channel) => {
+            *app.stack_edit_channel.edit.borrow_mut() = Some(channel.id);
+            app.stack_edit_channel.name.set_text(&channel.name);
+            app.stack_edit_channel.mode_bots.set_mode(channel.default_mode_bot);
+            app.stack_edit_channel.mode_users.set_mode(channel.default_mode_user);
+        },
+        None => {
+            *app.stack_edit_channel.edit.borrow_mut() = None;
+            app.stack_edit_channel.name.set_text("");
+            app.stack_edit_channel.mode_bots.set_mode(0);
+            app.stack_edit_channel.mode_users.set_mode(common::PERM_READ | common::PERM_WRITE);
+        }
     }
-
-    Some(bitmask)
+    app.stack.set_visible_child(&app.stack_edit_channel.container);
 }
 pub(crate) fn select_channel(app: &Rc<App>, synac: &mut Synac, channel_id: usize) {
     // Scope here so channel_name is dropped.
@@ -166,20 +293,31 @@ pub(crate) fn select_channel(app: &Rc<App>, synac: &mut Synac, channel_id: usize
 
     app.message_input.set_reveal_child(mode & common::PERM_WRITE == common::PERM_WRITE);
 
+    if synac.current_channel != Some(channel_id) && app.selected_messages.borrow().is_some() {
+        set_selecting(app, false);
+    }
+    if synac.current_channel != Some(channel_id) && app.search.get_reveal_child() {
+        set_searching(app, false);
+    }
     synac.current_channel = Some(channel_id);
+    app.recent.borrow_mut().touch(synac.id, channel_id);
+    if synac.unread.get(channel_id) > 0 {
+        synac.unread.clear(channel_id);
+        app.dirty.borrow_mut().mark(synac.id, &Render::Channels);
+    }
     app.channel_name.set_text(&channel_name);
     app.typing.set_text("");
 
     app.messages_noread.set_reveal_child(mode & common::PERM_READ != common::PERM_READ);
     if mode & common::PERM_READ == common::PERM_READ {
         if !synac.messages.has(channel_id) {
-            if let Err(err) = synac.session.write(&Packet::MessageList(common::MessageList {
+            if let Err(err) = synac.write(&Packet::MessageList(common::MessageList {
                 after: None,
                 before: None,
                 channel: channel_id,
                 limit: common::LIMIT_BULK
             })) {
-                eprintln!("error sending packet: {}", err);
+                error!("failed to send packet: {}", err);
             }
         }
     }
@@ -189,6 +327,69 @@ pub(crate) fn select_channel(app: &Rc<App>, synac: &mut Synac, channel_id: usize
 
     scroll_to_bottom(app);
 }
+pub(crate) fn apply_setting(app: &Rc<App>, key: &str, value: &str) -> bool {
+    if let Err(err) = app.settings.borrow_mut().update(&app.db, key, value) {
+        alert(&app.window, MessageType::Error, &err.to_string());
+        return false;
+    }
+
+    match key {
+        "layout" | "time_on_hover" | "group_window" => {
+            if let Some(id) = *app.connections.current_server.lock().unwrap() {
+                app.connections.execute(id, |result| {
+                    if let Ok(synac) = result {
+                        render_messages(app, Some(synac));
+                    }
+                });
+            }
+        },
+        "theme" | "css_path" => load_css(app),
+        "proxy" => {
+            // Only used for new connections, existing ones keep their route
+            let proxy = Proxy::parse(&app.settings.borrow().proxy).unwrap_or(None);
+            *app.connections.proxy.write().unwrap() = proxy;
+        },
+        "log_message_text" => logger::set_redact(!app.settings.borrow().log_message_text),
+        _ => ()
+    }
+    true
+}
+pub(crate) fn load_css(app: &Rc<App>) {
+    let settings = app.settings.borrow();
+
+    let custom = if !settings.css_path.is_empty() {
+        Some(PathBuf::from(&settings.css_path))
+    } else {
+        BaseDirectories::with_prefix("synac").ok()
+            .and_then(|basedirs| basedirs.find_config_file("style.css"))
+    };
+
+    let result: Result<(), Error> = if let Some(file) = custom {
+        if let Some(s) = file.to_str() {
+            app.css.load_from_path(s).map_err(Error::from)
+        } else {
+            Err(UnicodePathError.into())
+        }
+    } else {
+        let dark = match settings.theme {
+            settings::Theme::Dark => true,
+            settings::Theme::Light => false,
+            settings::Theme::Auto => if let Some(settings) = app.window.get_settings() {
+                settings.get_property_gtk_application_prefer_dark_theme()
+            } else { false }
+        };
+
+        app.css.load_from_data(if dark {
+            include_bytes!("dark.css")
+        } else {
+            include_bytes!("light.css")
+        }).map_err(Error::from)
+    };
+    if let Err(err) = result {
+        let string = format!("failed to load css: {}", err);
+        alert(&app.window, MessageType::Error, &string);
+    }
+}
 pub(crate) fn scroll_to_bottom(app: &Rc<App>) {
     // Wait until messages are properly rendered
 
@@ -201,67 +402,140 @@ pub(crate) fn scroll_to_bottom(app: &Rc<App>) {
         Continue(false)
     });
 }
+/// Carry out an update from the model: send its packets, then redraw what it asks for
+pub(crate) fn apply_update(app: &Rc<App>, synac: &mut Synac, update: Update) {
+    for packet in &update.send {
+        if let Err(err) = synac.write(packet) {
+            error!("failed to send packet: {}", err);
+        }
+    }
+    for render in update.render {
+        match render {
+            // Redraws wait for the next frame, see `flush`
+            Render::Channels |
+            Render::Messages |
+            Render::Users |
+            Render::Typing |
+            Render::ScrollToBottom => { app.dirty.borrow_mut().mark(synac.id, &render); },
+            Render::Edit(text) => {
+                app.message_edit_input.set_text(&text);
+                app.message_edit.set_reveal_child(true);
+
+                // Wait until the triggering key press has been processed and then focus
+                let app = Rc::clone(app);
+                gtk::idle_add(move || {
+                    app.message_edit_input.grab_focus();
+                    Continue(false)
+                });
+            },
+            Render::CloseEdit => app.message_edit.set_reveal_child(false),
+            Render::Notify { channel, author, summary, text } => notify(app, synac, channel, author, &summary, &text)
+        }
+    }
+}
+/// Redraw every view marked dirty since the last frame, each only once
+pub(crate) fn flush(app: &Rc<App>) {
+    if app.dirty.borrow_mut().take_servers() {
+        render_servers(app);
+    }
+
+    let current = *app.connections.current_server.lock().unwrap();
+    let views = app.dirty.borrow_mut().take(current);
+
+    if let Some(id) = current {
+        app.connections.execute(id, |result| {
+            if result.is_err() { return; }
+            let synac = result.unwrap();
+
+            if views.channels {
+                render_channels(app, Some(&mut *synac));
+            }
+            if views.messages {
+                render_messages(app, Some(&mut *synac));
+            }
+            if views.users {
+                render_users(app, Some(&mut *synac));
+            }
+            if views.typing {
+                let typing = synac.typing.text(synac.current_channel, &synac.state);
+                app.typing.set_text(&typing);
+            } else if let Some(typing) = synac.typing.check(synac.current_channel, &synac.state) {
+                app.typing.set_text(&typing);
+            }
+            if views.scroll {
+                scroll_to_bottom(app);
+            }
+        });
+    }
+}
+/// Run a user intent against the current server
+pub(crate) fn run_intent(app: &Rc<App>, intent: Intent) {
+    if let Some(id) = *app.connections.current_server.lock().unwrap() {
+        app.connections.execute(id, |result| {
+            if let Ok(synac) = result {
+                let update = app.model.borrow_mut().intent(&Server::new(synac), intent);
+                apply_update(app, synac, update);
+            }
+        });
+    }
+}
+/// Load the notification rules again, after changing them
+pub(crate) fn reload_rules(app: &App) {
+    match Rules::load(&app.db) {
+        Ok(rules) => *app.rules.borrow_mut() = rules,
+        Err(err) => warn!("failed to load notification rules: {}", err)
+    }
+}
+fn notify(app: &Rc<App>, synac: &Synac, channel: usize, author: usize, summary: &str, text: &[u8]) {
+    let settings = app.settings.borrow();
+    if !settings.notifications || app.window.is_active() {
+        return;
+    }
+
+    let rules = app.rules.borrow();
+    let text = String::from_utf8_lossy(text);
+    let incoming = Incoming {
+        server: &synac.host,
+        channel: channel,
+        private: synac.state.channels.get(&channel).map(|channel| channel.private).unwrap_or(false),
+        author: author,
+        text: &text,
+        me: synac.state.users.get(&synac.user).map(|user| &*user.name).unwrap_or("")
+    };
+    let (weekday, minute) = notifications::local_time();
+    if !rules.should_notify(&incoming, weekday, minute) {
+        return;
+    }
+
+    let body = if settings.notification_body {
+        text.to_string()
+    } else {
+        String::from("New message")
+    };
+    if let Err(err) = Notification::new().summary(summary).body(&body).show() {
+        error!("error showing notification: {}", err);
+    }
+}
 pub(crate) fn render_servers(app: &Rc<App>) {
     for child in app.servers.get_children() {
         app.servers.remove(&child);
     }
-    let mut stmt = app.db.prepare("SELECT ip, name, hash, token FROM servers ORDER BY name").unwrap();
+    let mut stmt = app.db.prepare("SELECT id, ip, account, name, hash, nick, proxy FROM servers ORDER BY name, account").unwrap();
     let mut rows = stmt.query(&[]).unwrap();
 
     while let Some(row) = rows.next() {
         let row = row.unwrap();
-        let addr: Rc<String> = Rc::new(row.get(0));
-        let name: Rc<String> = Rc::new(row.get(1));
-        let hash: Rc<String> = Rc::new(row.get(2));
-        let token: Rc<Option<String>> = Rc::new(row.get(3));
-
-        let ip_parsed = connections::parse_addr(&addr);
-
-        let name_clone: Rc<String> = Rc::clone(&name);
-        let hash_clone: Rc<String> = Rc::clone(&hash);
-        let token_clone: Rc<Option<String>> = Rc::clone(&token);
-
-        let button = Button::new_with_label(&name);
+        let id: ServerId = row.get(0);
+        let addr: Rc<String> = Rc::new(row.get(1));
+        let account: Rc<String> = Rc::new(row.get(2));
+        let name: Rc<String> = Rc::new(row.get(3));
+        let hash: Rc<String> = Rc::new(row.get(4));
+        let nick: Rc<Option<String>> = Rc::new(row.get(5));
+        let proxy: Rc<Option<String>> = Rc::new(row.get(6));
+
+        let button = Button::new_with_label(&server_label(&name, &account));
         let app_clone = Rc::clone(app);
-        button.connect_clicked(move |_| {
-            let addr = match ip_parsed {
-                Some(addr) => addr,
-                None => {
-                    alert(&app_clone.window, MessageType::Error, "Failed to parse IP address. Format: <ip[:port]>");
-                    return;
-                }
-            };
-            println!("server with ip {} was clicked", addr);
-            deselect_server(&app_clone);
-            let mut err = true;
-            app_clone.connections.execute(addr, |result| {
-                err = result.is_err();
-
-                if let Ok(synac) = result {
-                    render_channels(&app_clone, Some(synac));
-                    app_clone.connections.set_current(Some(addr));
-                    app_clone.message_edit.set_reveal_child(false);
-
-                    let channel_id = {
-                        let mut channels: Vec<_> = synac.state.channels.values().collect();
-                        channels.sort_by_key(|channel| &channel.name);
-
-                        channels.iter().find(|channel| !channel.private).map(|channel| channel.id)
-                    };
-
-                    if let Some(channel_id) = channel_id {
-                        select_channel(&app_clone, synac, channel_id);
-                    }
-                }
-            });
-            if err {
-                err = connect(&app_clone, addr, (*hash_clone).clone(), (*token_clone).clone()).is_some();
-                render_channels(&app_clone, None);
-            }
-            if !err {
-                app_clone.server_name.set_text(&name_clone);
-            }
-        });
+        button.connect_clicked(move |_| select_server(&app_clone, id));
 
         let app_clone = Rc::clone(app);
         button.connect_button_press_event(move |_, event| {
@@ -269,32 +543,95 @@ pub(crate) fn render_servers(app: &Rc<App>) {
                 let menu = Menu::new();
 
                 let addr_clone: Rc<String> = Rc::clone(&addr);
+                let account: Rc<String> = Rc::clone(&account);
                 let name: Rc<String> = Rc::clone(&name);
                 let hash: Rc<String> = Rc::clone(&hash);
+                let nick: Rc<Option<String>> = Rc::clone(&nick);
+                let proxy_clone: Rc<Option<String>> = Rc::clone(&proxy);
 
                 let edit = MenuItem::new_with_label("Edit server");
                 let app_clone2 = Rc::clone(&app_clone);
                 edit.connect_activate(move |_| {
+                    *app_clone2.stack_edit_server.edit.borrow_mut() = Some(id);
                     app_clone2.stack_edit_server.name.set_text(&name);
                     app_clone2.stack_edit_server.server.set_text(&addr_clone);
                     app_clone2.stack_edit_server.server.set_sensitive(false);
+                    app_clone2.stack_edit_server.account.set_text(&account);
                     app_clone2.stack_edit_server.hash.set_text(&hash);
+                    app_clone2.stack_edit_server.nick.set_text(nick.as_ref().map(|s| &**s).unwrap_or(""));
+                    app_clone2.stack_edit_server.proxy.set_text(proxy_clone.as_ref().map(|s| &**s).unwrap_or(""));
 
                     app_clone2.stack.set_visible_child(&app_clone2.stack_edit_server.container);
                 });
                 menu.add(&edit);
 
+                let add_account = MenuItem::new_with_label("Add account");
+                let app_clone2 = Rc::clone(&app_clone);
+                let name = Rc::clone(&name);
+                let addr_clone = Rc::clone(&addr);
+                let hash = Rc::clone(&hash);
+                let proxy = Rc::clone(&proxy);
+                add_account.connect_activate(move |_| {
+                    *app_clone2.stack_edit_server.edit.borrow_mut() = None;
+                    app_clone2.stack_edit_server.name.set_text(&name);
+                    app_clone2.stack_edit_server.server.set_text(&addr_clone);
+                    app_clone2.stack_edit_server.server.set_sensitive(false);
+                    app_clone2.stack_edit_server.account.set_text("");
+                    app_clone2.stack_edit_server.hash.set_text(&hash);
+                    app_clone2.stack_edit_server.nick.set_text("");
+                    // Another account on the same server most likely needs the same route
+                    app_clone2.stack_edit_server.proxy.set_text(proxy.as_ref().map(|s| &**s).unwrap_or(""));
+
+                    app_clone2.stack.set_visible_child(&app_clone2.stack_edit_server.container);
+                    app_clone2.stack_edit_server.account.grab_focus();
+                });
+                menu.add(&add_account);
+
+                let account_item = MenuItem::new_with_label("Account");
+                let app_clone2 = Rc::clone(&app_clone);
+                account_item.connect_activate(move |_| account_window::show(&app_clone2, id));
+                menu.add(&account_item);
+
+                let mut admin = false;
+                app_clone.connections.execute(id, |result| if let Ok(synac) = result {
+                    admin = synac.state.users.get(&synac.user).map(|user| user.admin).unwrap_or(false);
+                });
+                if admin {
+                    let dashboard = MenuItem::new_with_label("Dashboard");
+                    let app_clone2 = Rc::clone(&app_clone);
+                    dashboard.connect_activate(move |_| dashboard_window::show(&app_clone2, id));
+                    menu.add(&dashboard);
+                }
+
+                let moderation_item = MenuItem::new_with_label("Moderation");
+                let app_clone2 = Rc::clone(&app_clone);
+                moderation_item.connect_activate(move |_| moderation_window::show(&app_clone2, id, None));
+                menu.add(&moderation_item);
+
+                let level = app_clone.rules.borrow().server_level(&addr).unwrap_or(Level::All);
+                let app_clone2 = Rc::clone(&app_clone);
+                let addr_clone = Rc::clone(&addr);
+                let levels = level_menu(Some(level), None, move |level| {
+                    // Everything is the default, so it doesn't need saving
+                    let level = level.and_then(|level| if level == Level::All { None } else { Some(level) });
+                    if let Err(err) = notifications::set_server_level(&app_clone2.db, &addr_clone, level) {
+                        alert(&app_clone2.window, MessageType::Error, &format!("failed to save notification level: {}", err));
+                    }
+                    reload_rules(&app_clone2);
+                });
+                let notify = MenuItem::new_with_label("Notifications");
+                notify.set_submenu(&levels);
+                menu.add(&notify);
+
                 let disconnect = MenuItem::new_with_label("Disconnect server");
 
                 let app_clone2 = Rc::clone(&app_clone);
                 disconnect.connect_activate(move |_| {
-                    if let Some(parsed) = ip_parsed {
-                        app_clone2.connections.remove(parsed);
-                        if *app_clone2.connections.current_server.lock().unwrap() == Some(parsed) {
-                            deselect_server(&app_clone2);
-                        }
-                        render_servers(&app_clone2);
+                    app_clone2.connections.remove(id);
+                    if *app_clone2.connections.current_server.lock().unwrap() == Some(id) {
+                        deselect_server(&app_clone2);
                     }
+                    app_clone2.dirty.borrow_mut().servers = true;
                 });
                 menu.add(&disconnect);
 
@@ -303,15 +640,24 @@ pub(crate) fn render_servers(app: &Rc<App>) {
                 let app_clone2 = Rc::clone(&app_clone);
                 let addr = Rc::clone(&addr);
                 forget.connect_activate(move |_| {
-                    app_clone2.db.execute("DELETE FROM servers WHERE ip = ?", &[&*addr]).unwrap();
-                    app_clone2.db.execute("DELETE FROM muted WHERE server = ?", &[&*addr]).unwrap();
-                    if let Some(parsed) = ip_parsed {
-                        app_clone2.connections.remove(parsed);
-                        if *app_clone2.connections.current_server.lock().unwrap() == Some(parsed) {
-                            deselect_server(&app_clone2);
-                        }
+                    app_clone2.db.execute("DELETE FROM servers WHERE id = ?", &[&id]).unwrap();
+                    // Notification rules, the channel layout and closed conversations
+                    // are shared by all accounts on the server
+                    if let Err(err) = notifications::forget(&app_clone2.db, &addr) {
+                        warn!("failed to forget notification rules: {}", err);
                     }
-                    render_servers(&app_clone2);
+                    reload_rules(&app_clone2);
+                    if let Err(err) = organizer::forget(&app_clone2.db, &addr) {
+                        warn!("failed to forget the channel layout: {}", err);
+                    }
+                    if let Err(err) = dms::forget(&app_clone2.db, &addr) {
+                        warn!("failed to forget closed conversations: {}", err);
+                    }
+                    app_clone2.connections.remove(id);
+                    if *app_clone2.connections.current_server.lock().unwrap() == Some(id) {
+                        deselect_server(&app_clone2);
+                    }
+                    app_clone2.dirty.borrow_mut().servers = true;
                 });
                 menu.add(&forget);
 
@@ -325,6 +671,180 @@ pub(crate) fn render_servers(app: &Rc<App>) {
     app.servers.show_all();
     app.servers.queue_draw();
 }
+/// A submenu to pick a notification level.
+/// With `default`, there's also a choice to not have a level of its own.
+fn level_menu<F>(current: Option<Level>, default: Option<String>, set: F) -> Menu
+    where F: Fn(Option<Level>) + 'static
+{
+    let menu = Menu::new();
+    let set = Rc::new(set);
+
+    let mut choices = Vec::with_capacity(4);
+    if let Some(default) = default {
+        choices.push((default, None));
+    }
+    for &level in &[Level::All, Level::Mentions, Level::Nothing] {
+        choices.push((level.describe().to_string(), Some(level)));
+    }
+    for (label, level) in choices {
+        let item = CheckMenuItem::new_with_label(&label);
+        item.set_draw_as_radio(true);
+        item.set_active(level == current);
+
+        let set = Rc::clone(&set);
+        item.connect_activate(move |_| (*set)(level));
+        menu.add(&item);
+    }
+    menu
+}
+/// Drag and drop target for channels, carrying the channel id
+const CHANNEL_TARGET: &str = "synac-channel";
+
+/// Public channels and their names, for the organizer
+fn public_channels(synac: &Synac) -> Vec<(usize, &str)> {
+    synac.state.channels.values()
+        .filter(|channel| !channel.private)
+        .map(|channel| (channel.id, &*channel.name))
+        .collect()
+}
+/// Change how a server's channels are organised, and show it
+fn organize<F>(app: &Rc<App>, id: ServerId, change: F)
+    where F: FnOnce(&mut Organizer, &[(usize, &str)])
+{
+    let mut result = Ok(());
+    app.connections.execute(id, |inner| if let Ok(synac) = inner {
+        let channels = public_channels(synac);
+        result = Organizer::load(&app.db, &synac.host).and_then(|mut organizer| {
+            change(&mut organizer, &channels);
+            organizer.save(&app.db, &synac.host)
+        });
+    });
+    if let Err(err) = result {
+        alert(&app.window, MessageType::Error, &format!("failed to save the channel layout: {}", err));
+    }
+    app.dirty.borrow_mut().mark(id, &Render::Channels);
+}
+/// Ask for a name, like for a folder
+fn ask_name(window: &Window, title: &str, current: &str) -> Option<String> {
+    let dialog = Dialog::new_with_buttons(
+        Some(title),
+        Some(window),
+        DialogFlags::MODAL,
+        &[("_Cancel", ResponseType::Cancel.into()), ("_Ok", ResponseType::Ok.into())]
+    );
+    dialog.set_default_response(ResponseType::Ok.into());
+
+    let content = dialog.get_content_area();
+    content.set_property_margin(10);
+    content.set_spacing(6);
+    content.add(&Label::new("Name:"));
+
+    let entry = Entry::new();
+    entry.set_text(current);
+    entry.set_activates_default(true);
+    content.add(&entry);
+
+    dialog.show_all();
+    let response = dialog.run();
+    let name = entry.get_text().unwrap_or_default();
+    dialog.destroy();
+
+    let name = name.trim();
+    if response == ResponseType::Ok.into() && !name.is_empty() {
+        Some(name.to_string())
+    } else {
+        None
+    }
+}
+/// The heading above a group of channels. Channels dropped on it go in the group.
+fn channel_heading(app: &Rc<App>, id: ServerId, section: &Section) -> Button {
+    let text = match *section {
+        Section::Favorites(_) => String::from("Favorites"),
+        Section::Folder(ref folder, _) => format!("{} {}", if folder.collapsed { "▸" } else { "▾" }, folder.name),
+        Section::Other(_) => String::from("Channels")
+    };
+    let heading = Button::new_with_label(&text);
+    heading.set_relief(ReliefStyle::None);
+    add_class(&heading, "bold");
+    if let Some(label) = heading.get_child() {
+        label.set_halign(Align::Start);
+    }
+
+    let targets = [TargetEntry::new(CHANNEL_TARGET, TargetFlags::SAME_APP, 0)];
+    heading.drag_dest_set(DestDefaults::ALL, &targets, DragAction::MOVE);
+    let app_clone = Rc::clone(app);
+    let folder = match *section {
+        Section::Folder(ref folder, _) => Some(folder.clone()),
+        _ => None
+    };
+    let favorites = match *section {
+        Section::Favorites(_) => true,
+        _ => false
+    };
+    heading.connect_drag_data_received(move |_, _, _, _, data, _, _| {
+        if let Some(channel) = data.get_text().and_then(|text| text.parse().ok()) {
+            let folder = folder.as_ref().map(|folder| folder.id);
+            organize(&app_clone, id, |organizer, _| if favorites {
+                organizer.set_favorite(channel, true);
+            } else {
+                organizer.set_folder(channel, folder);
+            });
+        }
+    });
+
+    let folder = match *section {
+        Section::Folder(ref folder, _) => folder.clone(),
+        _ => return heading
+    };
+    let folder = Rc::new(folder);
+
+    let app_clone = Rc::clone(app);
+    let folder_clone = Rc::clone(&folder);
+    heading.connect_clicked(move |_| {
+        if let Err(err) = organizer::set_collapsed(&app_clone.db, folder_clone.id, !folder_clone.collapsed) {
+            alert(&app_clone.window, MessageType::Error, &format!("failed to save the folder: {}", err));
+        }
+        app_clone.dirty.borrow_mut().mark(id, &Render::Channels);
+    });
+
+    let app_clone = Rc::clone(app);
+    heading.connect_button_press_event(move |_, event| {
+        if event.get_button() == 3 {
+            let menu = Menu::new();
+
+            let rename = MenuItem::new_with_label("Rename folder");
+            let app_clone1 = Rc::clone(&app_clone);
+            let folder_clone = Rc::clone(&folder);
+            rename.connect_activate(move |_| {
+                if let Some(name) = ask_name(&app_clone1.window, "Synac: Rename folder", &folder_clone.name) {
+                    if let Err(err) = organizer::rename_folder(&app_clone1.db, folder_clone.id, &name) {
+                        alert(&app_clone1.window, MessageType::Error, &format!("failed to rename folder: {}", err));
+                    }
+                    app_clone1.dirty.borrow_mut().mark(id, &Render::Channels);
+                }
+            });
+            menu.add(&rename);
+
+            let delete = MenuItem::new_with_label("Delete folder");
+            delete.set_tooltip_text("Its channels stay, outside of any folder");
+            let app_clone2 = Rc::clone(&app_clone);
+            let folder_clone = Rc::clone(&folder);
+            delete.connect_activate(move |_| {
+                if let Err(err) = organizer::delete_folder(&app_clone2.db, folder_clone.id) {
+                    alert(&app_clone2.window, MessageType::Error, &format!("failed to delete folder: {}", err));
+                }
+                app_clone2.dirty.borrow_mut().mark(id, &Render::Channels);
+            });
+            menu.add(&delete);
+
+            menu.show_all();
+            menu.popup_at_pointer(&**event);
+        }
+        Inhibit(false)
+    });
+
+    heading
+}
 pub(crate) fn render_channels(app: &Rc<App>, synac: Option<&mut Synac>) {
     for child in app.channels.get_children() {
         app.channels.remove(&child);
@@ -333,13 +853,46 @@ pub(crate) fn render_channels(app: &Rc<App>, synac: Option<&mut Synac>) {
         app.channels_priv.remove(&child);
     }
     if let Some(synac) = synac {
-        let addr = synac.addr;
+        let host = Rc::new(synac.host.clone());
+        let id = synac.id;
+
+        let organizer = Organizer::load(&app.db, &synac.host).unwrap_or_else(|err| {
+            warn!("failed to load the channel layout: {}", err);
+            Organizer::default()
+        });
+        let folders = Rc::new(organizer.folders.clone());
+        let sections = organizer.arrange(&public_channels(synac));
 
-        let mut channel_list: Vec<_> = synac.state.channels.values().collect();
-        channel_list.sort_by_key(|channel| &channel.name);
+        let mut rows = organizer::rows(&sections);
 
-        for channel in channel_list {
+        // Private channels go by latest activity, without duplicates or closed ones
+        let hidden = dms::hidden(&app.db, &synac.host).unwrap_or_else(|err| {
+            warn!("failed to load closed conversations: {}", err);
+            HashMap::new()
+        });
+        let dm_list = dms::list(&synac.state, &synac.messages, &synac.unread, synac.user);
+        rows.extend(dm_list.iter()
+            .filter(|dm| dm.is_visible(hidden.get(&dm.channel).cloned()))
+            .map(|dm| Row::Channel(dm.channel)));
+
+        *app.channel_order.borrow_mut() = rows.iter().filter_map(|row| match *row {
+            Row::Channel(channel) if synac.state.channels.contains_key(&channel) => Some(channel),
+            _ => None
+        }).collect();
+
+        for row in rows {
+            let channel = match row {
+                Row::Heading(section) => {
+                    app.channels.add(&channel_heading(app, id, section));
+                    continue;
+                },
+                Row::Channel(channel) => match synac.state.channels.get(&channel) {
+                    Some(channel) => channel,
+                    None => continue
+                }
+            };
             let channel_id = channel.id;
+            let private = channel.private;
 
             let mut name = String::with_capacity(channel.name.len() + 1);
             if channel.private {
@@ -348,6 +901,10 @@ pub(crate) fn render_channels(app: &Rc<App>, synac: Option<&mut Synac>) {
                 } else {
                     name.push_str("unknown");
                 }
+                let unread = synac.unread.get(channel.id);
+                if unread > 0 {
+                    write!(name, " ({})", unread).unwrap();
+                }
             } else {
                 name.push('#');
                 name.push_str(&channel.name);
@@ -357,7 +914,7 @@ pub(crate) fn render_channels(app: &Rc<App>, synac: Option<&mut Synac>) {
 
             let app_clone = Rc::clone(app);
             button.connect_clicked(move |_| {
-                app_clone.connections.execute(addr, |result| {
+                app_clone.connections.execute(id, |result| {
                     if result.is_err() { return; }
                     let synac = result.unwrap();
 
@@ -365,14 +922,33 @@ pub(crate) fn render_channels(app: &Rc<App>, synac: Option<&mut Synac>) {
                 });
             });
 
+            if !private {
+                // Drag channels onto each other to reorder them
+                let targets = [TargetEntry::new(CHANNEL_TARGET, TargetFlags::SAME_APP, 0)];
+                button.drag_source_set(ModifierType::BUTTON1_MASK, &targets, DragAction::MOVE);
+                button.drag_dest_set(DestDefaults::ALL, &targets, DragAction::MOVE);
+
+                button.connect_drag_data_get(move |_, _, data, _, _| {
+                    data.set_text(&channel_id.to_string());
+                });
+                let app_clone = Rc::clone(app);
+                button.connect_drag_data_received(move |_, _, _, _, data, _, _| {
+                    if let Some(dragged) = data.get_text().and_then(|text| text.parse().ok()) {
+                        organize(&app_clone, id, |organizer, channels| organizer.move_before(channels, dragged, channel_id));
+                    }
+                });
+            }
+
             let app_clone = Rc::clone(app);
+            let host = Rc::clone(&host);
+            let folders = Rc::clone(&folders);
             button.connect_button_press_event(move |_, event| {
                 if event.get_button() == 3 {
                     let menu = Menu::new();
 
                     let mut mode = common::PERM_READ;
 
-                    app_clone.connections.execute(addr, |result| {
+                    app_clone.connections.execute(id, |result| {
                         if result.is_err() { return; }
                         let synac = result.unwrap();
 
@@ -388,19 +964,16 @@ pub(crate) fn render_channels(app: &Rc<App>, synac: Option<&mut Synac>) {
 
                         let app_clone1 = Rc::clone(&app_clone);
                         edit.connect_activate(move |_| {
-                            app_clone1.connections.execute(addr, |result| {
+                            let mut channel = None;
+                            app_clone1.connections.execute(id, |result| {
                                 if result.is_err() { return; }
                                 let synac = result.unwrap();
 
-                                if let Some(channel) = synac.state.channels.get(&channel_id) {
-                                    *app_clone1.stack_edit_channel.edit.borrow_mut() = Some(channel.id);
-                                    app_clone1.stack_edit_channel.name.set_text(&channel.name);
-                                    render_mode(&app_clone1.stack_edit_channel.mode_bots, channel.default_mode_bot);
-                                    render_mode(&app_clone1.stack_edit_channel.mode_users, channel.default_mode_user);
-                                }
+                                channel = synac.state.channels.get(&channel_id).cloned();
                             });
-
-                            app_clone1.stack.set_visible_child(&app_clone1.stack_edit_channel.container);
+                            if let Some(channel) = channel {
+                                edit_channel(&app_clone1, Some(&channel));
+                            }
                         });
 
                         menu.add(&edit);
@@ -409,49 +982,128 @@ pub(crate) fn render_channels(app: &Rc<App>, synac: Option<&mut Synac>) {
 
                         let app_clone2 = Rc::clone(&app_clone);
                         delete.connect_activate(move |_| {
-                            app_clone2.connections.execute(addr, |result| {
+                            app_clone2.connections.execute(id, |result| {
                                 if result.is_err() { return; }
                                 let synac = result.unwrap();
 
-                                let result = synac.session.write(&Packet::ChannelDelete(common::ChannelDelete {
+                                let result = synac.write(&Packet::ChannelDelete(common::ChannelDelete {
                                     id: channel_id
                                 }));
                                 if let Err(err) = result {
-                                    eprintln!("failed to send packet: {}", err);
+                                    error!("failed to send packet: {}", err);
                                 }
                             });
                         });
 
                         menu.add(&delete);
                     }
+                    if mode & common::PERM_MANAGE_MODES == common::PERM_MANAGE_MODES {
+                        let permissions = MenuItem::new_with_label("Permissions");
 
-                    let mut stmt = app_clone.db.prepare_cached(
-                        "SELECT COUNT(*) FROM muted WHERE channel = ? AND server = ?"
-                    ).unwrap();
-                    let count: i64 = stmt.query_row(
-                        &[&(channel_id as i64), &addr.to_string()],
-                        |row| row.get(0)
-                    ).unwrap();
-
-                    let mute = MenuItem::new_with_label(if count == 0 {
-                        "Mute channel"
-                    } else {
-                        "Unmute channel"
-                    });
+                        let app_clone2 = Rc::clone(&app_clone);
+                        permissions.connect_activate(move |_| permission_window::show(&app_clone2, id, channel_id));
+
+                        menu.add(&permissions);
+                    }
+
+                    let (level, default) = {
+                        let rules = app_clone.rules.borrow();
+                        (rules.channel_level(&host, channel_id), rules.server_level(&host).unwrap_or(Level::All))
+                    };
 
                     let app_clone3 = Rc::clone(&app_clone);
-                    mute.connect_activate(move |_| {
-                        app_clone3.db.execute(
-                            if count == 0 {
-                                "INSERT INTO muted (channel, server) VALUES (?, ?)"
-                            } else {
-                                "DELETE FROM muted WHERE channel = ? AND server = ?"
-                            },
-                            &[&(channel_id as i64), &addr.to_string()]
-                        ).unwrap();
-                    });
+                    let host_clone = Rc::clone(&host);
+                    let levels = level_menu(
+                        level,
+                        Some(format!("Server default ({})", default.describe())),
+                        move |level| {
+                            if let Err(err) = notifications::set_channel_level(&app_clone3.db, &host_clone, channel_id, level) {
+                                alert(&app_clone3.window, MessageType::Error, &format!("failed to save notification level: {}", err));
+                            }
+                            reload_rules(&app_clone3);
+                        }
+                    );
+                    let notify = MenuItem::new_with_label("Notifications");
+                    notify.set_submenu(&levels);
+                    menu.add(&notify);
+
+                    if !private {
+                        let placement = Organizer::load(&app_clone.db, &host)
+                            .map(|organizer| organizer.placement(channel_id))
+                            .unwrap_or_default();
+
+                        let favorite = MenuItem::new_with_label(if placement.favorite {
+                            "Remove from favorites"
+                        } else {
+                            "Add to favorites"
+                        });
+                        let app_clone4 = Rc::clone(&app_clone);
+                        favorite.connect_activate(move |_| {
+                            organize(&app_clone4, id, |organizer, _| organizer.set_favorite(channel_id, !placement.favorite));
+                        });
+                        menu.add(&favorite);
+
+                        let submenu = Menu::new();
+                        for folder in &*folders {
+                            if placement.folder == Some(folder.id) && !placement.favorite {
+                                continue;
+                            }
+                            let item = MenuItem::new_with_label(&folder.name);
+                            let app_clone4 = Rc::clone(&app_clone);
+                            let folder_id = folder.id;
+                            item.connect_activate(move |_| {
+                                organize(&app_clone4, id, |organizer, _| organizer.set_folder(channel_id, Some(folder_id)));
+                            });
+                            submenu.add(&item);
+                        }
+                        if placement.folder.is_some() || placement.favorite {
+                            let item = MenuItem::new_with_label("No folder");
+                            let app_clone4 = Rc::clone(&app_clone);
+                            item.connect_activate(move |_| {
+                                organize(&app_clone4, id, |organizer, _| organizer.set_folder(channel_id, None));
+                            });
+                            submenu.add(&item);
+                        }
+                        if !submenu.get_children().is_empty() {
+                            submenu.add(&SeparatorMenuItem::new());
+                        }
+                        let item = MenuItem::new_with_label("New folder...");
+                        let app_clone4 = Rc::clone(&app_clone);
+                        let host = Rc::clone(&host);
+                        item.connect_activate(move |_| {
+                            let name = match ask_name(&app_clone4.window, "Synac: New folder", "") {
+                                Some(name) => name,
+                                None => return
+                            };
+                            match organizer::create_folder(&app_clone4.db, &host, &name) {
+                                Ok(folder) => organize(&app_clone4, id, |organizer, _| organizer.set_folder(channel_id, Some(folder))),
+                                Err(err) => alert(&app_clone4.window, MessageType::Error, &format!("failed to create folder: {}", err))
+                            }
+                        });
+                        submenu.add(&item);
 
-                    menu.add(&mute);
+                        let folder = MenuItem::new_with_label("Move to folder");
+                        folder.set_submenu(&submenu);
+                        menu.add(&folder);
+                    }
+
+                    if private {
+                        let close = MenuItem::new_with_label("Close conversation");
+                        close.set_tooltip_text("Hide it here until there's a new message. Nothing is deleted.");
+
+                        let app_clone4 = Rc::clone(&app_clone);
+                        let host = Rc::clone(&host);
+                        close.connect_activate(move |_| {
+                            let now = chrono::Utc::now().timestamp();
+                            if let Err(err) = dms::hide(&app_clone4.db, &host, channel_id, now) {
+                                alert(&app_clone4.window, MessageType::Error, &err.to_string());
+                                return;
+                            }
+                            app_clone4.dirty.borrow_mut().mark(id, &Render::Channels);
+                        });
+
+                        menu.add(&close);
+                    }
 
                     menu.show_all();
                     menu.popup_at_pointer(&**event);
@@ -470,6 +1122,7 @@ pub(crate) fn render_channels(app: &Rc<App>, synac: Option<&mut Synac>) {
             app.channel_add.set_reveal_child(user.admin);
         }
     } else {
+        app.channel_order.borrow_mut().clear();
         app.channel_add.set_reveal_child(false);
         app.channel_name.set_text("");
         render_messages(app, None);
@@ -486,43 +1139,26 @@ pub(crate) fn render_messages(app: &Rc<App>, synac: Option<&mut Synac>) {
         app.messages.remove(&child);
     }
     if let Some(synac) = synac {
-        let addr = synac.addr;
+        let id = synac.id;
+        let layout = app.settings.borrow().layout;
+
         if let Some(channel) = synac.current_channel {
             let mut last: Option<&common::Message> = None;
+            let search = app.search_input.get_text().unwrap_or_default().to_lowercase();
 
             for msg in synac.messages.get(channel) {
-                let msgbox = GtkBox::new(Orientation::Vertical, 2);
-                let authorbox = GtkBox::new(Orientation::Horizontal, 4);
-
-                if last.map(|msg| msg.author) != Some(msg.author)
-                    || last.map(|msg| msg.timestamp + 60*5) < Some(msg.timestamp) {
-                    if last.is_some() {
-                        app.messages.add(&Separator::new(Orientation::Vertical));
-                    }
-
-                    let author = Label::new(&*synac.state.users[&msg.author].name);
-                    author.set_xalign(0.0);
-                    add_class(&author, "author");
-                    authorbox.add(&author);
-
-                    let mut time = String::with_capacity(32); // just a guess
-                    messages::format_timestamp(&mut time, msg.timestamp);
-                    if let Some(edit) = msg.timestamp_edit {
-                        time.push_str(" (edited ");
-                        messages::format_timestamp(&mut time, edit);
-                        time.push(')');
-                    }
-                    let time = Label::new(&*time);
-                    time.set_margin_right(10);
-                    time.set_hexpand(true);
-                    time.set_xalign(1.0);
-                    add_class(&time, "time");
-                    authorbox.add(&time);
-
-                    msgbox.add(&authorbox);
+                let mut full_time = String::with_capacity(32); // just a guess
+                messages::format_timestamp(&mut full_time, msg.timestamp);
+                if let Some(edit) = msg.timestamp_edit {
+                    full_time.push_str(" (edited ");
+                    messages::format_timestamp(&mut full_time, edit);
+                    full_time.push(')');
                 }
 
-                let string = Rc::new(String::from_utf8_lossy(&msg.text).into_owned());
+                let string = String::from_utf8_lossy(&msg.text).into_owned();
+                if !search.is_empty() && !string.to_lowercase().contains(&search) {
+                    continue;
+                }
                 let output = messages::markdown(&string);
 
                 let text = Label::new(None);
@@ -533,79 +1169,333 @@ pub(crate) fn render_messages(app: &Rc<App>, synac: Option<&mut Synac>) {
                 text.set_selectable(true);
                 text.set_xalign(0.0);
 
-                let app_clone = Rc::clone(app);
-                let msg_id = msg.id;
-                let msg_mine = msg.author == synac.user;
+                connect_message_popup(app, &text, id, msg.id, msg.author == synac.user);
 
-                text.connect_populate_popup(move |_, menu| {
-                    menu.add(&SeparatorMenuItem::new());
+                let author_name = synac.state.users.get(&msg.author).map(|user| &*user.name).unwrap_or("unknown");
 
-                    let mut has_perms = false;
+                match layout.layout {
+                    messages::Layout::Cozy => {
+                        let msgbox = GtkBox::new(Orientation::Vertical, 2);
 
-                    if msg_mine {
-                        has_perms = true;
+                        if !layout.is_grouped(last, msg) {
+                            if last.is_some() {
+                                app.messages.add(&Separator::new(Orientation::Vertical));
+                            }
 
-                        let edit = MenuItem::new_with_mnemonic("_E_dit message");
+                            let authorbox = GtkBox::new(Orientation::Horizontal, 4);
 
-                        let app_clone = Rc::clone(&app_clone);
-                        let string = Rc::clone(&string);
-                        edit.connect_activate(move |_| {
-                            *app_clone.message_edit_id.borrow_mut() = Some(msg_id);
-                            app_clone.message_edit_input.set_text(&string);
-                            app_clone.message_edit_input.grab_focus();
-                            app_clone.message_edit.set_reveal_child(true);
-                        });
+                            let author = Label::new(author_name);
+                            author.set_xalign(0.0);
+                            add_class(&author, "author");
+                            authorbox.add(&author);
 
-                        menu.add(&edit);
-                    } else {
-                        app_clone.connections.execute(addr, |result| {
-                            if result.is_err() { return; }
-                            let synac = result.unwrap();
+                            let time = Label::new(&*full_time);
+                            time.set_margin_right(10);
+                            time.set_hexpand(true);
+                            time.set_xalign(1.0);
+                            add_class(&time, "time");
+                            authorbox.add(&time);
 
-                            if synac.current_channel.is_none() { return };
-                            let channel_id = synac.current_channel.unwrap();
+                            msgbox.add(&authorbox);
+                        } else if layout.time_on_hover {
+                            text.set_tooltip_text(&*full_time);
+                        }
 
-                            if let Some(channel) = synac.state.channels.get(&channel_id) {
-                                if let Some(user) = synac.state.users.get(&synac.user) {
-                                    has_perms = synac::get_mode(channel, user) & common::PERM_MANAGE_MESSAGES
-                                                    == common::PERM_MANAGE_MESSAGES;
-                                }
-                            }
-                        });
+                        msgbox.add(&text);
+                        add_message(app, &msgbox, msg.id);
+                    },
+                    messages::Layout::Compact => {
+                        let msgbox = GtkBox::new(Orientation::Horizontal, 4);
+
+                        let mut short_time = String::with_capacity(7);
+                        short_time.push('[');
+                        messages::format_time(&mut short_time, msg.timestamp);
+                        short_time.push(']');
+
+                        let time = Label::new(&*short_time);
+                        time.set_valign(Align::Start);
+                        add_class(&time, "time");
+                        msgbox.add(&time);
+
+                        let author = Label::new(&*format!("<{}>", author_name));
+                        author.set_valign(Align::Start);
+                        add_class(&author, "author");
+                        msgbox.add(&author);
+
+                        if layout.time_on_hover {
+                            text.set_tooltip_text(&*full_time);
+                        }
+                        text.set_hexpand(true);
+                        msgbox.add(&text);
+                        add_message(app, &msgbox, msg.id);
                     }
+                }
 
-                    if has_perms {
-                        let delete = MenuItem::new_with_mnemonic("_D_elete message");
+                last = Some(msg);
+            }
+        }
+    }
+    app.messages.show_all();
+    app.messages.queue_draw();
+}
+/// Add a message to the view, with a check box in front while picking messages
+fn add_message(app: &Rc<App>, msgbox: &GtkBox, msg_id: usize) {
+    let selected = app.selected_messages.borrow();
+    let selected = match *selected {
+        Some(ref selected) => selected,
+        None => {
+            app.messages.add(msgbox);
+            return;
+        }
+    };
+
+    let row = GtkBox::new(Orientation::Horizontal, 4);
+
+    let check = CheckButton::new();
+    check.set_valign(Align::Start);
+    check.set_active(selected.contains(&msg_id));
+    let app_clone = Rc::clone(app);
+    check.connect_toggled(move |check| {
+        if let Some(ref mut selected) = *app_clone.selected_messages.borrow_mut() {
+            if check.get_active() {
+                selected.insert(msg_id);
+            } else {
+                selected.remove(&msg_id);
+            }
+        }
+        update_selected_label(&app_clone);
+    });
+    row.add(&check);
 
-                        let app_clone = Rc::clone(&app_clone);
-                        delete.connect_activate(move |_| {
-                            app_clone.connections.execute(addr, |result| {
-                                if result.is_err() { return; }
-                                let synac = result.unwrap();
+    msgbox.set_hexpand(true);
+    row.add(msgbox);
+    app.messages.add(&row);
+}
+fn update_selected_label(app: &Rc<App>) {
+    let count = app.selected_messages.borrow().as_ref().map(|selected| selected.len()).unwrap_or(0);
+    app.message_select_label.set_text(&format!("{} message(s) selected", count));
+}
+/// Start or stop picking messages to delete in bulk
+pub(crate) fn set_selecting(app: &Rc<App>, on: bool) {
+    *app.selected_messages.borrow_mut() = if on { Some(HashSet::new()) } else { None };
+    app.message_select.set_reveal_child(on);
+    update_selected_label(app);
+
+    if let Some(id) = *app.connections.current_server.lock().unwrap() {
+        app.dirty.borrow_mut().mark(id, &Render::Messages);
+    }
+}
+/// Show or hide the search bar, which filters the messages shown
+pub(crate) fn set_searching(app: &Rc<App>, on: bool) {
+    app.search.set_reveal_child(on);
+    if on {
+        app.search_input.grab_focus();
+    } else {
+        // Redraws through the changed handler
+        app.search_input.set_text("");
+    }
+}
+/// Carry out a keyboard shortcut
+pub(crate) fn run_shortcut(app: &Rc<App>, action: Shortcut) {
+    let current = *app.connections.current_server.lock().unwrap();
+    match action {
+        Shortcut::NextChannel | Shortcut::PreviousChannel => if let Some(id) = current {
+            let order = app.channel_order.borrow().clone();
+            app.connections.execute(id, |result| if let Ok(synac) = result {
+                let forward = action == Shortcut::NextChannel;
+                if let Some(channel) = shortcuts::cycle(&order, synac.current_channel, forward) {
+                    select_channel(app, synac, channel);
+                }
+            });
+        },
+        Shortcut::NextUnread => next_unread(app, current),
+        Shortcut::NextServer => {
+            let mut connected = HashSet::new();
+            app.connections.foreach(|synac| { connected.insert(synac.id); });
+
+            // In the same order as the server list
+            let mut order = Vec::new();
+            let mut stmt = app.db.prepare("SELECT id FROM servers ORDER BY name, account").unwrap();
+            for id in stmt.query_map(&[], |row| row.get::<_, ServerId>(0)).unwrap() {
+                let id = id.unwrap();
+                if connected.contains(&id) {
+                    order.push(id);
+                }
+            }
+            if let Some(id) = shortcuts::cycle(&order, current, true) {
+                if Some(id) != current {
+                    select_server(app, id);
+                }
+            }
+        },
+        Shortcut::FocusComposer => if app.message_input.get_reveal_child() {
+            if let Some(input) = app.message_input.get_child() {
+                input.grab_focus();
+            }
+        },
+        Shortcut::ToggleUsers => app.users_revealer.set_reveal_child(!app.users_revealer.get_reveal_child()),
+        Shortcut::EditLast => run_intent(app, Intent::Edit(None)),
+        Shortcut::MarkAllRead => if let Some(id) = current {
+            app.connections.execute(id, |result| if let Ok(synac) = result {
+                synac.unread = unread::Unread::new();
+            });
+            app.dirty.borrow_mut().mark(id, &Render::Channels);
+        },
+        Shortcut::Search => set_searching(app, !app.search.get_reveal_child()),
+        Shortcut::QuickSwitcher => switcher_window::show(app),
+        Shortcut::CheatSheet => shortcut_window::cheat_sheet(app)
+    }
+}
+/// Go to the next channel with unread messages, in the list of the current server first
+fn next_unread(app: &Rc<App>, current: Option<ServerId>) {
+    if let Some(id) = current {
+        let order = app.channel_order.borrow().clone();
+        let mut found = false;
+        app.connections.execute(id, |result| if let Ok(synac) = result {
+            let start = synac.current_channel
+                .and_then(|channel| order.iter().position(|&other| other == channel))
+                .map(|i| i + 1)
+                .unwrap_or(0);
+            let next = order.iter().cycle().skip(start).take(order.len()).cloned()
+                .find(|&channel| synac.unread.get(channel) > 0);
+            if let Some(channel) = next {
+                select_channel(app, synac, channel);
+                found = true;
+            }
+        });
+        if found {
+            return;
+        }
+    }
 
-                                let result = synac.session.write(&Packet::MessageDelete(common::MessageDelete {
-                                    id: msg_id
-                                }));
-                                if let Err(err) = result {
-                                    eprintln!("error sending packet: {}", err);
-                                }
-                            });
-                        });
+    let mut other = None;
+    app.connections.foreach(|synac| if other.is_none() && Some(synac.id) != current {
+        let channel = synac.state.channels.keys().cloned().filter(|&channel| synac.unread.get(channel) > 0).min();
+        other = channel.map(|channel| (synac.id, channel));
+    });
+    if let Some((id, channel)) = other {
+        select_server(app, id);
+        app.connections.execute(id, |result| if let Ok(synac) = result {
+            select_channel(app, synac, channel);
+        });
+    }
+}
+/// Add an entry to the local moderation log
+pub(crate) fn log_moderation(app: &Rc<App>, id: ServerId, action: Action, target: &str, detail: &str) {
+    let label = app.db.query_row(
+        "SELECT name, account FROM servers WHERE id = ?",
+        &[&id],
+        |row| server_label(&row.get::<_, String>(0), &row.get::<_, String>(1))
+    );
+    let result = label.map_err(Error::from).and_then(|label| {
+        moderation::record(&app.db, chrono::Utc::now().timestamp(), &label, action, target, detail)
+    });
+    if let Err(err) = result {
+        warn!("failed to log moderation action: {}", err);
+    }
+}
+/// Delete loaded messages in a channel.
+/// Deleting someone else's message is logged, deleting your own isn't.
+pub(crate) fn delete_messages(app: &Rc<App>, id: ServerId, channel: usize, ids: &[usize]) -> Option<String> {
+    let mut err = None;
+    let mut deleted = Vec::new();
+    app.connections.execute(id, |result| {
+        let synac = match result {
+            Ok(synac) => synac,
+            Err(inner) => {
+                err = Some(inner.to_string());
+                return;
+            }
+        };
+        for &msg_id in ids {
+            let msg = synac.messages.get(channel).iter()
+                .find(|msg| msg.id == msg_id)
+                .map(|msg| (msg.author, moderation::excerpt(&msg.text)));
+            let (author, excerpt) = match msg {
+                Some(msg) => msg,
+                None => continue
+            };
 
-                        menu.add(&delete);
+            if let Err(inner) = synac.write(&Packet::MessageDelete(common::MessageDelete {
+                id: msg_id
+            })) {
+                err = Some(inner.to_string());
+                break;
+            }
+            if author != synac.user {
+                let name = synac.state.users.get(&author).map(|user| user.name.clone());
+                deleted.push((name.unwrap_or_else(|| String::from("unknown")), excerpt));
+            }
+        }
+    });
+    for (author, excerpt) in deleted {
+        log_moderation(app, id, Action::DeleteMessage, &author, &excerpt);
+    }
+    err
+}
+fn connect_message_popup(app: &Rc<App>, text: &Label, id: ServerId, msg_id: usize, msg_mine: bool) {
+    let app_clone = Rc::clone(app);
+    text.connect_populate_popup(move |_, menu| {
+        menu.add(&SeparatorMenuItem::new());
+
+        let mut channel = None;
+        let mut can_manage = false;
+
+        app_clone.connections.execute(id, |result| {
+            if result.is_err() { return; }
+            let synac = result.unwrap();
+
+            if synac.current_channel.is_none() { return };
+            let channel_id = synac.current_channel.unwrap();
+            channel = Some(channel_id);
+
+            if let Some(channel) = synac.state.channels.get(&channel_id) {
+                if let Some(user) = synac.state.users.get(&synac.user) {
+                    can_manage = synac::get_mode(channel, user) & common::PERM_MANAGE_MESSAGES
+                                    == common::PERM_MANAGE_MESSAGES;
+                }
+            }
+        });
+
+        if msg_mine {
+            let edit = MenuItem::new_with_mnemonic("_E_dit message");
+
+            let app_clone = Rc::clone(&app_clone);
+            edit.connect_activate(move |_| run_intent(&app_clone, Intent::Edit(Some(msg_id))));
+
+            menu.add(&edit);
+        }
+
+        if let Some(channel) = channel {
+            if msg_mine || can_manage {
+                let delete = MenuItem::new_with_mnemonic("_D_elete message");
+
+                let app_clone = Rc::clone(&app_clone);
+                delete.connect_activate(move |_| {
+                    if let Some(err) = delete_messages(&app_clone, id, channel, &[msg_id]) {
+                        error!("failed to send packet: {}", err);
                     }
-                    menu.show_all();
                 });
 
-                msgbox.add(&text);
-                app.messages.add(&msgbox);
+                menu.add(&delete);
+            }
+            if can_manage && app_clone.selected_messages.borrow().is_none() {
+                let select = MenuItem::new_with_mnemonic("_Select messages");
+
+                let app_clone = Rc::clone(&app_clone);
+                select.connect_activate(move |_| {
+                    set_selecting(&app_clone, true);
+                    if let Some(ref mut selected) = *app_clone.selected_messages.borrow_mut() {
+                        selected.insert(msg_id);
+                    }
+                    update_selected_label(&app_clone);
+                });
 
-                last = Some(msg);
+                menu.add(&select);
             }
         }
-    }
-    app.messages.show_all();
-    app.messages.queue_draw();
+        menu.show_all();
+    });
 }
 pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
     for child in app.users.get_children() {
@@ -613,7 +1503,8 @@ pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
     }
     if let Some(synac) = synac {
         if let Some(channel) = synac.current_channel.and_then(|id| synac.state.channels.get(&id)) {
-            let addr = synac.addr;
+            let id = synac.id;
+            let host = Rc::new(synac.host.clone());
 
             let draw = |user: &common::User| {
                 let label = Label::new(&*user.name);
@@ -624,18 +1515,20 @@ pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
 
                 let user_id = user.id;
                 let app_clone = Rc::clone(app);
+                let host = Rc::clone(&host);
                 event.connect_button_press_event(move |_, event| {
                     if event.get_button() != 3 {
                         return Inhibit(false);
                     }
                     let mut admin = false;
-                    let mut id = 0;
+                    let mut me = 0;
                     let mut mode = 0;
 
                     let mut other_admin = None;
                     let mut other_ban = None;
+                    let mut other_name = String::new();
 
-                    app_clone.connections.execute(addr, |result| {
+                    app_clone.connections.execute(id, |result| {
                         if result.is_err() { return; }
                         let synac = result.unwrap();
 
@@ -648,12 +1541,13 @@ pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
                         let user = user.unwrap();
 
                         admin = user.admin;
-                        id = user.id;
+                        me = user.id;
                         mode = synac::get_mode(channel, user);
 
                         let other = synac.state.users.get(&user_id);
                         other_admin = other.map(|user| user.admin);
                         other_ban   = other.map(|user| user.ban);
+                        other_name  = other.map(|user| user.name.clone()).unwrap_or_default();
                     });
 
                     let menu = Menu::new();
@@ -663,7 +1557,7 @@ pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
 
                         let app_clone = Rc::clone(&app_clone);
                         edit_mode.connect_activate(move |_| {
-                            app_clone.connections.execute(addr, |result| {
+                            app_clone.connections.execute(id, |result| {
                                 if result.is_err() { return; }
                                 let synac = result.unwrap();
 
@@ -673,15 +1567,10 @@ pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
                                 if let Some(channel) = channel {
                                     if let Some(user) = user {
                                         *app_clone.stack_edit_user.user.borrow_mut() = Some(user_id);
-                                        if user.modes.contains_key(&channel.id) {
-                                            app_clone.stack_edit_user.radio_some.set_active(true);
-                                            app_clone.stack_edit_user.mode.set_sensitive(true);
-                                        } else {
-                                            app_clone.stack_edit_user.radio_none.set_active(true);
-                                            app_clone.stack_edit_user.mode.set_sensitive(false);
-                                        }
-                                        let mode = synac::get_mode(channel, user);
-                                        render_mode(&app_clone.stack_edit_user.mode, mode);
+                                        app_clone.stack_edit_user.mode.set_override(
+                                            user.modes.get(&channel.id).cloned(),
+                                            permissions::default_mode(channel, user)
+                                        );
 
                                         app_clone.stack.set_visible_child(&app_clone.stack_edit_user.container);
                                     }
@@ -692,7 +1581,7 @@ pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
                         menu.add(&edit_mode);
                     }
                     if let Some(other_admin) = other_admin {
-                        if admin && user_id != id {
+                        if admin && user_id != me {
                             let toggle_admin = MenuItem::new_with_label(if other_admin {
                                 "Demote admin"
                             } else {
@@ -700,21 +1589,28 @@ pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
                             });
 
                             let app_clone1 = Rc::clone(&app_clone);
+                            let name = other_name.clone();
                             toggle_admin.connect_activate(move |_| {
-                                app_clone1.connections.execute(addr, |result| {
+                                let mut sent = false;
+                                app_clone1.connections.execute(id, |result| {
                                     if result.is_err() { return; }
                                     let synac = result.unwrap();
 
-                                    let result = synac.session.write(&Packet::UserUpdate(common::UserUpdate {
+                                    let result = synac.write(&Packet::UserUpdate(common::UserUpdate {
                                         admin: Some(!other_admin),
                                         ban: None,
                                         channel_mode: None,
                                         id: user_id
                                     }));
-                                    if let Err(err) = result {
-                                        eprintln!("failed to send packet: {}", err);
+                                    match result {
+                                        Ok(()) => sent = true,
+                                        Err(err) => error!("failed to send packet: {}", err)
                                     }
                                 });
+                                if sent {
+                                    let action = if other_admin { Action::Demote } else { Action::Promote };
+                                    log_moderation(&app_clone1, id, action, &name, "");
+                                }
                             });
 
                             menu.add(&toggle_admin);
@@ -728,28 +1624,36 @@ pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
                                     });
 
                                     let app_clone2 = Rc::clone(&app_clone);
+                                    let name = other_name.clone();
                                     toggle_ban.connect_activate(move |_| {
                                         let app_clone = Rc::clone(&app_clone2);
+                                        let name = name.clone();
                                         let text = if other_ban {
                                             "Are you sure you want to unban this user?"
                                         } else {
                                             "Are you sure you want to ban this user?"
                                         };
                                         confirm(&app_clone2.window, text, move || {
-                                            app_clone.connections.execute(addr, |result| {
+                                            let mut sent = false;
+                                            app_clone.connections.execute(id, |result| {
                                                 if result.is_err() { return; }
                                                 let synac = result.unwrap();
 
-                                                let result = synac.session.write(&Packet::UserUpdate(common::UserUpdate {
+                                                let result = synac.write(&Packet::UserUpdate(common::UserUpdate {
                                                     admin: None,
                                                     ban: Some(!other_ban),
                                                     channel_mode: None,
                                                     id: user_id
                                                 }));
-                                                if let Err(err) = result {
-                                                    eprintln!("failed to send packet: {}", err);
+                                                match result {
+                                                    Ok(()) => sent = true,
+                                                    Err(err) => error!("failed to send packet: {}", err)
                                                 }
                                             });
+                                            if sent {
+                                                let action = if other_ban { Action::Unban } else { Action::Ban };
+                                                log_moderation(&app_clone, id, action, &name, "");
+                                            }
                                         });
                                     });
 
@@ -762,23 +1666,77 @@ pub(crate) fn render_users(app: &Rc<App>, synac: Option<&mut Synac>) {
                     let message = MenuItem::new_with_label("Message");
                     let app_clone3 = Rc::clone(&app_clone);
                     message.connect_activate(move |_| {
-                        app_clone3.connections.execute(addr, |result| {
+                        let mut created = false;
+                        app_clone3.connections.execute(id, |result| {
                             if result.is_err() { return; }
                             let synac = result.unwrap();
 
-                            let result = synac.session.write(&Packet::ChannelCreate(common::ChannelCreate {
+                            let dm_list = dms::list(&synac.state, &synac.messages, &synac.unread, synac.user);
+                            if let Some(channel) = dms::find(&dm_list, user_id) {
+                                if let Err(err) = dms::unhide(&app_clone3.db, &synac.host, channel) {
+                                    warn!("failed to reopen conversation: {}", err);
+                                }
+                                select_channel(&app_clone3, synac, channel);
+                                app_clone3.dirty.borrow_mut().mark(id, &Render::Channels);
+                                return;
+                            }
+
+                            let result = synac.write(&Packet::ChannelCreate(common::ChannelCreate {
                                 default_mode_bot: 0,
                                 default_mode_user: 0,
                                 name: String::new(),
                                 recipient: Some(user_id)
                             }));
-                            if let Err(err) = result {
-                                eprintln!("failed to send packet: {}", err);
+                            match result {
+                                Ok(()) => created = true,
+                                Err(err) => error!("failed to send packet: {}", err)
                             }
                         });
+                        if created {
+                            // Jump to it once the server sends it, see `open_pending_dm`
+                            *app_clone3.dm_pending.borrow_mut() = Some((id, user_id));
+                        }
                     });
                     menu.add(&message);
 
+                    let explain = MenuItem::new_with_label("Why can or can't they...?");
+                    let app_clone4 = Rc::clone(&app_clone);
+                    explain.connect_activate(move |_| {
+                        let mut channel = None;
+                        app_clone4.connections.execute(id, |result| {
+                            if let Ok(synac) = result {
+                                channel = synac.current_channel;
+                            }
+                        });
+                        explain_window::show(&app_clone4, id, user_id, channel);
+                    });
+                    menu.add(&explain);
+
+                    if user_id != me {
+                        let muted = app_clone.rules.borrow().is_user_muted(&host, user_id);
+                        let mute = MenuItem::new_with_label(if muted {
+                            "Unmute notifications"
+                        } else {
+                            "Mute notifications"
+                        });
+                        let app_clone5 = Rc::clone(&app_clone);
+                        let host = Rc::clone(&host);
+                        mute.connect_activate(move |_| {
+                            if let Err(err) = notifications::set_user_muted(&app_clone5.db, &host, user_id, !muted) {
+                                alert(&app_clone5.window, MessageType::Error, &format!("failed to save mute: {}", err));
+                            }
+                            reload_rules(&app_clone5);
+                        });
+                        menu.add(&mute);
+                    }
+
+                    if mode & common::PERM_MANAGE_MESSAGES == common::PERM_MANAGE_MESSAGES {
+                        let cleanup = MenuItem::new_with_label("Delete recent messages...");
+                        let app_clone5 = Rc::clone(&app_clone);
+                        cleanup.connect_activate(move |_| moderation_window::show(&app_clone5, id, Some(user_id)));
+                        menu.add(&cleanup);
+                    }
+
                     menu.show_all();
                     menu.popup_at_pointer(&**event);
                     Inhibit(false)
@@ -0,0 +1,262 @@
+use chrono::prelude::*;
+use failure::Error;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind as IoErrorKind, Read, Write};
+use std::path::Path;
+use synac::common::{self, Packet};
+
+/// Drop the oldest packets after this many
+pub const MAX_ENTRIES: usize = 10_000;
+
+const MAGIC: &[u8] = b"SYNACCAP\x01";
+
+#[derive(Debug, Fail)]
+pub enum CaptureError {
+    #[fail(display = "not a synac capture file")]
+    InvalidMagic,
+    #[fail(display = "corrupt capture file")]
+    Corrupt
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// Milliseconds since the unix epoch
+    pub time: i64,
+    pub server: String,
+    pub direction: Direction,
+    pub packet: Packet
+}
+impl Entry {
+    pub fn kind(&self) -> String {
+        kind(&self.packet)
+    }
+    pub fn format_time(&self) -> String {
+        let time = Utc.timestamp(self.time / 1000, (self.time % 1000) as u32 * 1_000_000);
+        time.with_timezone(&Local).format("%H:%M:%S%.3f").to_string()
+    }
+}
+
+pub struct Capture {
+    pub entries: VecDeque<Entry>,
+    /// The sequence number of the oldest entry.
+    /// Each entry gets the one after the last, and dropping or clearing entries moves this past them.
+    pub first: usize,
+    /// How many inspectors are open and not paused.
+    /// Packets are only recorded while there's at least one.
+    recorders: usize,
+    /// Bumped on every change, so views know when to refresh
+    pub generation: usize
+}
+impl Capture {
+    pub fn new() -> Self {
+        Capture {
+            entries: VecDeque::new(),
+            first: 0,
+            recorders: 0,
+            generation: 0
+        }
+    }
+    /// An inspector starts listening, because it was opened or unpaused
+    pub fn start(&mut self) {
+        self.recorders += 1;
+    }
+    /// An inspector stops listening, because it was closed or paused
+    pub fn stop(&mut self) {
+        self.recorders = self.recorders.saturating_sub(1);
+    }
+    pub fn is_recording(&self) -> bool {
+        self.recorders > 0
+    }
+    /// The sequence number the next entry gets
+    pub fn end(&self) -> usize {
+        self.first + self.entries.len()
+    }
+    /// The entries with their sequence numbers, from `start` on
+    pub fn since(&self, start: usize) -> Vec<(usize, Entry)> {
+        let skip = start.saturating_sub(self.first);
+        self.entries.iter().cloned().enumerate().skip(skip).map(|(i, entry)| (self.first + i, entry)).collect()
+    }
+    pub fn record<S: ToString>(&mut self, server: S, direction: Direction, packet: &Packet) {
+        if !self.is_recording() {
+            return;
+        }
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.pop_front();
+            self.first += 1;
+        }
+        let mut packet = packet.clone();
+        scrub(&mut packet);
+
+        let now = Utc::now();
+        self.entries.push_back(Entry {
+            time: now.timestamp() * 1000 + now.timestamp_subsec_millis() as i64,
+            server: server.to_string(),
+            direction: direction,
+            packet: packet
+        });
+        self.generation += 1;
+    }
+    pub fn clear(&mut self) {
+        self.first = self.end();
+        self.entries.clear();
+        self.generation += 1;
+    }
+}
+
+/// The packet's variant name, like `MessageReceive`
+pub fn kind(packet: &Packet) -> String {
+    let debug = format!("{:?}", packet);
+    let end = debug.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(debug.len());
+    debug[..end].to_string()
+}
+/// Remove passwords and tokens, so captures are safe to share
+pub fn scrub(packet: &mut Packet) {
+    let redacted = || String::from("<redacted>");
+    match *packet {
+        Packet::Login(ref mut login) => {
+            login.password = login.password.as_ref().map(|_| redacted());
+            login.token = login.token.as_ref().map(|_| redacted());
+        },
+        Packet::LoginSuccess(ref mut login) => login.token = redacted(),
+        Packet::LoginUpdate(ref mut update) => {
+            update.password_current = update.password_current.as_ref().map(|_| redacted());
+            update.password_new = update.password_new.as_ref().map(|_| redacted());
+        },
+        _ => ()
+    }
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), Error> {
+    let len = bytes.len() as u32;
+    writer.write_all(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8])?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = (len[0] as usize) << 24 | (len[1] as usize) << 16 | (len[2] as usize) << 8 | len[3] as usize;
+
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Write entries to a capture file.
+/// Each entry is a direction byte, the time as 8 bytes, then the server and packet as length-prefixed fields.
+pub fn save<'a, I>(path: &Path, entries: I) -> Result<(), Error>
+    where I: IntoIterator<Item = &'a Entry>
+{
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+
+    for entry in entries {
+        file.write_all(&[match entry.direction {
+            Direction::Sent => 0,
+            Direction::Received => 1
+        }])?;
+        let time = entry.time as u64;
+        file.write_all(&[
+            (time >> 56) as u8, (time >> 48) as u8, (time >> 40) as u8, (time >> 32) as u8,
+            (time >> 24) as u8, (time >> 16) as u8, (time >> 8) as u8, time as u8
+        ])?;
+        write_bytes(&mut file, entry.server.as_bytes())?;
+        write_bytes(&mut file, &common::serialize(&entry.packet)?)?;
+    }
+
+    file.flush()?;
+    Ok(())
+}
+pub fn load(path: &Path) -> Result<Vec<Entry>, Error> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 9];
+    file.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(CaptureError::InvalidMagic.into());
+    }
+
+    let mut entries = Vec::new();
+    loop {
+        let mut direction = [0];
+        match file.read_exact(&mut direction) {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == IoErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into())
+        }
+        let direction = match direction[0] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            _ => return Err(CaptureError::Corrupt.into())
+        };
+        let mut time = [0; 8];
+        file.read_exact(&mut time)?;
+        let time = time.iter().fold(0u64, |acc, &byte| acc << 8 | byte as u64) as i64;
+        let server = String::from_utf8(read_bytes(&mut file)?)?;
+        let packet = common::deserialize(&read_bytes(&mut file)?)?;
+
+        entries.push(Entry {
+            time: time,
+            server: server,
+            direction: direction,
+            packet: packet
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut capture = Capture::new();
+    let packet = Packet::Typing(common::Typing { channel: 1 });
+
+    capture.record("127.0.0.1:8439", Direction::Sent, &packet);
+    assert!(capture.entries.is_empty());
+
+    // Two inspectors open, one of them paused
+    capture.start();
+    capture.start();
+    capture.stop();
+    assert!(capture.is_recording());
+    capture.record("127.0.0.1:8439", Direction::Sent, &packet);
+    capture.record("127.0.0.1:8439", Direction::Received, &Packet::LoginSuccess(common::LoginSuccess {
+        created: false,
+        id: 1,
+        token: String::from("secret")
+    }));
+    assert_eq!(capture.entries.len(), 2);
+    assert_eq!(capture.since(1).iter().map(|&(seq, _)| seq).collect::<Vec<_>>(), vec![1]);
+
+    // Both closed
+    capture.stop();
+    capture.record("127.0.0.1:8439", Direction::Sent, &packet);
+    assert_eq!(capture.entries.len(), 2);
+    assert_eq!(capture.entries[0].kind(), "Typing");
+    assert!(!format!("{:?}", capture.entries[1].packet).contains("secret"));
+
+    let path = ::std::env::temp_dir().join("synac-inspector-test.cap");
+    save(&path, &capture.entries).unwrap();
+    let loaded = load(&path).unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[0].direction, Direction::Sent);
+    assert_eq!(loaded[0].time, capture.entries[0].time);
+    assert_eq!(loaded[1].server, "127.0.0.1:8439");
+    assert_eq!(loaded[1].kind(), "LoginSuccess");
+
+    capture.clear();
+    assert_eq!((capture.first, capture.end()), (2, 2));
+    capture.start();
+    capture.record("127.0.0.1:8439", Direction::Sent, &packet);
+    assert_eq!(capture.since(0).iter().map(|&(seq, _)| seq).collect::<Vec<_>>(), vec![2]);
+}
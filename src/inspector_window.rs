@@ -0,0 +1,322 @@
+use ::*;
+use gtk::{
+    ComboBoxText,
+    FileChooserAction,
+    FileChooserDialog,
+    ListBox,
+    ListBoxRow,
+    Paned,
+    TextView,
+    ToggleButton
+};
+use inspector::Direction;
+use std::cell::Cell;
+
+struct Inspector {
+    window: Window,
+    list: ListBox,
+    details: TextView,
+    server: ComboBoxText,
+    direction: ComboBoxText,
+    kind: Entry,
+    pause: ToggleButton,
+    source: Label,
+
+    /// Set when viewing a capture file instead of live traffic
+    loaded: RefCell<Option<Vec<inspector::Entry>>>,
+    /// The entries currently listed with their sequence numbers, in list order
+    shown: RefCell<Vec<(usize, inspector::Entry)>>,
+    generation: Cell<usize>,
+    /// The sequence number of the first live entry that isn't listed yet
+    next: Cell<usize>,
+    /// Whether this window counts as recording, which it does while open and not paused
+    recording: Cell<bool>,
+    servers: RefCell<Vec<String>>
+}
+
+fn matches(inspector: &Inspector, entry: &inspector::Entry) -> bool {
+    let server = inspector.server.get_active();
+    if server > 0 {
+        match inspector.servers.borrow().get(server as usize - 1) {
+            Some(server) if *server == entry.server => (),
+            _ => return false
+        }
+    }
+    match (inspector.direction.get_active(), entry.direction) {
+        (1, Direction::Received) | (2, Direction::Sent) => return false,
+        _ => ()
+    }
+    let kind = inspector.kind.get_text().unwrap_or_default().to_lowercase();
+    kind.is_empty() || entry.kind().to_lowercase().contains(&kind)
+}
+/// List the entries that pass the filters, after the ones already listed
+fn append(inspector: &Inspector, entries: Vec<(usize, inspector::Entry)>) {
+    // Keep the server filter up to date, without losing the selection
+    for &(_, ref entry) in &entries {
+        if !inspector.servers.borrow().contains(&entry.server) {
+            inspector.server.append_text(&entry.server);
+            inspector.servers.borrow_mut().push(entry.server.clone());
+        }
+    }
+
+    for (seq, entry) in entries {
+        if !matches(inspector, &entry) {
+            continue;
+        }
+        let arrow = match entry.direction {
+            Direction::Sent => "→",
+            Direction::Received => "←"
+        };
+        let label = Label::new(&*format!("{} {} {} {}", entry.format_time(), arrow, entry.server, entry.kind()));
+        label.set_xalign(0.0);
+
+        let row = ListBoxRow::new();
+        row.add(&label);
+        inspector.list.insert(&row, -1);
+
+        inspector.shown.borrow_mut().push((seq, entry));
+    }
+    inspector.list.show_all();
+}
+/// List everything again, like when the filters change
+fn refresh(app: &Rc<App>, inspector: &Inspector) {
+    inspector.shown.borrow_mut().clear();
+    for child in inspector.list.get_children() {
+        inspector.list.remove(&child);
+    }
+
+    let entries = match *inspector.loaded.borrow() {
+        Some(ref loaded) => loaded.iter().cloned().enumerate().collect(),
+        None => {
+            let capture = app.connections.capture.lock().unwrap();
+            inspector.next.set(capture.end());
+            capture.since(0)
+        }
+    };
+    append(inspector, entries);
+}
+/// Catch up with live traffic: list new entries and unlist the ones that were dropped or cleared
+fn update(app: &Rc<App>, inspector: &Inspector) {
+    let (first, entries) = {
+        let capture = app.connections.capture.lock().unwrap();
+        let entries = capture.since(inspector.next.get());
+        inspector.next.set(capture.end());
+        (capture.first, entries)
+    };
+
+    let gone = inspector.shown.borrow().iter().take_while(|&&(seq, _)| seq < first).count();
+    inspector.shown.borrow_mut().drain(..gone);
+    for _ in 0..gone {
+        if let Some(row) = inspector.list.get_row_at_index(0) {
+            inspector.list.remove(&row);
+        }
+    }
+
+    append(inspector, entries);
+}
+
+/// Start or stop recording for this window.
+/// Packets are recorded as long as any inspector wants them.
+fn set_recording(app: &Rc<App>, inspector: &Inspector, recording: bool) {
+    if inspector.recording.get() == recording {
+        return;
+    }
+    inspector.recording.set(recording);
+
+    let mut capture = app.connections.capture.lock().unwrap();
+    if recording {
+        capture.start();
+    } else {
+        capture.stop();
+    }
+}
+
+pub(crate) fn show(app: &Rc<App>) {
+    let inspector = Rc::new(Inspector {
+        window: Window::new(WindowType::Toplevel),
+        list: ListBox::new(),
+        details: TextView::new(),
+        server: ComboBoxText::new(),
+        direction: ComboBoxText::new(),
+        kind: Entry::new(),
+        pause: ToggleButton::new_with_mnemonic("_Pause"),
+        source: Label::new("Live traffic"),
+
+        loaded: RefCell::new(None),
+        shown: RefCell::new(Vec::new()),
+        generation: Cell::new(0),
+        next: Cell::new(0),
+        recording: Cell::new(false),
+        servers: RefCell::new(Vec::new())
+    });
+
+    inspector.window.set_title("Synac: Packet inspector");
+    inspector.window.set_default_size(1000, 600);
+
+    let container = GtkBox::new(Orientation::Vertical, 2);
+    container.set_property_margin(10);
+
+    let controls = GtkBox::new(Orientation::Horizontal, 4);
+
+    inspector.server.append_text("All servers");
+    inspector.server.set_active(0);
+    controls.add(&inspector.server);
+
+    inspector.direction.append_text("Sent and received");
+    inspector.direction.append_text("Sent");
+    inspector.direction.append_text("Received");
+    inspector.direction.set_active(0);
+    controls.add(&inspector.direction);
+
+    inspector.kind.set_placeholder_text("Packet type...");
+    controls.add(&inspector.kind);
+
+    let app_clone = Rc::clone(app);
+    let inspector_clone = Rc::clone(&inspector);
+    inspector.server.connect_changed(move |_| refresh(&app_clone, &inspector_clone));
+    let app_clone = Rc::clone(app);
+    let inspector_clone = Rc::clone(&inspector);
+    inspector.direction.connect_changed(move |_| refresh(&app_clone, &inspector_clone));
+    let app_clone = Rc::clone(app);
+    let inspector_clone = Rc::clone(&inspector);
+    inspector.kind.connect_property_text_notify(move |_| refresh(&app_clone, &inspector_clone));
+
+    let app_clone = Rc::clone(app);
+    let inspector_clone = Rc::clone(&inspector);
+    inspector.pause.connect_toggled(move |pause| {
+        set_recording(&app_clone, &inspector_clone, !pause.get_active());
+    });
+    controls.add(&inspector.pause);
+
+    let clear = Button::new_with_mnemonic("_Clear");
+    let app_clone = Rc::clone(app);
+    let inspector_clone = Rc::clone(&inspector);
+    clear.connect_clicked(move |_| {
+        app_clone.connections.capture.lock().unwrap().clear();
+        *inspector_clone.loaded.borrow_mut() = None;
+        inspector_clone.source.set_text("Live traffic");
+        refresh(&app_clone, &inspector_clone);
+    });
+    controls.add(&clear);
+
+    let save = Button::new_with_mnemonic("_Save...");
+    let inspector_clone = Rc::clone(&inspector);
+    save.connect_clicked(move |_| {
+        let dialog = FileChooserDialog::new(Some("Save capture"), Some(&inspector_clone.window), FileChooserAction::Save);
+        dialog.add_button("_Cancel", ResponseType::Cancel.into());
+        dialog.add_button("_Save", ResponseType::Accept.into());
+        dialog.set_do_overwrite_confirmation(true);
+        dialog.set_current_name("capture.synaccap");
+
+        if dialog.run() == ResponseType::Accept.into() {
+            if let Some(path) = dialog.get_filename() {
+                // Save exactly what's listed, so filters can be used to trim a capture
+                let shown = inspector_clone.shown.borrow();
+                if let Err(err) = inspector::save(&path, shown.iter().map(|&(_, ref entry)| entry)) {
+                    alert(&inspector_clone.window, MessageType::Error, &format!("failed to save capture: {}", err));
+                }
+            }
+        }
+        dialog.destroy();
+    });
+    controls.add(&save);
+
+    let load = Button::new_with_mnemonic("_Load...");
+    let app_clone = Rc::clone(app);
+    let inspector_clone = Rc::clone(&inspector);
+    load.connect_clicked(move |_| {
+        let dialog = FileChooserDialog::new(Some("Load capture"), Some(&inspector_clone.window), FileChooserAction::Open);
+        dialog.add_button("_Cancel", ResponseType::Cancel.into());
+        dialog.add_button("_Open", ResponseType::Accept.into());
+
+        if dialog.run() == ResponseType::Accept.into() {
+            if let Some(path) = dialog.get_filename() {
+                match inspector::load(&path) {
+                    Ok(entries) => {
+                        inspector_clone.source.set_text(&format!("Viewing {}", path.display()));
+                        *inspector_clone.loaded.borrow_mut() = Some(entries);
+                        refresh(&app_clone, &inspector_clone);
+                    },
+                    Err(err) => alert(&inspector_clone.window, MessageType::Error, &format!("failed to load capture: {}", err))
+                }
+            }
+        }
+        dialog.destroy();
+    });
+    controls.add(&load);
+
+    let live = Button::new_with_mnemonic("Back to li_ve");
+    let app_clone = Rc::clone(app);
+    let inspector_clone = Rc::clone(&inspector);
+    live.connect_clicked(move |_| {
+        *inspector_clone.loaded.borrow_mut() = None;
+        inspector_clone.source.set_text("Live traffic");
+        refresh(&app_clone, &inspector_clone);
+    });
+    controls.add(&live);
+
+    container.add(&controls);
+
+    inspector.source.set_xalign(0.0);
+    container.add(&inspector.source);
+
+    let paned = Paned::new(Orientation::Horizontal);
+    paned.set_vexpand(true);
+    paned.set_position(450);
+
+    let scroll = ScrolledWindow::new(None, None);
+    scroll.add(&inspector.list);
+    paned.pack1(&scroll, true, false);
+
+    inspector.details.set_editable(false);
+    inspector.details.set_monospace(true);
+
+    let scroll = ScrolledWindow::new(None, None);
+    scroll.add(&inspector.details);
+    paned.pack2(&scroll, true, false);
+
+    let inspector_clone = Rc::clone(&inspector);
+    inspector.list.connect_row_selected(move |_, row| {
+        let text = row.as_ref()
+            .and_then(|row| inspector_clone.shown.borrow().get(row.get_index() as usize).map(|&(_, ref entry)| {
+                format!("{:#?}", entry.packet)
+            }))
+            .unwrap_or_default();
+        if let Some(buffer) = inspector_clone.details.get_buffer() {
+            buffer.set_text(&text);
+        }
+    });
+
+    container.add(&paned);
+    inspector.window.add(&container);
+
+    set_recording(app, &inspector, true);
+
+    let open = Rc::new(Cell::new(true));
+    let open_clone = Rc::clone(&open);
+    let app_clone = Rc::clone(app);
+    let inspector_clone = Rc::clone(&inspector);
+    inspector.window.connect_destroy(move |_| {
+        open_clone.set(false);
+        set_recording(&app_clone, &inspector_clone, false);
+    });
+
+    let app_clone = Rc::clone(app);
+    let inspector_clone = Rc::clone(&inspector);
+    gtk::timeout_add(500, move || {
+        if !open.get() {
+            return Continue(false);
+        }
+        let generation = app_clone.connections.capture.lock().unwrap().generation;
+        // Other inspectors may still be recording, but a paused one stays as it is
+        let live = inspector_clone.loaded.borrow().is_none() && inspector_clone.recording.get();
+        if live && generation != inspector_clone.generation.get() {
+            inspector_clone.generation.set(generation);
+            update(&app_clone, &inspector_clone);
+        }
+        Continue(true)
+    });
+
+    refresh(app, &inspector);
+    inspector.window.show_all();
+}
@@ -0,0 +1,72 @@
+use ::*;
+use gtk::{Clipboard, TextView};
+
+fn fill(app: &Rc<App>, view: &TextView) {
+    let buffer = app.log_buffer.lock().unwrap();
+
+    let mut text = String::new();
+    for line in buffer.iter() {
+        text.push_str(line);
+        text.push('\n');
+    }
+
+    if let Some(textbuffer) = view.get_buffer() {
+        textbuffer.set_text(&text);
+    }
+}
+
+pub(crate) fn show(app: &Rc<App>) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Logs");
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(800, 500);
+
+    let container = GtkBox::new(Orientation::Vertical, 2);
+    container.set_property_margin(10);
+
+    let label = Label::new("Recent log output. Message text is hidden unless enabled in the preferences.");
+    label.set_xalign(0.0);
+    container.add(&label);
+
+    let view = TextView::new();
+    view.set_editable(false);
+    view.set_monospace(true);
+    fill(app, &view);
+
+    let scroll = ScrolledWindow::new(None, None);
+    scroll.set_vexpand(true);
+    scroll.add(&view);
+    container.add(&scroll);
+
+    let controls = GtkBox::new(Orientation::Horizontal, 2);
+    controls.set_halign(Align::End);
+
+    let refresh = Button::new_with_mnemonic("_Refresh");
+    let app_clone = Rc::clone(app);
+    let view_clone = view.clone();
+    refresh.connect_clicked(move |_| fill(&app_clone, &view_clone));
+    controls.add(&refresh);
+
+    let copy = Button::new_with_mnemonic("C_opy to clipboard");
+    let view_clone = view.clone();
+    copy.connect_clicked(move |_| {
+        if let Some(buffer) = view_clone.get_buffer() {
+            let (start, end) = buffer.get_bounds();
+            if let Some(text) = buffer.get_text(&start, &end, false) {
+                Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(&text);
+            }
+        }
+    });
+    controls.add(&copy);
+
+    let close = Button::new_with_mnemonic("_Close");
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    controls.add(&close);
+
+    container.add(&controls);
+
+    window.add(&container);
+    window.show_all();
+}
@@ -0,0 +1,243 @@
+use chrono::prelude::*;
+use failure::Error;
+use inspector;
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::sync::{Arc, Mutex};
+use synac::common::Packet;
+
+/// Rotate the log file once it grows past this many bytes
+pub const MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// How many rotated files (synac.log.1, synac.log.2, ...) to keep
+pub const MAX_FILES: usize = 3;
+/// How many lines to keep in memory for the log window
+pub const BUFFER_LINES: usize = 1000;
+
+/// Logs packets when enabled, for example with `--verbose`
+pub const PACKETS: &str = "client_gtk::packets";
+
+/// Off by default, so message text is redacted unless asked for
+static SHOW_TEXT: AtomicBool = ATOMIC_BOOL_INIT;
+
+pub type Buffer = Arc<Mutex<VecDeque<String>>>;
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64
+}
+impl LogFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile {
+            path: path,
+            file: file,
+            size: size
+        })
+    }
+    fn rotate(&mut self) -> io::Result<()> {
+        for i in (1..MAX_FILES).rev() {
+            let from = rotated_path(&self.path, i);
+            if from.exists() {
+                fs::rename(from, rotated_path(&self.path, i+1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        *self = LogFile::open(self.path.clone())?;
+        Ok(())
+    }
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.size + line.len() as u64 + 1 > MAX_FILE_SIZE {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+fn rotated_path(path: &Path, i: usize) -> PathBuf {
+    let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(format!(".{}", i));
+    path.with_file_name(name)
+}
+
+pub struct Logger {
+    default: LevelFilter,
+    filters: Vec<(String, LevelFilter)>,
+    file: Option<Mutex<LogFile>>,
+    buffer: Buffer
+}
+impl Logger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        // Longest matching prefix wins
+        self.filters.iter()
+            .filter(|&&(ref module, _)| target == module || target.starts_with(&format!("{}::", module)))
+            .max_by_key(|&&(ref module, _)| module.len())
+            .map(|&(_, level)| level)
+            .unwrap_or(self.default)
+    }
+}
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:<5} {}: {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        if record.level() <= Level::Warn {
+            eprintln!("{}", line);
+        }
+        if let Some(ref file) = self.file {
+            if let Err(err) = file.lock().unwrap().write(&line) {
+                eprintln!("failed to write log file: {}", err);
+            }
+        }
+
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.len() >= BUFFER_LINES {
+            buffer.pop_front();
+        }
+        buffer.push_back(line);
+    }
+    fn flush(&self) {
+        if let Some(ref file) = self.file {
+            let _ = file.lock().unwrap().file.flush();
+        }
+    }
+}
+
+/// Parse a filter such as `info,client_gtk::connections=debug`.
+/// A bare level sets the default, `module=level` overrides it for a module and its children.
+pub fn parse_filters(spec: &str) -> Option<(LevelFilter, Vec<(String, LevelFilter)>)> {
+    let mut default = LevelFilter::Info;
+    let mut filters = Vec::new();
+
+    for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let mut split = part.splitn(2, '=');
+        match (split.next()?, split.next()) {
+            (module, Some(level)) => filters.push((module.to_string(), level.parse().ok()?)),
+            (level, None) => default = level.parse().ok()?
+        }
+    }
+
+    Some((default, filters))
+}
+/// Install the global logger, writing to `path` if set.
+/// Returns the buffer of recent lines used by the log window.
+pub fn init(spec: &str, path: Option<PathBuf>) -> Result<Buffer, Error> {
+    let (default, filters) = parse_filters(spec)
+        .ok_or_else(|| format_err!("invalid log filter: {:?}", spec))?;
+
+    let max = filters.iter().map(|&(_, level)| level).chain(Some(default)).max().unwrap_or(default);
+
+    let buffer = Arc::new(Mutex::new(VecDeque::with_capacity(BUFFER_LINES)));
+    let logger = Logger {
+        default: default,
+        filters: filters,
+        file: match path {
+            Some(path) => Some(Mutex::new(LogFile::open(path)?)),
+            None => None
+        },
+        buffer: Arc::clone(&buffer)
+    };
+
+    log::set_boxed_logger(Box::new(logger)).map_err(|_| format_err!("logger already initialized"))?;
+    log::set_max_level(max);
+
+    Ok(buffer)
+}
+
+pub fn set_redact(redact: bool) {
+    SHOW_TEXT.store(!redact, Ordering::SeqCst);
+}
+/// Message text as it should appear in logs
+pub fn redact(text: &[u8]) -> String {
+    if !SHOW_TEXT.load(Ordering::SeqCst) {
+        format!("<{} bytes>", text.len())
+    } else {
+        format!("{:?}", String::from_utf8_lossy(text))
+    }
+}
+/// A packet as it should appear in logs.
+/// Credentials are always hidden, and message text unless redaction is off.
+/// Packets not listed here could carry either, so only their type is shown.
+pub fn packet(packet: &Packet) -> String {
+    match *packet {
+        Packet::Login(ref login) =>
+            format!("Login {{ bot: {}, name: {:?}, .. }}", login.bot, login.name),
+        Packet::LoginSuccess(ref login) =>
+            format!("LoginSuccess {{ created: {}, id: {}, .. }}", login.created, login.id),
+        Packet::LoginUpdate(ref update) =>
+            format!("LoginUpdate {{ name: {:?}, reset_token: {}, .. }}", update.name, update.reset_token),
+        Packet::MessageCreate(ref msg) =>
+            format!("MessageCreate {{ channel: {}, text: {} }}", msg.channel, redact(&msg.text)),
+        Packet::MessageUpdate(ref msg) =>
+            format!("MessageUpdate {{ id: {}, text: {} }}", msg.id, redact(&msg.text)),
+        Packet::MessageReceive(ref event) =>
+            format!(
+                "MessageReceive {{ id: {}, channel: {}, author: {}, new: {}, text: {} }}",
+                event.inner.id,
+                event.inner.channel,
+                event.inner.author,
+                event.new,
+                redact(&event.inner.text)
+            ),
+        Packet::Command(ref cmd) if !SHOW_TEXT.load(Ordering::SeqCst) =>
+            format!("Command {{ recipient: {}, args: <{} args> }}", cmd.recipient, cmd.args.len()),
+        Packet::Command(_) |
+        Packet::ChannelCreate(_) |
+        Packet::ChannelDelete(_) |
+        Packet::ChannelDeleteReceive(_) |
+        Packet::ChannelReceive(_) |
+        Packet::ChannelUpdate(_) |
+        Packet::Err(_) |
+        Packet::MessageDelete(_) |
+        Packet::MessageDeleteReceive(_) |
+        Packet::MessageList(_) |
+        Packet::MessageListReceived |
+        Packet::Typing(_) |
+        Packet::TypingReceive(_) |
+        Packet::UserReceive(_) |
+        Packet::UserUpdate(_) => format!("{:?}", packet),
+        _ => format!("{} {{ .. }}", inspector::kind(packet))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let (default, filters) = parse_filters("warn, client_gtk::connections=debug,client_gtk=info").unwrap();
+    assert_eq!(default, LevelFilter::Warn);
+
+    let logger = Logger {
+        default: default,
+        filters: filters,
+        file: None,
+        buffer: Arc::new(Mutex::new(VecDeque::new()))
+    };
+    assert_eq!(logger.level_for("client_gtk::connections"), LevelFilter::Debug);
+    assert_eq!(logger.level_for("client_gtk::functions"), LevelFilter::Info);
+    assert_eq!(logger.level_for("client_gtk_other"), LevelFilter::Warn);
+    assert_eq!(logger.level_for("synac"), LevelFilter::Warn);
+
+    assert!(parse_filters("loud").is_none());
+    assert!(parse_filters("client_gtk=loud").is_none());
+
+    set_redact(true);
+    assert_eq!(redact(b"secret"), "<6 bytes>");
+    assert_eq!(packet(&Packet::Typing(::synac::common::Typing { channel: 1 })), "Typing(Typing { channel: 1 })");
+}
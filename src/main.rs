@@ -1,19 +1,54 @@
 #[macro_use] extern crate failure;
+#[macro_use] extern crate log;
 extern crate chrono;
 extern crate gdk;
+extern crate getopts;
 extern crate gtk;
 extern crate notify_rust;
+extern crate openssl;
 extern crate pango;
 extern crate pulldown_cmark;
 extern crate rusqlite;
 extern crate synac;
 extern crate xdg;
 
+mod account_window;
+mod cli;
 mod connections;
+mod dashboard;
+mod dashboard_window;
+mod dirty;
+mod dms;
+mod explain_window;
 mod functions;
+mod inspector;
+mod inspector_window;
+mod log_window;
+mod logger;
 mod messages;
+mod migrations;
+#[cfg(test)] mod mock;
+mod model;
+mod moderation;
+mod moderation_window;
+mod notification_window;
+mod notifications;
+mod organizer;
 mod parser;
+mod permission_editor;
+mod permission_window;
+mod permissions;
+mod preferences;
+mod proxy;
+mod settings;
+mod shortcut_window;
+mod shortcuts;
+mod switcher;
+mod switcher_window;
 mod typing;
+mod unread;
+mod vault;
+mod vault_window;
 
 use gtk::{
     Align,
@@ -21,7 +56,9 @@ use gtk::{
     Button,
     ButtonsType,
     CheckButton,
+    CheckMenuItem,
     CssProvider,
+    DestDefaults,
     Dialog,
     DialogFlags,
     Entry,
@@ -36,7 +73,7 @@ use gtk::{
     Orientation,
     PolicyType,
     PositionType,
-    RadioButton,
+    ReliefStyle,
     ResponseType,
     Revealer,
     RevealerTransitionType,
@@ -46,27 +83,41 @@ use gtk::{
     Stack,
     StackTransitionType,
     StyleContext,
+    TargetEntry,
+    TargetFlags,
     STYLE_PROVIDER_PRIORITY_APPLICATION,
     Window,
     WindowType
 };
-use connections::{Connections, Synac};
+use connections::{ConnectionError, Connections, ServerEntry, ServerId, Synac};
+use dirty::Dirty;
 use failure::Error;
 use functions::*;
-use gdk::Screen;
+use gdk::{DragAction, ModifierType, Screen};
 use gtk::prelude::*;
+use model::{Intent, Model, Render, Server, Update};
+use moderation::Action;
+use notifications::{Incoming, Level, Rules};
 use notify_rust::Notification;
+use organizer::{Organizer, Row, Section};
+use permission_editor::PermissionEditor;
 use pango::WrapMode;
+use proxy::Proxy;
 use rusqlite::Connection as SqlConnection;
+use settings::Settings;
+use shortcuts::Action as Shortcut;
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt::Write;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
-use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use switcher::Recent;
 use synac::common::{self, Packet};
+use vault::Vault;
 use xdg::BaseDirectories;
 
 #[derive(Debug, Fail)]
@@ -78,39 +129,64 @@ struct EditChannel {
     edit: RefCell<Option<usize>>,
 
     name: Entry,
-    mode_bots: GtkBox,
-    mode_users: GtkBox
+    mode_bots: PermissionEditor,
+    mode_users: PermissionEditor
 }
 struct EditServer {
     container: GtkBox,
 
+    edit: RefCell<Option<ServerId>>,
+
     name: Entry,
     server: Entry,
-    hash: Entry
+    account: Entry,
+    hash: Entry,
+    nick: Entry,
+    proxy: Entry
 }
 struct EditUser {
     container: GtkBox,
     user: RefCell<Option<usize>>,
 
-    radio_none: RadioButton,
-    radio_some: RadioButton,
-    mode: GtkBox
+    mode: PermissionEditor
 }
 struct App {
+    /// Account changes waiting for the server, with what to say when they're done
+    account_pending: RefCell<HashMap<ServerId, &'static str>>,
+    accounts: Button,
+    accounts_menu: Menu,
     connections: Arc<Connections>,
+    css: CssProvider,
     db: Rc<SqlConnection>,
+    dirty: RefCell<Dirty>,
+    /// A private channel that was asked for, to open once it arrives
+    dm_pending: RefCell<Option<(ServerId, usize)>>,
+    log_buffer: logger::Buffer,
+    /// Notification rules, loaded again by `reload_rules` whenever they're changed
+    rules: RefCell<Rules>,
+    settings: RefCell<Settings>,
 
     channel_add: Revealer,
     channel_name: Label,
+    /// The channels of the current server, as listed
+    channel_order: RefCell<Vec<usize>>,
     channels: GtkBox,
     channels_priv: GtkBox,
     message_edit: Revealer,
-    message_edit_id: RefCell<Option<usize>>,
     message_edit_input: Entry,
     message_input: Revealer,
+    message_select: Revealer,
+    message_select_label: Label,
     messages: GtkBox,
     messages_noread: Revealer,
     messages_scroll: ScrolledWindow,
+    model: RefCell<Model>,
+    search: Revealer,
+    search_input: Entry,
+    /// Channels in the order they were last shown, for the quick switcher
+    recent: RefCell<Recent>,
+    /// The messages picked for deletion, while picking
+    selected_messages: RefCell<Option<HashSet<usize>>>,
     server_name: Label,
     servers: GtkBox,
     stack: Stack,
@@ -128,44 +204,74 @@ struct App {
 }
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args.get(0).map(|arg| &**arg).unwrap_or("synac-gtk");
+    let options = match cli::parse(args.iter().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("{}", cli::usage(program));
+            return;
+        }
+    };
+    if options.help {
+        println!("{}", cli::usage(program));
+        return;
+    }
+
     let basedirs = match BaseDirectories::with_prefix("synac") {
         Ok(basedirs) => basedirs,
         Err(err) => { eprintln!("error initializing xdg: {}", err); return; }
     };
-    let path = match basedirs.find_data_file("data.sqlite") {
-        Some(path) => path,
-        None => match basedirs.place_data_file("data.sqlite") {
-            Ok(path) => path,
-            Err(err) => { eprintln!("error placing config: {}", err); return; }
+    let path = match options.db {
+        Some(ref path) => path.clone(),
+        None => {
+            let file = match options.profile {
+                Some(ref profile) => format!("profiles/{}.sqlite", profile),
+                None => String::from("data.sqlite")
+            };
+            match basedirs.find_data_file(&file) {
+                Some(path) => path,
+                None => match basedirs.place_data_file(&file) {
+                    Ok(path) => path,
+                    Err(err) => { eprintln!("error placing config: {}", err); return; }
+                }
+            }
         }
     };
-    let db = match SqlConnection::open(&path) {
+
+    let log_path = match basedirs.place_data_file(format!("logs/{}.log", options.profile.as_ref().map(|s| &**s).unwrap_or("synac"))) {
+        Ok(path) => Some(path),
+        Err(err) => { eprintln!("error placing log file: {}", err); None }
+    };
+    let mut log_filter = options.log.clone()
+        .or_else(|| env::var("SYNAC_LOG").ok())
+        .unwrap_or_else(|| String::from("info"));
+    if options.verbose {
+        log_filter.push(',');
+        log_filter.push_str(logger::PACKETS);
+        log_filter.push_str("=trace");
+    }
+    let log_buffer = match logger::init(&log_filter, log_path) {
+        Ok(buffer) => buffer,
+        Err(err) => { eprintln!("error initializing logger: {}", err); return; }
+    };
+
+    let mut db = match SqlConnection::open(&path) {
         Ok(ok) => ok,
         Err(err) => {
-            eprintln!("Failed to open database");
-            eprintln!("{}", err);
+            error!("failed to open database: {}", err);
             return;
         }
     };
-    db.execute("CREATE TABLE IF NOT EXISTS data (
-                    key     TEXT NOT NULL PRIMARY KEY UNIQUE,
-                    value   TEXT NOT NULL
-                )", &[])
-        .expect("Couldn't create SQLite table");
-    db.execute("CREATE TABLE IF NOT EXISTS servers (
-                    ip      TEXT NOT NULL PRIMARY KEY UNIQUE,
-                    name    TEXT NOT NULL,
-                    hash    BLOB NOT NULL,
-                    token   TEXT
-                )", &[])
-        .expect("Couldn't create SQLite table");
-    db.execute("CREATE TABLE IF NOT EXISTS muted (
-                    channel INTEGER NOT NULL,
-                    server  TEXT    NOT NULL
-                )", &[])
-        .expect("Couldn't create SQLite table");
-
-    let nick = {
+    if let Err(err) = migrations::migrate(&mut db, Some(&path)) {
+        error!("failed to migrate database: {}", err);
+        return;
+    }
+
+    let nick = if let Some(ref nick) = options.nick {
+        nick.clone()
+    } else {
         let mut stmt = db.prepare("SELECT value FROM data WHERE key = 'nick'").unwrap();
         let mut rows = stmt.query(&[]).unwrap();
 
@@ -182,7 +288,7 @@ fn main() {
     };
 
     if let Err(err) = gtk::init() {
-        eprintln!("gtk error: {}", err);
+        error!("gtk error: {}", err);
         return;
     }
 
@@ -191,48 +297,86 @@ fn main() {
     window.set_default_size(1152, 648);
     window.set_position(gtk::WindowPosition::Center);
 
-    let radio_none = RadioButton::new_with_label("Inherit channel's mode");
-    let radio_some = RadioButton::new_with_label_from_widget(&radio_none, "Use custom mode:");
+    let settings = Settings::load(&db);
+    logger::set_redact(!settings.log_message_text);
+    let proxy = Proxy::parse(&settings.proxy).unwrap_or(None);
+    let rules = Rules::load(&db).unwrap_or_else(|err| {
+        warn!("failed to load notification rules: {}", err);
+        Rules::default()
+    });
+
+    let mut vault = match Vault::load(&db) {
+        Ok(vault) => vault,
+        Err(err) => {
+            error!("failed to load vault: {}", err);
+            return;
+        }
+    };
+    // Before connecting, so saved tokens can be used right away
+    if vault.is_enabled() {
+        let unlocked = vault_window::unlock(&window, |passphrase| vault.unlock(&db, passphrase));
+        if !unlocked {
+            vault.skip();
+        }
+    }
 
     let app = Rc::new(App {
+        account_pending: RefCell::new(HashMap::new()),
+        accounts: Button::new_from_icon_name("system-users", IconSize::Menu.into()),
+        accounts_menu: Menu::new(),
         channel_add: Revealer::new(),
         channel_name: Label::new(""),
+        channel_order: RefCell::new(Vec::new()),
         channels: GtkBox::new(Orientation::Vertical, 2),
         channels_priv: GtkBox::new(Orientation::Vertical, 2),
-        connections: Connections::new(&db, nick),
+        connections: Connections::new(&db, nick, proxy, vault),
+        css: CssProvider::new(),
         db: Rc::new(db),
+        dirty: RefCell::new(Dirty::new()),
+        dm_pending: RefCell::new(None),
+        log_buffer: log_buffer,
         message_edit: Revealer::new(),
-        message_edit_id: RefCell::new(None),
         message_edit_input: Entry::new(),
         message_input: Revealer::new(),
+        message_select: Revealer::new(),
+        message_select_label: Label::new(""),
         messages: GtkBox::new(Orientation::Vertical, 3),
         messages_noread: Revealer::new(),
         messages_scroll: ScrolledWindow::new(None, None),
+        model: RefCell::new(Model::new()),
+        recent: RefCell::new(Recent::new()),
+        rules: RefCell::new(rules),
+        search: Revealer::new(),
+        search_input: Entry::new(),
+        selected_messages: RefCell::new(None),
         server_name: Label::new(""),
         servers: GtkBox::new(Orientation::Vertical, 2),
+        settings: RefCell::new(settings),
         stack: Stack::new(),
         stack_edit_channel: EditChannel {
             container: GtkBox::new(Orientation::Vertical, 2),
             edit: RefCell::new(None),
 
             name: Entry::new(),
-            mode_bots: GtkBox::new(Orientation::Vertical, 2),
-            mode_users: GtkBox::new(Orientation::Vertical, 2)
+            mode_bots: PermissionEditor::new(false),
+            mode_users: PermissionEditor::new(false)
         },
         stack_edit_server: EditServer {
             container: GtkBox::new(Orientation::Vertical, 2),
+            edit: RefCell::new(None),
 
             name: Entry::new(),
             server: Entry::new(),
-            hash: Entry::new()
+            account: Entry::new(),
+            hash: Entry::new(),
+            nick: Entry::new(),
+            proxy: Entry::new()
         },
         stack_edit_user: EditUser {
             container: GtkBox::new(Orientation::Vertical, 2),
             user: RefCell::new(None),
 
-            radio_none: radio_none,
-            radio_some: radio_some,
-            mode: GtkBox::new(Orientation::Vertical, 2)
+            mode: PermissionEditor::new(true)
         },
         stack_main: GtkBox::new(Orientation::Horizontal, 10),
         user_stack: Stack::new(),
@@ -247,6 +391,7 @@ fn main() {
     app.channel_add.set_transition_type(RevealerTransitionType::SlideUp);
     app.message_edit.set_transition_type(RevealerTransitionType::SlideUp);
     app.message_input.set_transition_type(RevealerTransitionType::SlideUp);
+    app.message_select.set_transition_type(RevealerTransitionType::SlideUp);
     app.messages_noread.set_transition_type(RevealerTransitionType::SlideDown);
     app.stack.set_transition_type(StackTransitionType::SlideLeftRight);
     app.user_stack.set_transition_type(StackTransitionType::Crossfade);
@@ -262,6 +407,7 @@ fn main() {
 
     let user_name = Label::new(&**app.connections.nick.read().unwrap());
     add_class(&user_name, "bold");
+    user_name.set_tooltip_text("Your default nickname, for servers without their own");
 
     app.user_stack_edit.set_alignment(0.5);
 
@@ -278,15 +424,24 @@ fn main() {
 
         drop(old);
 
+        // Servers with their own nick keep it
+        let mut stmt = app_clone.db.prepare("SELECT id FROM servers WHERE nick IS NOT NULL").unwrap();
+        let custom: Vec<ServerId> = stmt.query_map(&[], |row| row.get(0)).unwrap()
+            .filter_map(|row| row.ok())
+            .collect();
+
         app_clone.connections.foreach(|synac| {
-            let result = synac.session.write(&Packet::LoginUpdate(common::LoginUpdate {
+            if custom.contains(&synac.id) {
+                return;
+            }
+            let result = synac.write(&Packet::LoginUpdate(common::LoginUpdate {
                 name: Some(text.clone()),
                 password_current: None,
                 password_new: None,
                 reset_token: false
             }));
             if let Err(err) = result {
-                let string = format!("failed to update server {}: {}", synac.addr, err.to_string());
+                let string = format!("failed to update server {}: {}", synac.peer, err.to_string());
                 alert(&app_clone.window, MessageType::Warning, &string);
             }
         });
@@ -332,10 +487,14 @@ fn main() {
 
     let app_clone = Rc::clone(&app);
     add.connect_clicked(move |_| {
+        *app_clone.stack_edit_server.edit.borrow_mut() = None;
         app_clone.stack_edit_server.name.set_text("");
         app_clone.stack_edit_server.server.set_text("");
         app_clone.stack_edit_server.server.set_sensitive(true);
+        app_clone.stack_edit_server.account.set_text("");
         app_clone.stack_edit_server.hash.set_text("");
+        app_clone.stack_edit_server.nick.set_text("");
+        app_clone.stack_edit_server.proxy.set_text("");
 
         app_clone.stack.set_visible_child(&app_clone.stack_edit_server.container);
     });
@@ -371,14 +530,7 @@ fn main() {
     add_class(&add, "add");
 
     let app_clone = Rc::clone(&app);
-    add.connect_clicked(move |_| {
-        *app_clone.stack_edit_channel.edit.borrow_mut() = None;
-        app_clone.stack_edit_channel.name.set_text("");
-        render_mode(&app_clone.stack_edit_channel.mode_bots, 0);
-        render_mode(&app_clone.stack_edit_channel.mode_users, common::PERM_READ | common::PERM_WRITE);
-
-        app_clone.stack.set_visible_child(&app_clone.stack_edit_channel.container);
-    });
+    add.connect_clicked(move |_| edit_channel(&app_clone, None));
 
     app.channel_add.add(&add);
     channels_wrapper.add(&app.channel_add);
@@ -394,10 +546,110 @@ fn main() {
 
     header.add(&app.channel_name);
 
+    let view = Button::new_from_icon_name("view-list", IconSize::Menu.into());
+    add_class(&view, "icon");
+    view.set_hexpand(true);
+    view.set_halign(Align::End);
+
+    let app_clone = Rc::clone(&app);
+    view.connect_clicked(move |view| {
+        let menu = Menu::new();
+        let current = app_clone.settings.borrow().layout;
+
+        for &(layout, label) in &[(messages::Layout::Cozy, "Cozy layout"), (messages::Layout::Compact, "Compact layout")] {
+            let item = CheckMenuItem::new_with_label(label);
+            item.set_draw_as_radio(true);
+            item.set_active(current.layout == layout);
+
+            let app_clone = Rc::clone(&app_clone);
+            item.connect_activate(move |_| {
+                apply_setting(&app_clone, "layout", layout.as_str());
+            });
+            menu.add(&item);
+        }
+
+        menu.add(&SeparatorMenuItem::new());
+
+        let hover = CheckMenuItem::new_with_label("Show time on hover");
+        hover.set_active(current.time_on_hover);
+
+        let app_clone1 = Rc::clone(&app_clone);
+        hover.connect_activate(move |_| {
+            apply_setting(&app_clone1, "time_on_hover", if current.time_on_hover { "0" } else { "1" });
+        });
+        menu.add(&hover);
+
+        let group = MenuItem::new_with_label("Group messages within");
+        let submenu = Menu::new();
+
+        for &(window, label) in &[
+            (0, "Never group"),
+            (60, "1 minute"),
+            (60*5, "5 minutes"),
+            (60*10, "10 minutes"),
+            (60*30, "30 minutes")
+        ] {
+            let item = CheckMenuItem::new_with_label(label);
+            item.set_draw_as_radio(true);
+            item.set_active(current.group_window == window);
+
+            let app_clone = Rc::clone(&app_clone);
+            item.connect_activate(move |_| {
+                apply_setting(&app_clone, "group_window", &window.to_string());
+            });
+            submenu.add(&item);
+        }
+
+        group.set_submenu(&submenu);
+        menu.add(&group);
+
+        menu.add(&SeparatorMenuItem::new());
+
+        let preferences = MenuItem::new_with_mnemonic("_Preferences");
+        let app_clone2 = Rc::clone(&app_clone);
+        preferences.connect_activate(move |_| preferences::show(&app_clone2));
+        menu.add(&preferences);
+
+        let logs = MenuItem::new_with_mnemonic("Show _logs");
+        let app_clone3 = Rc::clone(&app_clone);
+        logs.connect_activate(move |_| log_window::show(&app_clone3));
+        menu.add(&logs);
+
+        let inspector = MenuItem::new_with_mnemonic("Packet _inspector");
+        let app_clone4 = Rc::clone(&app_clone);
+        inspector.connect_activate(move |_| inspector_window::show(&app_clone4));
+        menu.add(&inspector);
+
+        let vault = MenuItem::new_with_mnemonic("Token _vault");
+        let app_clone5 = Rc::clone(&app_clone);
+        vault.connect_activate(move |_| vault_window::show(&app_clone5));
+        menu.add(&vault);
+
+        if app_clone.connections.vault.lock().unwrap().is_unlocked() {
+            let lock = MenuItem::new_with_mnemonic("Loc_k vault");
+            let app_clone6 = Rc::clone(&app_clone);
+            lock.connect_activate(move |_| app_clone6.connections.vault.lock().unwrap().lock());
+            menu.add(&lock);
+        }
+
+        menu.show_all();
+        menu.popup_at_widget(view, gdk::Gravity::SouthEast, gdk::Gravity::NorthEast, None);
+    });
+
+    header.add(&view);
+
+    add_class(&app.accounts, "icon");
+    app.accounts.set_tooltip_text("Switch account");
+    app.accounts.set_no_show_all(true);
+
+    let app_clone = Rc::clone(&app);
+    app.accounts.connect_clicked(move |button| {
+        app_clone.accounts_menu.popup_at_widget(button, gdk::Gravity::SouthEast, gdk::Gravity::NorthEast, None);
+    });
+    header.add(&app.accounts);
+
     let toggle_users = Button::new_from_icon_name("user-available", IconSize::Menu.into());
     add_class(&toggle_users, "icon");
-    toggle_users.set_hexpand(true);
-    toggle_users.set_halign(Align::End);
 
     let app_clone = Rc::clone(&app);
     toggle_users.connect_clicked(move |_| {
@@ -409,6 +661,24 @@ fn main() {
     content.add(&header);
     content.add(&Separator::new(Orientation::Vertical));
 
+    app.search_input.set_placeholder_text("Search messages in this channel...");
+    let app_clone = Rc::clone(&app);
+    app.search_input.connect_changed(move |_| {
+        if let Some(id) = *app_clone.connections.current_server.lock().unwrap() {
+            app_clone.dirty.borrow_mut().mark(id, &Render::Messages);
+        }
+    });
+    let app_clone = Rc::clone(&app);
+    app.search_input.connect_key_press_event(move |_, event| {
+        if event.get_keyval() == gdk::enums::key::Escape {
+            set_searching(&app_clone, false);
+            return Inhibit(true);
+        }
+        Inhibit(false)
+    });
+    app.search.add(&app.search_input);
+    content.add(&app.search);
+
     let noread = Label::new("You do not have the read permission in this channel");
     add_class(&noread, "warning");
     app.messages_noread.add(&noread);
@@ -433,19 +703,19 @@ fn main() {
         if pos != PositionType::Top {
             return;
         }
-        if let Some(addr) = *app_clone.connections.current_server.lock().unwrap() {
-            app_clone.connections.execute(addr, |result| {
+        if let Some(id) = *app_clone.connections.current_server.lock().unwrap() {
+            app_clone.connections.execute(id, |result| {
                 if let Ok(synac) = result {
                     if let Some(channel) = synac.current_channel {
-                        println!("requesting more messages");
+                        debug!("requesting more messages");
 
-                        if let Err(err) = synac.session.write(&Packet::MessageList(common::MessageList {
+                        if let Err(err) = synac.write(&Packet::MessageList(common::MessageList {
                             after: None,
                             before: synac.messages.get(channel).first().map(|msg| msg.id),
                             channel: channel,
                             limit: common::LIMIT_BULK
                         })) {
-                            eprintln!("error sending packet: {}", err);
+                            error!("failed to send packet: {}", err);
                         }
                     }
                 }
@@ -461,41 +731,65 @@ fn main() {
     let app_clone = Rc::clone(&app);
     app.message_edit_input.connect_activate(move |input| {
         let text = input.get_text().unwrap_or_default();
-        if text.is_empty() {
-            return;
-        }
-        input.set_sensitive(false);
-        if let Some(addr) = *app_clone.connections.current_server.lock().unwrap() {
-            app_clone.connections.execute(addr, |result| {
-                if result.is_err() {
-                    return;
-                }
-                let synac = result.unwrap();
-
-                if let Err(err) = synac.session.write(&Packet::MessageUpdate(common::MessageUpdate {
-                    id: app_clone.message_edit_id.borrow().expect("wait how is this variable not set"),
-                    text: text.into_bytes()
-                })) {
-                    eprintln!("failed to send packet: {}", err);
-                }
-            });
-        }
-        input.set_sensitive(true);
-        app_clone.message_edit.set_reveal_child(false);
+        run_intent(&app_clone, Intent::SubmitEdit(text));
     });
 
     message_edit.add(&app.message_edit_input);
 
     let message_edit_cancel = Button::new_with_mnemonic("_Cancel");
     let app_clone = Rc::clone(&app);
-    message_edit_cancel.connect_clicked(move |_| {
-        app_clone.message_edit.set_reveal_child(false);
-    });
+    message_edit_cancel.connect_clicked(move |_| run_intent(&app_clone, Intent::CancelEdit));
     message_edit.add(&message_edit_cancel);
 
     app.message_edit.add(&message_edit);
     content.add(&app.message_edit);
 
+    let message_select = GtkBox::new(Orientation::Horizontal, 2);
+
+    app.message_select_label.set_hexpand(true);
+    app.message_select_label.set_xalign(0.0);
+    message_select.add(&app.message_select_label);
+
+    let message_select_delete = Button::new_with_mnemonic("_Delete selected");
+    let app_clone = Rc::clone(&app);
+    message_select_delete.connect_clicked(move |_| {
+        let mut ids: Vec<usize> = match *app_clone.selected_messages.borrow() {
+            Some(ref selected) => selected.iter().cloned().collect(),
+            None => return
+        };
+        if ids.is_empty() {
+            return;
+        }
+        ids.sort();
+
+        let app_clone2 = Rc::clone(&app_clone);
+        let text = format!("Are you sure you want to delete {} message(s)?", ids.len());
+        confirm(&app_clone.window, &text, move || {
+            let app_clone = &app_clone2;
+            if let Some(id) = *app_clone.connections.current_server.lock().unwrap() {
+                let mut channel = None;
+                app_clone.connections.execute(id, |result| if let Ok(synac) = result {
+                    channel = synac.current_channel;
+                });
+                if let Some(channel) = channel {
+                    if let Some(err) = delete_messages(app_clone, id, channel, &ids) {
+                        alert(&app_clone.window, MessageType::Error, &format!("failed to delete messages: {}", err));
+                    }
+                }
+            }
+            set_selecting(app_clone, false);
+        });
+    });
+    message_select.add(&message_select_delete);
+
+    let message_select_cancel = Button::new_with_mnemonic("_Cancel");
+    let app_clone = Rc::clone(&app);
+    message_select_cancel.connect_clicked(move |_| set_selecting(&app_clone, false));
+    message_select.add(&message_select_cancel);
+
+    app.message_select.add(&message_select);
+    content.add(&app.message_select);
+
     let input = Entry::new();
     input.set_hexpand(true);
     input.set_placeholder_text("Send a message...");
@@ -505,33 +799,11 @@ fn main() {
 
     let app_clone = Rc::clone(&app);
     input.connect_key_press_event(move |_, event| {
-        if event.get_keyval() != 65362 {
-            // hardcoded value because gdk::enums::key::uparrow doesn't work
+        // Not a shortcut, since Up moves around everywhere else, but the cheat sheet lists it
+        if event.get_keyval() != gdk::enums::key::Up {
             return Inhibit(false);
         }
-        if let Some(addr) = *app_clone.connections.current_server.lock().unwrap() {
-            app_clone.connections.execute(addr, |result| {
-                if result.is_err() { return; }
-                let synac = result.unwrap();
-
-                if synac.current_channel.is_none() { return; }
-                let channel = synac.current_channel.unwrap();
-
-                if let Some(msg) = synac.messages.get(channel).iter().rev().find(|msg| msg.author == synac.user) {
-                    *app_clone.message_edit_id.borrow_mut() = Some(msg.id);
-                    app_clone.message_edit_input.set_text(&*String::from_utf8_lossy(&msg.text));
-                    app_clone.message_edit.set_reveal_child(true);
-
-                    // Wait until up arrow has been processed and then refocus
-
-                    let app_clone = Rc::clone(&app_clone);
-                    gtk::idle_add(move || {
-                        app_clone.message_edit_input.grab_focus();
-                        Continue(false)
-                    });
-                }
-            });
-        }
+        run_intent(&app_clone, Intent::Edit(None));
         Inhibit(false)
     });
     let app_clone = Rc::clone(&app);
@@ -542,14 +814,14 @@ fn main() {
         }
         *typing_last = Instant::now();
 
-        if let Some(addr) = *app_clone.connections.current_server.lock().unwrap() {
-            app_clone.connections.execute(addr, |result| {
+        if let Some(id) = *app_clone.connections.current_server.lock().unwrap() {
+            app_clone.connections.execute(id, |result| {
                 if let Ok(synac) = result {
                     if let Some(channel) = synac.current_channel {
-                        if let Err(err) = synac.session.write(&Packet::Typing(common::Typing {
+                        if let Err(err) = synac.write(&Packet::Typing(common::Typing {
                             channel: channel
                         })) {
-                            eprintln!("failed to send packet: {}", err);
+                            error!("failed to send packet: {}", err);
                         }
                     }
                 }
@@ -563,8 +835,8 @@ fn main() {
             return;
         }
         input.set_sensitive(false);
-        if let Some(addr) = *app_clone.connections.current_server.lock().unwrap() {
-            app_clone.connections.execute(addr, |result| {
+        if let Some(id) = *app_clone.connections.current_server.lock().unwrap() {
+            app_clone.connections.execute(id, |result| {
                 if result.is_err() {
                     return;
                 }
@@ -591,12 +863,12 @@ fn main() {
                         return;
                     }
 
-                    let result = synac.session.write(&Packet::Command(common::Command {
+                    let result = synac.write(&Packet::Command(common::Command {
                         args: args,
                         recipient: user_id.unwrap()
                     }));
                     if let Err(err) = result {
-                        eprintln!("failed to send packet: {}", err);
+                        error!("failed to send packet: {}", err);
                         return;
                     }
                     return;
@@ -605,7 +877,7 @@ fn main() {
                     return;
                 }
                 let channel = synac.current_channel.unwrap();
-                let result = synac.session.write(&Packet::MessageCreate(common::MessageCreate {
+                let result = synac.write(&Packet::MessageCreate(common::MessageCreate {
                     channel: channel,
                     text: text.into_bytes()
                 }));
@@ -616,17 +888,7 @@ fn main() {
                         }
                     }
 
-                    let mut stmt = app_clone.db.prepare_cached("SELECT hash, token FROM servers WHERE ip = ?").unwrap();
-                    let mut rows = stmt.query(&[&addr.to_string()]).unwrap();
-
-                    if let Some(row) = rows.next() {
-                        let row = row.unwrap();
-
-                        let hash = row.get(0);
-                        let token = row.get(1);
-
-                        connect(&app_clone, addr, hash, token);
-                    }
+                    connect(&app_clone, id);
                 }
             });
         }
@@ -654,19 +916,33 @@ fn main() {
     app.stack_edit_server.container.add(&app.stack_edit_server.name);
     app.stack_edit_server.container.add(&Label::new("The server name. This can be anything you want it to."));
 
-    app.stack_edit_server.server.set_placeholder_text("Server IP...");
+    app.stack_edit_server.server.set_placeholder_text("Server address...");
     app.stack_edit_server.container.add(&app.stack_edit_server.server);
 
-    let mut string = String::with_capacity(43 + 4 + 1);
-    write!(string, "The server IP address. The default port is {}.", common::DEFAULT_PORT).unwrap();
+    let mut string = String::with_capacity(58 + 4 + 1);
+    write!(string, "The server's host name or IP address. The default port is {}.", common::DEFAULT_PORT).unwrap();
 
     app.stack_edit_server.container.add(&Label::new(&*string));
 
+    app.stack_edit_server.account.set_placeholder_text("Account...");
+    app.stack_edit_server.container.add(&app.stack_edit_server.account);
+    app.stack_edit_server.container.add(&Label::new("A label for this account, to join the same server as several users.\n\
+                               Leave empty for your main account."));
+
     app.stack_edit_server.hash.set_placeholder_text("Server's certificate hash...");
     app.stack_edit_server.container.add(&app.stack_edit_server.hash);
     app.stack_edit_server.container.add(&Label::new("The server's certificate public key hash.\n\
                                This is to verify nobody is snooping on your connection"));
 
+    app.stack_edit_server.nick.set_placeholder_text("Nickname...");
+    app.stack_edit_server.container.add(&app.stack_edit_server.nick);
+    app.stack_edit_server.container.add(&Label::new("Your nickname on this server. Leave empty to use the default."));
+
+    app.stack_edit_server.proxy.set_placeholder_text("Proxy...");
+    app.stack_edit_server.container.add(&app.stack_edit_server.proxy);
+    app.stack_edit_server.container.add(&Label::new("A proxy for this server, like socks5://localhost:9050 or http://proxy:3128.\n\
+                               Leave empty to use the one from preferences, or enter \"direct\" to bypass it."));
+
     let edit_server_controls = GtkBox::new(Orientation::Horizontal, 2);
 
     let edit_server_cancel = Button::new_with_mnemonic("_Cancel");
@@ -683,19 +959,67 @@ fn main() {
         let name_text   = app_clone.stack_edit_server.name.get_text().unwrap_or_default();
         let server_text = app_clone.stack_edit_server.server.get_text().unwrap_or_default();
         let hash_text   = app_clone.stack_edit_server.hash.get_text().unwrap_or_default();
-
-        let addr = match connections::parse_addr(&server_text) {
-            Some(addr) => addr,
-            None => return
+        let nick_text   = app_clone.stack_edit_server.nick.get_text().unwrap_or_default();
+        let proxy_text  = app_clone.stack_edit_server.proxy.get_text().unwrap_or_default();
+        let account     = app_clone.stack_edit_server.account.get_text().unwrap_or_default();
+        let nick = if nick_text.is_empty() { None } else { Some(nick_text) };
+        let proxy_text = proxy_text.trim();
+        let proxy = if proxy_text.is_empty() { None } else { Some(proxy_text) };
+
+        if connections::parse_host(&server_text).is_none() {
+            alert(&app_clone.window, MessageType::Error, "Invalid server address. Format: <host[:port]>");
+            return;
+        }
+        if let Err(err) = Proxy::parse(proxy_text) {
+            alert(&app_clone.window, MessageType::Error, &err.to_string());
+            return;
+        }
+        let server_text = server_text.trim();
+
+        let edit = *app_clone.stack_edit_server.edit.borrow();
+        let result = match edit {
+            Some(id) => {
+                let old_nick = server_nick(&app_clone, id);
+
+                // Update in place, so the token isn't lost
+                app_clone.db.execute(
+                    "UPDATE servers SET name = ?, account = ?, hash = ?, nick = ?, proxy = ? WHERE id = ?",
+                    &[&name_text, &account, &hash_text, &nick, &proxy, &id]
+                ).map(|_| (id, old_nick))
+            },
+            None => app_clone.db.execute(
+                "INSERT INTO servers (name, ip, account, hash, nick, proxy) VALUES (?, ?, ?, ?, ?, ?)",
+                &[&name_text, &server_text, &account, &hash_text, &nick, &proxy]
+            ).map(|_| (app_clone.db.last_insert_rowid(), nick.clone()))
+        };
+        let (id, old_nick) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                let string = format!("failed to save server (is the account name already used?): {}", err);
+                alert(&app_clone.window, MessageType::Error, &string);
+                return;
+            }
         };
 
         app_clone.stack.set_visible_child(&app_clone.stack_main);
+        app_clone.dirty.borrow_mut().servers = true;
 
-        app_clone.db.execute(
-            "REPLACE INTO servers (name, ip, hash) VALUES (?, ?, ?)",
-            &[&name_text, &addr.to_string(), &hash_text]
-        ).unwrap();
-        render_servers(&app_clone);
+        if nick != old_nick {
+            let nick = nick.unwrap_or_else(|| app_clone.connections.nick.read().unwrap().clone());
+            app_clone.connections.execute(id, |result| {
+                if let Ok(synac) = result {
+                    let result = synac.write(&Packet::LoginUpdate(common::LoginUpdate {
+                        name: Some(nick),
+                        password_current: None,
+                        password_new: None,
+                        reset_token: false
+                    }));
+                    if let Err(err) = result {
+                        alert(&app_clone.window, MessageType::Warning, &format!("failed to update nick: {}", err));
+                    }
+                }
+            });
+        }
     });
 
     edit_server_controls.add(&edit_server_ok);
@@ -712,13 +1036,13 @@ fn main() {
     label.set_xalign(0.0);
     app.stack_edit_channel.container.add(&label);
 
-    app.stack_edit_channel.container.add(&app.stack_edit_channel.mode_bots);
+    app.stack_edit_channel.container.add(app.stack_edit_channel.mode_bots.widget());
 
     let label = Label::new("Default permissions for users: ");
     label.set_xalign(0.0);
     app.stack_edit_channel.container.add(&label);
 
-    app.stack_edit_channel.container.add(&app.stack_edit_channel.mode_users);
+    app.stack_edit_channel.container.add(app.stack_edit_channel.mode_users.widget());
 
     let edit_channel_controls = GtkBox::new(Orientation::Horizontal, 2);
 
@@ -734,8 +1058,8 @@ fn main() {
     let app_clone = Rc::clone(&app);
     edit_channel_ok.connect_clicked(move |_| {
         app_clone.stack.set_visible_child(&app_clone.stack_main);
-        if let Some(addr) = *app_clone.connections.current_server.lock().unwrap() {
-            app_clone.connections.execute(addr, |result| {
+        if let Some(id) = *app_clone.connections.current_server.lock().unwrap() {
+            app_clone.connections.execute(id, |result| {
                 if result.is_err() { return; }
                 let synac = result.unwrap();
 
@@ -748,8 +1072,8 @@ fn main() {
                 let packet = if let Some(channel) = *app_clone.stack_edit_channel.edit.borrow() {
                     Packet::ChannelUpdate(common::ChannelUpdate {
                         inner: common::Channel {
-                            default_mode_bot: get_mode(&app_clone.stack_edit_channel.mode_bots).unwrap(),
-                            default_mode_user: get_mode(&app_clone.stack_edit_channel.mode_users).unwrap(),
+                            default_mode_bot: app_clone.stack_edit_channel.mode_bots.mode(),
+                            default_mode_user: app_clone.stack_edit_channel.mode_users.mode(),
                             id: channel,
                             name: name,
                             private: false
@@ -757,15 +1081,15 @@ fn main() {
                     })
                 } else {
                     Packet::ChannelCreate(common::ChannelCreate {
-                        default_mode_bot: get_mode(&app_clone.stack_edit_channel.mode_bots).unwrap(),
-                        default_mode_user: get_mode(&app_clone.stack_edit_channel.mode_users).unwrap(),
+                        default_mode_bot: app_clone.stack_edit_channel.mode_bots.mode(),
+                        default_mode_user: app_clone.stack_edit_channel.mode_users.mode(),
                         name: name,
                         recipient: None
                     })
                 };
 
-                if let Err(err) = synac.session.write(&packet) {
-                    eprintln!("error sending packet: {}", err);
+                if let Err(err) = synac.write(&packet) {
+                    error!("failed to send packet: {}", err);
                 }
             });
         }
@@ -776,19 +1100,20 @@ fn main() {
 
     app.stack_edit_user.container.set_property_margin(10);
 
-    let app_clone = Rc::clone(&app);
-    app.stack_edit_user.radio_none.connect_toggled(move |radio_none| {
-        if radio_none.get_active() {
-            app_clone.stack_edit_user.mode.set_sensitive(false);
-        } else {
-            app_clone.stack_edit_user.mode.set_sensitive(true);
-        }
-    });
+    let label = Label::new("Permissions in this channel. \
+                            A user's own mode is always complete, so inherited ones are copied from the channel's default when saved. \
+                            Only inheriting everything keeps following the default.");
+    label.set_xalign(0.0);
+    label.set_line_wrap(true);
+    app.stack_edit_user.container.add(&label);
 
-    app.stack_edit_user.container.add(&app.stack_edit_user.radio_none);
-    app.stack_edit_user.container.add(&app.stack_edit_user.radio_some);
+    app.stack_edit_user.container.add(app.stack_edit_user.mode.widget());
 
-    app.stack_edit_user.container.add(&app.stack_edit_user.mode);
+    let edit_user_reset = Button::new_with_mnemonic("_Inherit everything");
+    edit_user_reset.set_halign(Align::Start);
+    let app_clone = Rc::clone(&app);
+    edit_user_reset.connect_clicked(move |_| app_clone.stack_edit_user.mode.reset());
+    app.stack_edit_user.container.add(&edit_user_reset);
 
     let edit_user_controls = GtkBox::new(Orientation::Horizontal, 2);
 
@@ -805,28 +1130,24 @@ fn main() {
 
     let app_clone = Rc::clone(&app);
     edit_user_ok.connect_clicked(move |_| {
-        if let Some(addr) = *app_clone.connections.current_server.lock().unwrap() {
-            app_clone.connections.execute(addr, |result| {
+        if let Some(id) = *app_clone.connections.current_server.lock().unwrap() {
+            app_clone.connections.execute(id, |result| {
                 if result.is_err() { return; }
                 let synac = result.unwrap();
 
                 if synac.current_channel.is_none() { return; }
                 let channel = synac.current_channel.unwrap();
 
-                let mode = if app_clone.stack_edit_user.radio_none.get_active() {
-                    None
-                } else {
-                    Some(get_mode(&app_clone.stack_edit_user.mode).unwrap())
-                };
+                let mode = app_clone.stack_edit_user.mode.get_override();
 
-                let result = synac.session.write(&Packet::UserUpdate(common::UserUpdate {
+                let result = synac.write(&Packet::UserUpdate(common::UserUpdate {
                     admin: None,
                     ban: None,
                     channel_mode: Some((channel, mode)),
                     id: app_clone.stack_edit_user.user.borrow().expect("( ͡° ͜ʖ ͡°)")
                 }));
                 if let Err(result) = result {
-                    eprintln!("error sending packet: {}", result);
+                    error!("failed to send packet: {}", result);
                 }
             });
             app_clone.stack.set_visible_child(&app_clone.stack_main);
@@ -838,34 +1159,25 @@ fn main() {
 
     app.window.add(&app.stack);
 
+    let app_clone = Rc::clone(&app);
+    app.window.connect_key_press_event(move |_, event| {
+        let action = shortcut_window::binding(event)
+            .and_then(|binding| app_clone.settings.borrow().shortcuts.find(&binding));
+        match action {
+            Some(action) => {
+                run_shortcut(&app_clone, action);
+                Inhibit(true)
+            },
+            None => Inhibit(false)
+        }
+    });
+
     // Load CSS
-    let screen = Screen::get_default();
-    match screen {
-        None => eprintln!("error: no default screen"),
+    match Screen::get_default() {
+        None => error!("no default screen"),
         Some(screen) => {
-            let css = CssProvider::new();
-            let result: Result<(), Error> = if let Some(file) = basedirs.find_config_file("style.css") {
-                if let Some(s) = file.to_str() {
-                    css.load_from_path(s).map_err(Error::from)
-                } else {
-                    Err(UnicodePathError.into())
-                }
-            } else {
-                let dark = if let Some(settings) = app.window.get_settings() {
-                    settings.get_property_gtk_application_prefer_dark_theme()
-                } else { false };
-
-                css.load_from_data(if dark {
-                    include_bytes!("dark.css")
-                } else {
-                    include_bytes!("light.css")
-                }).map_err(Error::from)
-            };
-            if let Err(err) = result {
-                let string = format!("failed to load css: {}", err);
-                alert(&app.window, MessageType::Error, &string);
-            }
-            StyleContext::add_provider_for_screen(&screen, &css, STYLE_PROVIDER_PRIORITY_APPLICATION);
+            load_css(&app);
+            StyleContext::add_provider_for_screen(&screen, &app.css, STYLE_PROVIDER_PRIORITY_APPLICATION);
         }
     }
 
@@ -875,82 +1187,58 @@ fn main() {
         Inhibit(false)
     });
 
+    if let Some(ref server) = options.connect {
+        quick_connect(&app, server, options.hash.clone().unwrap());
+    }
+
     gtk::timeout_add(10, move || {
-        let mut channels = false;
-        let mut messages = false;
-        let mut users = false;
+        let mut updates = Vec::new();
+        let mut account = Vec::new();
+        let mut channels = Vec::new();
 
         let current_server = *app.connections.current_server.lock().unwrap();
 
-        if let Err(err) = app.connections.try_read(|synac, packet, channel_id| {
-            println!("received {:?}", packet);
-            if current_server != Some(synac.addr) {
+        if let Err(err) = app.connections.try_read(|synac, packet, channel| {
+            match packet {
+                Packet::LoginSuccess(_) | Packet::Err(_) => account.push((synac.id, packet.clone())),
+                _ => ()
+            }
+            // Any server can notify, the rules decide whether it does
+            let notify = model::notification(&Server::new(synac), &packet);
+            if let Some(notify) = notify {
+                apply_update(&app, synac, Update::render(notify));
+            }
+            // But only the shown one is redrawn
+            if current_server != Some(synac.id) {
                 return;
             }
-            let channel = channel_id.and_then(|id| synac.state.channels.get(&id));
-            match packet {
-                Packet::ChannelDeleteReceive(_) |
-                Packet::ChannelReceive(_) => channels = true,
-                Packet::MessageDeleteReceive(_) => messages = true,
-                Packet::MessageListReceived => {
-                    messages = true;
-                    scroll_to_bottom(&app);
-                }
-                Packet::MessageReceive(e) => {
-                    messages = e.new;
-
-                    let msg = &e.inner;
-                    if e.new && msg.author != synac.user && !app.window.is_active() {
-                        if let Some(channel) = channel {
-                            if let Some(author) = synac.state.users.get(&msg.author) {
-                                let mut stmt = app.db.prepare_cached(
-                                    "SELECT COUNT(*) FROM muted WHERE channel = ? AND server = ?"
-                                ).unwrap();
-                                let count: i64 = stmt.query_row(
-                                    &[&(channel.id as i64), &synac.addr.to_string()],
-                                    |row| row.get(0)
-                                ).unwrap();
-
-                                if count == 0 {
-                                    let result =
-                                        Notification::new()
-                                            .summary(&format!("{} (#{})", author.name, channel.name))
-                                            .body(&*String::from_utf8_lossy(&msg.text))
-                                            .show();
-                                    if let Err(err) = result {
-                                        eprintln!("error showing notification: {}", err);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                },
-                Packet::UserReceive(_) => users = true,
-                _ => {}
+            if let Packet::ChannelReceive(ref event) = packet {
+                channels.push(event.inner.clone());
             }
+            updates.push(app.model.borrow_mut().packet(&Server::new(synac), &packet, channel));
         }) {
-            eprintln!("receive error: {}", err);
+            error!("receive error: {}", err);
             return Continue(true);
         }
 
-        if let Some(addr) = current_server {
-            app.connections.execute(addr, |result| {
+        for (id, packet) in account {
+            account_window::packet(&app, id, &packet);
+        }
+
+        if let Some(id) = current_server {
+            app.connections.execute(id, |result| {
                 if result.is_err() { return; }
                 let synac = result.unwrap();
 
-                if channels {
-                    render_channels(&app, Some(synac));
-                } else if messages {
-                    render_messages(&app, Some(synac));
-                } else if users {
-                    render_users(&app,    Some(synac));
+                for update in updates {
+                    apply_update(&app, synac, update);
                 }
-
-                if let Some(typing) = synac.typing.check(synac.current_channel, &synac.state) {
-                    app.typing.set_text(&typing);
+                for channel in &channels {
+                    open_pending_dm(&app, synac, channel);
                 }
             });
         }
+        flush(&app);
 
         Continue(true)
     });
@@ -4,6 +4,56 @@ use std::collections::HashMap;
 use std::fmt::Write;
 use synac::common::Message;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Cozy,
+    Compact
+}
+impl Layout {
+    pub fn parse(input: &str) -> Option<Self> {
+        match input {
+            "cozy" => Some(Layout::Cozy),
+            "compact" => Some(Layout::Compact),
+            _ => None
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Layout::Cozy => "cozy",
+            Layout::Compact => "compact"
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LayoutSettings {
+    pub layout: Layout,
+    pub time_on_hover: bool,
+    /// Seconds between two messages by the same author before a new group starts.
+    /// Zero means never group.
+    pub group_window: i64
+}
+impl Default for LayoutSettings {
+    fn default() -> Self {
+        LayoutSettings {
+            layout: Layout::Cozy,
+            time_on_hover: false,
+            group_window: 60*5
+        }
+    }
+}
+impl LayoutSettings {
+    /// Returns true if `msg` should be shown in the same group as `last`
+    pub fn is_grouped(&self, last: Option<&Message>, msg: &Message) -> bool {
+        match last {
+            Some(last) => self.group_window > 0
+                && last.author == msg.author
+                && last.timestamp + self.group_window >= msg.timestamp,
+            None => false
+        }
+    }
+}
+
 pub struct Messages {
     messages: HashMap<usize, Vec<Message>>
 }
@@ -76,9 +126,56 @@ pub fn format_timestamp(output: &mut String, timestamp: i64) {
     write!(output, "{}:{:02} ", hour, local.minute()).unwrap();
     output.push_str(if is_pm { "PM" } else { "AM" });
 }
+pub fn format_time(output: &mut String, timestamp: i64) {
+    let local = Utc.timestamp(timestamp, 0).with_timezone(&Local);
+    write!(output, "{:02}:{:02}", local.hour(), local.minute()).unwrap();
+}
 pub fn markdown(input: &str) -> String {
     let mut output = String::with_capacity(input.len());
     md_html::push_html(&mut output, MDParser::new(&input));
 
     output
 }
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let message = |author, timestamp| Message {
+        author: author,
+        channel: 1,
+        id: 1,
+        text: Vec::new(),
+        timestamp: timestamp,
+        timestamp_edit: None
+    };
+    let layout = LayoutSettings {
+        group_window: 60,
+        ..LayoutSettings::default()
+    };
+    let first = message(1, 1000);
+    assert!(!layout.is_grouped(None, &first));
+    assert!(layout.is_grouped(Some(&first), &message(1, 1000)));
+    assert!(layout.is_grouped(Some(&first), &message(1, 1060)));
+    assert!(!layout.is_grouped(Some(&first), &message(1, 1061)));
+    assert!(!layout.is_grouped(Some(&first), &message(2, 1010)));
+
+    let never = LayoutSettings {
+        group_window: 0,
+        ..layout
+    };
+    assert!(!never.is_grouped(Some(&first), &message(1, 1000)));
+
+    let now = Local::now();
+    let mut output = String::new();
+    format_timestamp(&mut output, now.timestamp());
+    assert!(output.starts_with("Today at "), "{}", output);
+
+    let old = Local.ymd(2017, 3, 4).and_hms(15, 7, 0);
+    output.clear();
+    format_timestamp(&mut output, old.timestamp());
+    assert_eq!(output, "2017-3-4 at 3:07 PM");
+
+    output.clear();
+    format_time(&mut output, old.timestamp());
+    assert_eq!(output, "15:07");
+}
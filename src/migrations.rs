@@ -0,0 +1,204 @@
+use failure::Error;
+use rusqlite::Connection as SqlConnection;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Fail)]
+pub enum MigrationError {
+    #[fail(display = "database schema version {} is newer than this client supports ({})", _0, _1)]
+    TooNew(usize, usize)
+}
+
+/// Upgrade steps, in order. Step `i` brings the schema from version `i` to `i + 1`.
+/// Never edit a step that has been released, add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: The original schema, from before versions were tracked.
+    "CREATE TABLE IF NOT EXISTS data (
+        key     TEXT NOT NULL PRIMARY KEY UNIQUE,
+        value   TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS servers (
+        ip      TEXT NOT NULL PRIMARY KEY UNIQUE,
+        name    TEXT NOT NULL,
+        hash    BLOB NOT NULL,
+        token   TEXT
+    );
+    CREATE TABLE IF NOT EXISTS muted (
+        channel INTEGER NOT NULL,
+        server  TEXT    NOT NULL
+    );",
+    // 2: Give muted a primary key, dropping any duplicates.
+    "CREATE TABLE muted_new (
+        channel INTEGER NOT NULL,
+        server  TEXT    NOT NULL,
+        PRIMARY KEY (channel, server)
+    );
+    INSERT OR IGNORE INTO muted_new (channel, server) SELECT channel, server FROM muted;
+    DROP TABLE muted;
+    ALTER TABLE muted_new RENAME TO muted;",
+    // 3: Per-server nicknames. NULL means the default nick.
+    "ALTER TABLE servers ADD COLUMN nick TEXT;",
+    // 4: Key servers by id, so one address can have several accounts.
+    "CREATE TABLE servers_new (
+        id      INTEGER PRIMARY KEY,
+        ip      TEXT NOT NULL,
+        account TEXT NOT NULL DEFAULT '',
+        name    TEXT NOT NULL,
+        hash    BLOB NOT NULL,
+        token   TEXT,
+        nick    TEXT,
+        UNIQUE (ip, account)
+    );
+    INSERT INTO servers_new (ip, name, hash, token, nick) SELECT ip, name, hash, token, nick FROM servers;
+    DROP TABLE servers;
+    ALTER TABLE servers_new RENAME TO servers;",
+    // 5: Per-server proxies. NULL means the global proxy.
+    "ALTER TABLE servers ADD COLUMN proxy TEXT;",
+    // 6: The optional token vault. Its single row only exists once a passphrase is set,
+    //    and then tokens are kept encrypted in token_sealed instead of token.
+    "CREATE TABLE vault (
+        id       INTEGER PRIMARY KEY CHECK (id = 1),
+        salt     BLOB NOT NULL,
+        verifier BLOB NOT NULL
+    );
+    ALTER TABLE servers ADD COLUMN token_sealed BLOB;",
+    // 7: A local log of moderation actions. The server is stored by label,
+    //    so the log stays readable after a server is forgotten.
+    "CREATE TABLE modlog (
+        id        INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        server    TEXT    NOT NULL,
+        action    TEXT    NOT NULL,
+        target    TEXT    NOT NULL,
+        detail    TEXT    NOT NULL DEFAULT ''
+    );",
+    // 8: Private channels closed locally. Like muted, keyed by the server address.
+    "CREATE TABLE hidden_dms (
+        server    TEXT    NOT NULL,
+        channel   INTEGER NOT NULL,
+        hidden_at INTEGER NOT NULL,
+        PRIMARY KEY (server, channel)
+    );",
+    // 9: Local channel organisation, keyed by server address and channel id.
+    "CREATE TABLE channel_folders (
+        id        INTEGER PRIMARY KEY,
+        server    TEXT    NOT NULL,
+        name      TEXT    NOT NULL,
+        position  INTEGER NOT NULL,
+        collapsed INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE channel_layout (
+        server    TEXT    NOT NULL,
+        channel   INTEGER NOT NULL,
+        favorite  INTEGER NOT NULL DEFAULT 0,
+        folder    INTEGER,
+        position  INTEGER,
+        PRIMARY KEY (server, channel)
+    );",
+    // 10: Notification rules. Muted channels become channels that notify nothing.
+    "CREATE TABLE notify_servers (
+        server  TEXT NOT NULL PRIMARY KEY,
+        level   TEXT NOT NULL
+    );
+    CREATE TABLE notify_channels (
+        server  TEXT    NOT NULL,
+        channel INTEGER NOT NULL,
+        level   TEXT    NOT NULL,
+        PRIMARY KEY (server, channel)
+    );
+    INSERT INTO notify_channels (server, channel, level) SELECT server, channel, 'nothing' FROM muted;
+    DROP TABLE muted;
+    CREATE TABLE notify_muted_users (
+        server  TEXT    NOT NULL,
+        user    INTEGER NOT NULL,
+        PRIMARY KEY (server, user)
+    );
+    CREATE TABLE notify_keywords (
+        keyword TEXT NOT NULL PRIMARY KEY
+    );
+    CREATE TABLE notify_schedules (
+        id           INTEGER PRIMARY KEY,
+        days         INTEGER NOT NULL,
+        start_minute INTEGER NOT NULL,
+        end_minute   INTEGER NOT NULL
+    );"
+];
+
+pub fn latest() -> usize {
+    MIGRATIONS.len()
+}
+pub fn version(db: &SqlConnection) -> Result<usize, Error> {
+    let version: i64 = db.query_row("PRAGMA user_version", &[], |row| row.get(0))?;
+    Ok(version as usize)
+}
+/// Where the database at `path` is copied before migrating away from `version`
+pub fn backup_path(path: &Path, version: usize) -> PathBuf {
+    let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(format!(".v{}.bak", version));
+    path.with_file_name(name)
+}
+/// Bring the database up to the latest schema version.
+/// If `path` is set and the file isn't empty, it's backed up first.
+pub fn migrate(db: &mut SqlConnection, path: Option<&Path>) -> Result<(), Error> {
+    let current = version(db)?;
+    let latest = latest();
+
+    if current > latest {
+        return Err(MigrationError::TooNew(current, latest).into());
+    }
+    if current == latest {
+        return Ok(());
+    }
+
+    if let Some(path) = path {
+        if fs::metadata(path).map(|meta| meta.len() > 0).unwrap_or(false) {
+            fs::copy(path, backup_path(path, current))?;
+        }
+    }
+
+    let tx = db.transaction()?;
+    for step in &MIGRATIONS[current..] {
+        tx.execute_batch(step)?;
+    }
+    tx.execute_batch(&format!("PRAGMA user_version = {}", latest))?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut db = SqlConnection::open_in_memory().unwrap();
+
+    // What an existing user's database looks like
+    db.execute_batch(MIGRATIONS[0]).unwrap();
+    db.execute_batch("INSERT INTO muted (channel, server) VALUES (1, 'a'), (1, 'a'), (2, 'a')").unwrap();
+    db.execute_batch("INSERT INTO servers (ip, name, hash, token) VALUES ('a', 'A', 'hash', 'token')").unwrap();
+    assert_eq!(version(&db).unwrap(), 0);
+
+    migrate(&mut db, None).unwrap();
+    assert_eq!(version(&db).unwrap(), latest());
+
+    // Mutes carry over, without the duplicates
+    let count: i64 = db.query_row("SELECT COUNT(*) FROM notify_channels WHERE level = 'nothing'", &[], |row| row.get(0)).unwrap();
+    assert_eq!(count, 2);
+    assert!(db.execute("INSERT INTO notify_channels (server, channel, level) VALUES ('a', 1, 'all')", &[]).is_err());
+
+    let (id, account, token, proxy): (i64, String, String, Option<String>) = db.query_row(
+        "SELECT id, account, token, proxy FROM servers WHERE ip = 'a'",
+        &[],
+        |row| (row.get(0), row.get(1), row.get(2), row.get(3))
+    ).unwrap();
+    assert_eq!((id, &*account, &*token, proxy), (1, "", "token", None));
+    assert!(db.execute("INSERT INTO servers (ip, account, name, hash) VALUES ('a', 'bot', 'A', 'hash')", &[]).is_ok());
+    assert!(db.execute("INSERT INTO servers (ip, account, name, hash) VALUES ('a', 'bot', 'A', 'hash')", &[]).is_err());
+
+    // Migrating twice is a no-op
+    migrate(&mut db, None).unwrap();
+
+    db.execute_batch(&format!("PRAGMA user_version = {}", latest() + 1)).unwrap();
+    assert!(migrate(&mut db, None).is_err());
+
+    assert_eq!(backup_path(Path::new("/tmp/data.sqlite"), 1), Path::new("/tmp/data.sqlite.v1.bak"));
+}
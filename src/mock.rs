@@ -0,0 +1,695 @@
+//! A minimal in-process synac server, for testing without a real server.
+//! It speaks just enough of the protocol for logging in, listing and
+//! sending messages, deleting them and typing.
+//! There's also a stand-in proxy, to test connecting through one.
+
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::sha;
+use openssl::ssl::{SslAcceptor, SslMethod, SslStream};
+use openssl::x509::{X509, X509NameBuilder};
+use connections;
+use proxy::Kind;
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use synac::Listener;
+use synac::common::{self, Packet};
+
+pub const PASSWORD: &str = "hunter2";
+pub const TOKEN: &str = "mock-token";
+
+pub struct State {
+    pub users: HashMap<usize, common::User>,
+    pub channels: HashMap<usize, common::Channel>,
+    pub messages: Vec<common::Message>,
+    next_id: usize,
+    clients: Vec<Sender<Packet>>
+}
+impl State {
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+    fn broadcast(&mut self, packet: &Packet) {
+        self.clients.retain(|client| client.send(packet.clone()).is_ok());
+    }
+    pub fn add_channel(&mut self, name: &str) -> usize {
+        let id = self.next_id();
+        self.channels.insert(id, common::Channel {
+            default_mode_bot: 0,
+            default_mode_user: common::PERM_READ | common::PERM_WRITE,
+            id: id,
+            name: name.to_string(),
+            private: false
+        });
+        id
+    }
+    pub fn add_user(&mut self, name: &str) -> usize {
+        let id = self.next_id();
+        self.users.insert(id, common::User {
+            admin: false,
+            ban: false,
+            bot: false,
+            id: id,
+            modes: HashMap::new(),
+            name: name.to_string()
+        });
+        id
+    }
+    pub fn add_message(&mut self, channel: usize, author: usize, timestamp: i64, text: &[u8]) -> usize {
+        let id = self.next_id();
+        self.messages.push(common::Message {
+            author: author,
+            channel: channel,
+            id: id,
+            text: text.to_vec(),
+            timestamp: timestamp,
+            timestamp_edit: None
+        });
+        id
+    }
+}
+
+pub struct MockServer {
+    pub addr: SocketAddr,
+    pub hash: String,
+    pub state: Arc<Mutex<State>>
+}
+impl MockServer {
+    pub fn start() -> MockServer {
+        let rsa = Rsa::generate(2048).unwrap();
+        let pkey = PKey::from_rsa(rsa).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&pkey).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let cert = cert.build();
+
+        // The same hash synac's Session verifies the certificate against
+        let digest = sha::sha256(&pkey.public_key_to_pem().unwrap());
+        let mut hash = String::with_capacity(64);
+        for byte in &digest {
+            write!(hash, "{:02X}", byte).unwrap();
+        }
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&pkey).unwrap();
+        acceptor.set_certificate(&cert).unwrap();
+        let acceptor = Arc::new(acceptor.build());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let state = Arc::new(Mutex::new(State {
+            users: HashMap::new(),
+            channels: HashMap::new(),
+            messages: Vec::new(),
+            next_id: 0,
+            clients: Vec::new()
+        }));
+
+        let state_clone = Arc::clone(&state);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue
+                };
+                let acceptor = Arc::clone(&acceptor);
+                let state = Arc::clone(&state_clone);
+                thread::spawn(move || {
+                    if let Ok(stream) = acceptor.accept(stream) {
+                        serve(stream, state);
+                    }
+                });
+            }
+        });
+
+        MockServer {
+            addr: addr,
+            hash: hash,
+            state: state
+        }
+    }
+}
+
+fn serve(mut stream: SslStream<TcpStream>, state: Arc<Mutex<State>>) {
+    // Poll like the client does, so packets from other clients can be forwarded in between
+    if stream.get_ref().set_nonblocking(true).is_err() {
+        return;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut listener = Listener::new();
+    let mut user = None;
+
+    loop {
+        while let Ok(packet) = rx.try_recv() {
+            if common::write(&mut stream, &packet).is_err() {
+                return;
+            }
+        }
+
+        let packet = match listener.try_read(&mut stream) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => {
+                thread::sleep(Duration::from_millis(5));
+                continue;
+            },
+            Err(_) => return
+        };
+
+        let mut state = state.lock().unwrap();
+        let reply = match (packet, user) {
+            (Packet::Login(login), None) => {
+                let valid = login.token.as_ref().map(|token| token == TOKEN).unwrap_or(false)
+                    || login.password.as_ref().map(|password| password == PASSWORD).unwrap_or(false);
+                if !valid {
+                    vec![Packet::Err(common::ERR_LOGIN_INVALID)]
+                } else {
+                    let existing = state.users.values().find(|user| user.name == login.name).map(|user| user.id);
+                    let id = existing.unwrap_or_else(|| state.add_user(&login.name));
+                    user = Some(id);
+                    state.clients.push(tx.clone());
+
+                    let mut reply = vec![Packet::LoginSuccess(common::LoginSuccess {
+                        created: existing.is_none(),
+                        id: id,
+                        token: String::from(TOKEN)
+                    })];
+                    reply.extend(state.users.values().cloned().map(|user| {
+                        Packet::UserReceive(common::UserReceive { inner: user })
+                    }));
+                    reply.extend(state.channels.values().cloned().map(|channel| {
+                        Packet::ChannelReceive(common::ChannelReceive { inner: channel })
+                    }));
+                    reply
+                }
+            },
+            (_, None) => vec![Packet::Err(common::ERR_MISSING_PERMISSION)],
+            (Packet::MessageList(list), Some(_)) => {
+                let mut reply: Vec<_> = state.messages.iter()
+                    .filter(|msg| msg.channel == list.channel)
+                    .cloned()
+                    .map(|msg| Packet::MessageReceive(common::MessageReceive { inner: msg, new: false }))
+                    .collect();
+                reply.push(Packet::MessageListReceived);
+                reply
+            },
+            (Packet::MessageCreate(create), Some(author)) => {
+                let timestamp = state.messages.iter().map(|msg| msg.timestamp).max().unwrap_or(0) + 1;
+                state.add_message(create.channel, author, timestamp, &create.text);
+                let msg = state.messages.last().cloned().unwrap();
+                state.broadcast(&Packet::MessageReceive(common::MessageReceive { inner: msg, new: true }));
+                Vec::new()
+            },
+            (Packet::MessageDelete(delete), Some(_)) => {
+                state.messages.retain(|msg| msg.id != delete.id);
+                state.broadcast(&Packet::MessageDeleteReceive(common::MessageDeleteReceive { id: delete.id }));
+                Vec::new()
+            },
+            (Packet::Typing(typing), Some(author)) => {
+                state.broadcast(&Packet::TypingReceive(common::TypingReceive {
+                    author: author,
+                    channel: typing.channel
+                }));
+                Vec::new()
+            },
+            // Anything else isn't needed by the tests
+            (_, Some(_)) => Vec::new()
+        };
+        drop(state);
+
+        for packet in reply {
+            if common::write(&mut stream, &packet).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Start a local SOCKS5 or HTTP CONNECT proxy that relays to whatever it's asked for.
+/// Returns its address and every target it connected to, as requested.
+pub fn start_proxy(kind: Kind, auth: Option<(&'static str, &'static str)>) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let targets = Arc::new(Mutex::new(Vec::new()));
+
+    let targets_clone = Arc::clone(&targets);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue
+            };
+            let targets = Arc::clone(&targets_clone);
+            thread::spawn(move || {
+                let _ = proxy_client(stream, kind, auth, &targets);
+            });
+        }
+    });
+
+    (addr, targets)
+}
+fn proxy_client(mut client: TcpStream, kind: Kind, auth: Option<(&str, &str)>, targets: &Mutex<Vec<String>>) -> io::Result<()> {
+    let (host, port) = match kind {
+        Kind::Socks5 => {
+            let mut head = [0; 2];
+            client.read_exact(&mut head)?;
+            let mut methods = vec![0; head[1] as usize];
+            client.read_exact(&mut methods)?;
+
+            let method = if auth.is_some() { 2 } else { 0 };
+            if !methods.contains(&method) {
+                return client.write_all(&[5, 0xFF]);
+            }
+            client.write_all(&[5, method])?;
+
+            if let Some((user, password)) = auth {
+                client.read_exact(&mut head)?;
+                let mut given_user = vec![0; head[1] as usize];
+                client.read_exact(&mut given_user)?;
+                let mut len = [0];
+                client.read_exact(&mut len)?;
+                let mut given_password = vec![0; len[0] as usize];
+                client.read_exact(&mut given_password)?;
+
+                if given_user != user.as_bytes() || given_password != password.as_bytes() {
+                    return client.write_all(&[1, 1]);
+                }
+                client.write_all(&[1, 0])?;
+            }
+
+            let mut request = [0; 4];
+            client.read_exact(&mut request)?;
+            let host = match request[3] {
+                1 => {
+                    let mut ip = [0; 4];
+                    client.read_exact(&mut ip)?;
+                    Ipv4Addr::from(ip).to_string()
+                },
+                4 => {
+                    let mut ip = [0; 16];
+                    client.read_exact(&mut ip)?;
+                    Ipv6Addr::from(ip).to_string()
+                },
+                _ => {
+                    let mut len = [0];
+                    client.read_exact(&mut len)?;
+                    let mut host = vec![0; len[0] as usize];
+                    client.read_exact(&mut host)?;
+                    String::from_utf8_lossy(&host).into_owned()
+                }
+            };
+            let mut port = [0; 2];
+            client.read_exact(&mut port)?;
+            (host, (port[0] as u16) << 8 | port[1] as u16)
+        },
+        Kind::Http => {
+            let mut head = Vec::new();
+            let mut byte = [0];
+            while !head.ends_with(b"\r\n\r\n") {
+                client.read_exact(&mut byte)?;
+                head.push(byte[0]);
+            }
+            let head = String::from_utf8_lossy(&head).into_owned();
+            let target = head.split_whitespace().nth(1).unwrap_or_default();
+            match connections::split_host(target, 80) {
+                Some((host, port)) if head.starts_with("CONNECT ") => (host.to_string(), port),
+                _ => return client.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+            }
+        }
+    };
+
+    let target = TcpStream::connect((&*host, port));
+    match (kind, target.is_ok()) {
+        (Kind::Socks5, true) => client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?,
+        (Kind::Socks5, false) => client.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])?,
+        (Kind::Http, true) => client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?,
+        (Kind::Http, false) => client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n")?
+    }
+    let target = target?;
+    targets.lock().unwrap().push(format!("{}:{}", host, port));
+
+    let mut client_read = client.try_clone()?;
+    let mut target_write = target.try_clone()?;
+    thread::spawn(move || {
+        let _ = io::copy(&mut client_read, &mut target_write);
+        let _ = target_write.shutdown(Shutdown::Write);
+    });
+    let (mut target, mut client) = (target, client);
+    io::copy(&mut target, &mut client)?;
+    client.shutdown(Shutdown::Write)
+}
+
+mod tests {
+    use super::*;
+    use connections::{ConnectionError, Connections, ServerEntry, ServerId};
+    use proxy::{self, Proxy, ProxyError};
+    use failure::Error;
+    use rusqlite::Connection as SqlConnection;
+    use std::rc::Rc;
+    use std::time::Instant;
+    use vault::Vault;
+
+    /// The server's row id in `database`, and the id used for sessions
+    const ID: ServerId = 1;
+
+    fn database(server: &MockServer) -> SqlConnection {
+        let mut db = SqlConnection::open_in_memory().unwrap();
+        ::migrations::migrate(&mut db, None).unwrap();
+        db.execute(
+            "INSERT INTO servers (ip, name, hash) VALUES (?, 'mock', ?)",
+            &[&server.addr.to_string(), &server.hash]
+        ).unwrap();
+        db
+    }
+    fn connections(nick: &str) -> Arc<Connections> {
+        let mut db = SqlConnection::open_in_memory().unwrap();
+        ::migrations::migrate(&mut db, None).unwrap();
+        Connections::new(&db, nick.to_string(), None, Vault::default())
+    }
+    fn entry(server: &MockServer, token: Option<&str>) -> ServerEntry {
+        ServerEntry {
+            id: ID,
+            host: server.addr.to_string(),
+            hash: server.hash.clone(),
+            token: token.map(String::from),
+            ..ServerEntry::default()
+        }
+    }
+    fn login(server: &MockServer, connections: &Connections) -> Result<(), Error> {
+        let synac = connections.connect(entry(server, Some(TOKEN)), || None)?;
+        connections.insert(ID, synac);
+        Ok(())
+    }
+    /// Read packets until `done` returns true, failing after a few seconds
+    fn read_until<F>(connections: &Connections, mut done: F)
+        where F: FnMut(&Packet) -> bool
+    {
+        let start = Instant::now();
+        let mut finished = false;
+        while !finished {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out waiting for packet");
+            connections.try_read(|_, packet, _| if done(&packet) { finished = true; }).unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn connect() {
+        let server = MockServer::start();
+        let db = Rc::new(database(&server));
+        let connections = connections("alice");
+
+        // Password login saves the token
+        let db_clone = Rc::clone(&db);
+        let synac = connections.connect(entry(&server, None), || {
+            Some((String::from(PASSWORD), db_clone))
+        }).unwrap();
+        assert_eq!(server.state.lock().unwrap().users[&synac.user].name, "alice");
+
+        let token: Option<String> = db.query_row("SELECT token FROM servers", &[], |row| row.get(0)).unwrap();
+        assert_eq!(token.as_ref().map(|s| &**s), Some(TOKEN));
+
+        // Token login, which the packet inspector sees
+        connections.capture.lock().unwrap().start();
+        assert!(connections.connect(ServerEntry { token: token, ..entry(&server, None) }, || None).is_ok());
+        {
+            let mut capture = connections.capture.lock().unwrap();
+            let kinds: Vec<_> = capture.entries.iter().map(|entry| entry.kind()).collect();
+            assert_eq!(kinds, ["Login", "LoginSuccess"]);
+            capture.stop();
+        }
+
+        // Host names are resolved on connect. localhost may resolve to ::1 first,
+        // which the mock doesn't listen on, so this also covers falling back.
+        let host = format!("localhost:{}", server.addr.port());
+        assert!(connections.connect(ServerEntry { host: host, ..entry(&server, Some(TOKEN)) }, || None).is_ok());
+
+        // A per-server nick overrides the default
+        let carol = ServerEntry { nick: Some(String::from("carol")), ..entry(&server, Some(TOKEN)) };
+        let synac = connections.connect(carol, || None).unwrap();
+        assert_eq!(server.state.lock().unwrap().users[&synac.user].name, "carol");
+
+        // Bad credentials
+        let err = connections.connect(entry(&server, Some("bad")), || None).unwrap_err();
+        match err.downcast::<ConnectionError>() {
+            Ok(ConnectionError::InvalidToken) => (),
+            other => panic!("expected invalid token, got {:?}", other)
+        }
+        let db_clone = Rc::clone(&db);
+        let err = connections.connect(entry(&server, None), || {
+            Some((String::from("wrong"), db_clone))
+        }).unwrap_err();
+        match err.downcast::<ConnectionError>() {
+            Ok(ConnectionError::InvalidPassword) => (),
+            other => panic!("expected invalid password, got {:?}", other)
+        }
+
+        // Wrong certificate
+        let wrong = ServerEntry { hash: String::from("00"), ..entry(&server, Some(TOKEN)) };
+        assert!(connections.connect(wrong, || None).is_err());
+    }
+
+    #[test]
+    fn accounts() {
+        let server = MockServer::start();
+        let connections = connections("alice");
+
+        // The same server, joined twice as different users
+        let main = connections.connect(ServerEntry { id: 1, ..entry(&server, Some(TOKEN)) }, || None).unwrap();
+        let bot = connections.connect(ServerEntry {
+            id: 2,
+            nick: Some(String::from("alice-bot")),
+            ..entry(&server, Some(TOKEN))
+        }, || None).unwrap();
+        assert_ne!(main.user, bot.user);
+        connections.insert(1, main);
+        connections.insert(2, bot);
+
+        let mut sessions = Vec::new();
+        connections.foreach(|synac| sessions.push(synac.id));
+        sessions.sort();
+        assert_eq!(sessions, &[1, 2]);
+    }
+
+    #[test]
+    fn messages() {
+        let server = MockServer::start();
+        let channel = {
+            let mut state = server.state.lock().unwrap();
+            let channel = state.add_channel("general");
+            let bob = state.add_user("bob");
+            // Deliberately out of order, with a timestamp collision
+            state.add_message(channel, bob, 30, b"third");
+            state.add_message(channel, bob, 10, b"first");
+            state.add_message(channel, bob, 20, b"second (a)");
+            state.add_message(channel, bob, 20, b"second (b)");
+            channel
+        };
+
+        let connections = connections("alice");
+        login(&server, &connections).unwrap();
+
+        // Login sends the state
+        read_until(&connections, |packet| match *packet {
+            Packet::ChannelReceive(ref event) => event.inner.id == channel,
+            _ => false
+        });
+
+        connections.execute(ID, |result| {
+            result.unwrap().write(&Packet::MessageList(common::MessageList {
+                after: None,
+                before: None,
+                channel: channel,
+                limit: common::LIMIT_BULK
+            })).unwrap();
+        });
+        read_until(&connections, |packet| match *packet {
+            Packet::MessageListReceived => true,
+            _ => false
+        });
+
+        let texts = |connections: &Connections| {
+            let mut texts = Vec::new();
+            connections.execute(ID, |result| {
+                texts = result.unwrap().messages.get(channel).iter()
+                    .map(|msg| String::from_utf8_lossy(&msg.text).into_owned())
+                    .collect();
+            });
+            texts
+        };
+        assert_eq!(texts(&connections), &["first", "second (a)", "second (b)", "third"]);
+
+        // Receiving the same message again replaces it rather than duplicating it
+        connections.execute(ID, |result| {
+            result.unwrap().write(&Packet::MessageList(common::MessageList {
+                after: None,
+                before: None,
+                channel: channel,
+                limit: common::LIMIT_BULK
+            })).unwrap();
+        });
+        read_until(&connections, |packet| match *packet {
+            Packet::MessageListReceived => true,
+            _ => false
+        });
+        assert_eq!(texts(&connections).len(), 4);
+
+        // Sending and deleting
+        connections.execute(ID, |result| {
+            result.unwrap().write(&Packet::MessageCreate(common::MessageCreate {
+                channel: channel,
+                text: b"fourth".to_vec()
+            })).unwrap();
+        });
+        let mut id = None;
+        read_until(&connections, |packet| match *packet {
+            Packet::MessageReceive(ref event) if event.new => { id = Some(event.inner.id); true },
+            _ => false
+        });
+        assert_eq!(texts(&connections).last().map(|s| &**s), Some("fourth"));
+
+        connections.execute(ID, |result| {
+            result.unwrap().write(&Packet::MessageDelete(common::MessageDelete {
+                id: id.unwrap()
+            })).unwrap();
+        });
+        read_until(&connections, |packet| match *packet {
+            Packet::MessageDeleteReceive(_) => true,
+            _ => false
+        });
+        assert_eq!(texts(&connections), &["first", "second (a)", "second (b)", "third"]);
+    }
+
+    #[test]
+    fn typing() {
+        let server = MockServer::start();
+        let channel = {
+            let mut state = server.state.lock().unwrap();
+            // Create bob up front, so alice receives him on login
+            state.add_user("bob");
+            state.add_channel("general")
+        };
+
+        let alice = connections("alice");
+        let bob = connections("bob");
+        login(&server, &alice).unwrap();
+        login(&server, &bob).unwrap();
+
+        // Channels are sent last, so after this alice knows about bob
+        read_until(&alice, |packet| match *packet {
+            Packet::ChannelReceive(_) => true,
+            _ => false
+        });
+
+        bob.execute(ID, |result| {
+            result.unwrap().write(&Packet::Typing(common::Typing { channel: channel })).unwrap();
+        });
+        read_until(&alice, |packet| match *packet {
+            Packet::TypingReceive(_) => true,
+            _ => false
+        });
+
+        // Typing::check only updates once a second
+        thread::sleep(Duration::from_millis(1100));
+
+        alice.execute(ID, |result| {
+            let synac = result.unwrap();
+            assert_eq!(synac.typing.check(Some(channel), &synac.state).unwrap(), "bob is typing");
+            assert_eq!(synac.typing.check(Some(channel), &synac.state), None);
+        });
+        alice.execute(ID, |result| {
+            let synac = result.unwrap();
+            thread::sleep(Duration::from_millis(1100));
+            assert_eq!(synac.typing.check(Some(channel + 1), &synac.state).unwrap(), "");
+        });
+    }
+
+    #[test]
+    fn vault() {
+        let server = MockServer::start();
+        let db = Rc::new(database(&server));
+        let connections = connections("alice");
+        connections.vault.lock().unwrap().enable(&db, "passphrase").unwrap();
+
+        // Tokens from password logins go into the vault
+        let db_clone = Rc::clone(&db);
+        connections.connect(entry(&server, None), || Some((String::from(PASSWORD), db_clone))).unwrap();
+        let saved = ServerEntry::load(&db, ID).unwrap();
+        assert_eq!(saved.token, None);
+        assert!(saved.token_sealed.is_some());
+        assert!(connections.connect(saved.clone(), || None).is_ok());
+
+        // Locked, the sealed token can't be used
+        connections.vault.lock().unwrap().lock();
+        match connections.connect(saved, || None).unwrap_err().downcast::<ConnectionError>() {
+            Ok(ConnectionError::InvalidToken) => (),
+            other => panic!("expected invalid token, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn proxy() {
+        let server = MockServer::start();
+        let connections = connections("alice");
+        // The host name should reach the proxy unresolved
+        let host = format!("localhost:{}", server.addr.port());
+
+        // A per-server SOCKS5 proxy with authentication
+        let (socks, socks_targets) = start_proxy(Kind::Socks5, Some(("user", "secret")));
+        let synac = connections.connect(ServerEntry {
+            host: host.clone(),
+            proxy: Some(format!("socks5://user:secret@{}", socks)),
+            ..entry(&server, Some(TOKEN))
+        }, || None).unwrap();
+        assert_eq!(synac.addr, socks);
+        assert_eq!(synac.peer, host);
+        assert_eq!(server.state.lock().unwrap().users[&synac.user].name, "alice");
+
+        let err = connections.connect(ServerEntry {
+            proxy: Some(format!("socks5://user:wrong@{}", socks)),
+            ..entry(&server, Some(TOKEN))
+        }, || None).unwrap_err();
+        match err.downcast::<ProxyError>() {
+            Ok(ProxyError::Auth) => (),
+            other => panic!("expected rejected credentials, got {:?}", other)
+        }
+
+        // The global proxy, over HTTP CONNECT
+        let (http, http_targets) = start_proxy(Kind::Http, None);
+        *connections.proxy.write().unwrap() = Proxy::parse(&format!("http://{}", http)).unwrap();
+        let synac = connections.connect(ServerEntry { host: host.clone(), ..entry(&server, Some(TOKEN)) }, || None).unwrap();
+        assert_eq!(synac.addr, http);
+        assert_eq!(synac.peer, host);
+
+        // Unless the server bypasses it
+        let synac = connections.connect(ServerEntry {
+            proxy: Some(String::from(proxy::DIRECT)),
+            ..entry(&server, Some(TOKEN))
+        }, || None).unwrap();
+        assert_eq!(synac.addr, server.addr);
+        assert_eq!(synac.peer, server.addr.to_string());
+
+        assert_eq!(*socks_targets.lock().unwrap(), vec![host.clone()]);
+        assert_eq!(*http_targets.lock().unwrap(), vec![host]);
+    }
+}
@@ -0,0 +1,292 @@
+//! Application state that isn't tied to any widget.
+//! Packets and user intents go in, state changes and render commands come out,
+//! so the behavior can be tested without GTK.
+
+use connections::Synac;
+use messages::Messages;
+use synac::State;
+use synac::common::{self, Packet};
+
+/// Something the views should do
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Render {
+    Channels,
+    Messages,
+    Users,
+    Typing,
+    ScrollToBottom,
+    /// Open the edit box with this text
+    Edit(String),
+    CloseEdit,
+    /// A new message from someone else, which may deserve a notification
+    Notify {
+        channel: usize,
+        author: usize,
+        summary: String,
+        text: Vec<u8>
+    }
+}
+
+/// Something the user wants to do
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Intent {
+    /// Edit a message, or the user's last message in the current channel
+    Edit(Option<usize>),
+    SubmitEdit(String),
+    CancelEdit
+}
+
+/// The result of an update: what to redraw and what to send
+#[derive(Debug, Default)]
+pub struct Update {
+    pub render: Vec<Render>,
+    pub send: Vec<Packet>
+}
+impl Update {
+    pub fn render(render: Render) -> Self {
+        Update {
+            render: vec![render],
+            send: Vec::new()
+        }
+    }
+}
+
+/// A read-only view of one server's state
+pub struct Server<'a> {
+    pub state: &'a State,
+    pub messages: &'a Messages,
+    pub user: usize,
+    pub current_channel: Option<usize>
+}
+impl<'a> Server<'a> {
+    pub fn new(synac: &'a Synac) -> Self {
+        Server {
+            state: &synac.state,
+            messages: &synac.messages,
+            user: synac.user,
+            current_channel: synac.current_channel
+        }
+    }
+}
+
+/// The notification a packet may deserve.
+/// Unlike `Model::packet`, this is for every server, shown or not.
+pub fn notification(server: &Server, packet: &Packet) -> Option<Render> {
+    let msg = match *packet {
+        Packet::MessageReceive(ref event) if event.new => &event.inner,
+        _ => return None
+    };
+    if msg.author == server.user {
+        return None;
+    }
+    let channel = server.state.channels.get(&msg.channel)?;
+    let author = server.state.users.get(&msg.author)?;
+    Some(Render::Notify {
+        channel: channel.id,
+        author: author.id,
+        summary: format!("{} (#{})", author.name, channel.name),
+        text: msg.text.clone()
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct Model {
+    /// The message being edited, if any
+    pub editing: Option<usize>
+}
+impl Model {
+    pub fn new() -> Self {
+        Model::default()
+    }
+    /// Handle a packet from the currently shown server. Notifications are separate, see `notification`.
+    /// `channel` is the channel the packet affected, as given by `Connections::try_read`.
+    pub fn packet(&mut self, server: &Server, packet: &Packet, channel: Option<usize>) -> Update {
+        let current = channel.is_some() && channel == server.current_channel;
+        let mut update = Update::default();
+
+        match *packet {
+            Packet::ChannelDeleteReceive(_) |
+            Packet::ChannelReceive(_) => update.render.push(Render::Channels),
+            Packet::MessageDeleteReceive(ref event) => {
+                if self.editing == Some(event.id) {
+                    self.editing = None;
+                    update.render.push(Render::CloseEdit);
+                }
+                if current {
+                    update.render.push(Render::Messages);
+                }
+            },
+            Packet::MessageListReceived => {
+                update.render.push(Render::Messages);
+                update.render.push(Render::ScrollToBottom);
+            },
+            Packet::MessageReceive(ref event) => if event.new && current {
+                update.render.push(Render::Messages);
+            },
+            Packet::TypingReceive(_) => if current {
+                update.render.push(Render::Typing);
+            },
+            Packet::UserReceive(_) => update.render.push(Render::Users),
+            _ => ()
+        }
+
+        update
+    }
+    pub fn intent(&mut self, server: &Server, intent: Intent) -> Update {
+        match intent {
+            Intent::Edit(id) => {
+                let channel = match server.current_channel {
+                    Some(channel) => channel,
+                    None => return Update::default()
+                };
+                let messages = server.messages.get(channel);
+                let msg = match id {
+                    Some(id) => messages.iter().find(|msg| msg.id == id),
+                    None => messages.iter().rev().find(|msg| msg.author == server.user)
+                };
+                match msg {
+                    Some(msg) => {
+                        self.editing = Some(msg.id);
+                        Update::render(Render::Edit(String::from_utf8_lossy(&msg.text).into_owned()))
+                    },
+                    None => Update::default()
+                }
+            },
+            Intent::SubmitEdit(text) => {
+                if text.is_empty() {
+                    return Update::default();
+                }
+                match self.editing.take() {
+                    Some(id) => Update {
+                        render: vec![Render::CloseEdit],
+                        send: vec![Packet::MessageUpdate(common::MessageUpdate {
+                            id: id,
+                            text: text.into_bytes()
+                        })]
+                    },
+                    None => Update::render(Render::CloseEdit)
+                }
+            },
+            Intent::CancelEdit => {
+                self.editing = None;
+                Update::render(Render::CloseEdit)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::collections::HashMap;
+
+    let mut state = State::new();
+    let mut messages = Messages::new();
+    let mut model = Model::new();
+
+    let channel = common::Channel {
+        default_mode_bot: 0,
+        default_mode_user: common::PERM_READ | common::PERM_WRITE,
+        id: 1,
+        name: String::from("general"),
+        private: false
+    };
+    let user = |id, name: &str| common::User {
+        admin: false,
+        ban: false,
+        bot: false,
+        id: id,
+        modes: HashMap::new(),
+        name: name.to_string()
+    };
+    let message = |id, author| common::Message {
+        author: author,
+        channel: 1,
+        id: id,
+        text: format!("message {}", id).into_bytes(),
+        timestamp: id as i64,
+        timestamp_edit: None
+    };
+    for packet in &[
+        Packet::ChannelReceive(common::ChannelReceive { inner: channel.clone() }),
+        Packet::UserReceive(common::UserReceive { inner: user(1, "me") }),
+        Packet::UserReceive(common::UserReceive { inner: user(2, "them") })
+    ] {
+        state.update(packet);
+    }
+    messages.add(message(10, 1));
+    messages.add(message(11, 2));
+
+    {
+        let server = Server {
+            state: &state,
+            messages: &messages,
+            user: 1,
+            current_channel: Some(1)
+        };
+
+        // Every affected view gets a command, not just the first one
+        let mut render = model.packet(&server, &Packet::ChannelReceive(common::ChannelReceive {
+            inner: channel.clone()
+        }), None).render;
+        let received = Packet::MessageReceive(common::MessageReceive {
+            inner: message(12, 2),
+            new: true
+        });
+        render.extend(model.packet(&server, &received, Some(1)).render);
+        assert_eq!(render, vec![Render::Channels, Render::Messages]);
+
+        // Notifications don't depend on what's shown
+        let background = Server {
+            state: &state,
+            messages: &messages,
+            user: 1,
+            current_channel: None
+        };
+        let notify = Some(Render::Notify {
+            channel: 1,
+            author: 2,
+            summary: String::from("them (#general)"),
+            text: b"message 12".to_vec()
+        });
+        assert_eq!(notification(&server, &received), notify);
+        assert_eq!(notification(&background, &received), notify);
+
+        // Old messages and own messages don't notify, other channels don't redraw
+        let own = Packet::MessageReceive(common::MessageReceive {
+            inner: message(13, 1),
+            new: true
+        });
+        assert_eq!(model.packet(&server, &own, Some(1)).render, vec![Render::Messages]);
+        assert_eq!(notification(&server, &own), None);
+        let old = Packet::MessageReceive(common::MessageReceive {
+            inner: message(14, 2),
+            new: false
+        });
+        assert!(model.packet(&server, &old, Some(1)).render.is_empty());
+        assert_eq!(notification(&server, &old), None);
+        assert!(model.packet(&server, &Packet::MessageDeleteReceive(common::MessageDeleteReceive {
+            id: 15
+        }), Some(2)).render.is_empty());
+        assert!(model.packet(&server, &Packet::TypingReceive(common::TypingReceive {
+            author: 2,
+            channel: 2
+        }), Some(2)).render.is_empty());
+
+        // Editing the last own message
+        assert_eq!(model.intent(&server, Intent::Edit(None)).render, vec![Render::Edit(String::from("message 10"))]);
+        assert_eq!(model.editing, Some(10));
+
+        let update = model.intent(&server, Intent::SubmitEdit(String::from("edited")));
+        assert_eq!(update.render, vec![Render::CloseEdit]);
+        assert_eq!(update.send.len(), 1);
+        assert_eq!(model.editing, None);
+
+        // Deleting the message being edited closes the edit box
+        model.intent(&server, Intent::Edit(Some(11)));
+        assert_eq!(model.packet(&server, &Packet::MessageDeleteReceive(common::MessageDeleteReceive {
+            id: 11
+        }), Some(1)).render, vec![Render::CloseEdit, Render::Messages]);
+        assert_eq!(model.editing, None);
+    }
+}
@@ -0,0 +1,146 @@
+//! The local moderation log, and helpers for bulk moderation.
+
+use failure::Error;
+use rusqlite::Connection as SqlConnection;
+use synac::common::Message;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Ban,
+    Unban,
+    Promote,
+    Demote,
+    DeleteMessage
+}
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Action::Ban => "ban",
+            Action::Unban => "unban",
+            Action::Promote => "promote admin",
+            Action::Demote => "demote admin",
+            Action::DeleteMessage => "delete message"
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub timestamp: i64,
+    pub server: String,
+    pub action: String,
+    pub target: String,
+    pub detail: String
+}
+
+pub fn record(db: &SqlConnection, timestamp: i64, server: &str, action: Action, target: &str, detail: &str) -> Result<(), Error> {
+    db.execute(
+        "INSERT INTO modlog (timestamp, server, action, target, detail) VALUES (?, ?, ?, ?, ?)",
+        &[&timestamp, &server, &action.as_str(), &target, &detail]
+    )?;
+    Ok(())
+}
+/// The log, newest first, optionally only for one server
+pub fn list(db: &SqlConnection, server: Option<&str>) -> Result<Vec<Entry>, Error> {
+    let mut stmt = db.prepare(
+        "SELECT timestamp, server, action, target, detail FROM modlog
+         WHERE ?1 IS NULL OR server = ?1 ORDER BY timestamp DESC, id DESC"
+    )?;
+    let rows = stmt.query_map(&[&server], |row| Entry {
+        timestamp: row.get(0),
+        server: row.get(1),
+        action: row.get(2),
+        target: row.get(3),
+        detail: row.get(4)
+    })?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        entries.push(row?);
+    }
+    Ok(entries)
+}
+
+fn csv_field(output: &mut String, field: &str) {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        output.push('"');
+        output.push_str(&field.replace('"', "\"\""));
+        output.push('"');
+    } else {
+        output.push_str(field);
+    }
+}
+/// Format the log as CSV, with a header row
+pub fn to_csv(entries: &[Entry]) -> String {
+    let mut output = String::from("timestamp,server,action,target,detail\r\n");
+    for entry in entries {
+        output.push_str(&entry.timestamp.to_string());
+        for field in &[&entry.server, &entry.action, &entry.target, &entry.detail] {
+            output.push(',');
+            csv_field(&mut output, field);
+        }
+        output.push_str("\r\n");
+    }
+    output
+}
+
+/// The ids of the last `n` messages by `author`, out of the messages loaded
+pub fn last_messages_by(messages: &[Message], author: usize, n: usize) -> Vec<usize> {
+    let mut ids: Vec<_> = messages.iter()
+        .rev()
+        .filter(|msg| msg.author == author)
+        .take(n)
+        .map(|msg| msg.id)
+        .collect();
+    ids.reverse();
+    ids
+}
+/// A short excerpt of a message, for the log
+pub fn excerpt(text: &[u8]) -> String {
+    const MAX: usize = 100;
+
+    let text = String::from_utf8_lossy(text);
+    let mut chars = text.chars();
+    let mut excerpt: String = chars.by_ref().take(MAX).collect();
+    if chars.next().is_some() {
+        excerpt.push('…');
+    }
+    excerpt
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut db = SqlConnection::open_in_memory().unwrap();
+    ::migrations::migrate(&mut db, None).unwrap();
+
+    record(&db, 10, "a", Action::Ban, "mallory", "").unwrap();
+    record(&db, 20, "b", Action::DeleteMessage, "mallory", "spam, \"more spam\"").unwrap();
+    record(&db, 30, "a", Action::Unban, "mallory", "").unwrap();
+
+    let all = list(&db, None).unwrap();
+    assert_eq!(all.iter().map(|entry| entry.timestamp).collect::<Vec<_>>(), vec![30, 20, 10]);
+    let a = list(&db, Some("a")).unwrap();
+    assert_eq!(a.len(), 2);
+    assert_eq!(a[1].action, "ban");
+
+    assert_eq!(
+        to_csv(&all[1..2]),
+        "timestamp,server,action,target,detail\r\n20,b,delete message,mallory,\"spam, \"\"more spam\"\"\"\r\n"
+    );
+
+    let message = |id, author| Message {
+        author: author,
+        channel: 1,
+        id: id,
+        text: Vec::new(),
+        timestamp: id as i64,
+        timestamp_edit: None
+    };
+    let messages = [message(1, 1), message(2, 2), message(3, 1), message(4, 1), message(5, 2)];
+    assert_eq!(last_messages_by(&messages, 1, 2), vec![3, 4]);
+    assert_eq!(last_messages_by(&messages, 2, 10), vec![2, 5]);
+
+    assert_eq!(excerpt(b"short"), "short");
+    assert_eq!(excerpt("ä".repeat(150).as_bytes()).chars().count(), 101);
+}
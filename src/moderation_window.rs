@@ -0,0 +1,321 @@
+use ::*;
+use gtk::{ComboBoxText, FileChooserAction, FileChooserDialog, Grid, Notebook, SpinButton};
+use std::fs;
+
+struct Panel {
+    app: Rc<App>,
+    id: ServerId,
+    window: Window,
+
+    bans: GtkBox,
+    bans_search: Entry,
+    log: Grid,
+    log_entries: RefCell<Vec<moderation::Entry>>
+}
+
+fn fill_bans(panel: &Rc<Panel>) {
+    for child in panel.bans.get_children() {
+        panel.bans.remove(&child);
+    }
+
+    let search = panel.bans_search.get_text().unwrap_or_default().to_lowercase();
+    let mut banned = Vec::new();
+    panel.app.connections.execute(panel.id, |result| if let Ok(synac) = result {
+        banned = synac.state.users.values()
+            .filter(|user| user.ban && user.name.to_lowercase().contains(&search))
+            .map(|user| (user.id, user.name.clone()))
+            .collect();
+    });
+    banned.sort_by(|a, b| a.1.cmp(&b.1));
+
+    if banned.is_empty() {
+        add_text(&panel.bans, if search.is_empty() { "Nobody is banned." } else { "No banned user matches." });
+    }
+    for (user_id, name) in banned {
+        let row = GtkBox::new(Orientation::Horizontal, 10);
+
+        let label = Label::new(&*name);
+        label.set_xalign(0.0);
+        label.set_hexpand(true);
+        row.add(&label);
+
+        let unban = Button::new_with_label("Unban");
+        let panel_clone = Rc::clone(panel);
+        unban.connect_clicked(move |_| {
+            let panel_clone2 = Rc::clone(&panel_clone);
+            let name = name.clone();
+            confirm(&panel_clone.window, &format!("Unban {}?", name), move || {
+                let panel_clone = &panel_clone2;
+                let mut sent = false;
+                panel_clone.app.connections.execute(panel_clone.id, |result| match result {
+                    Ok(synac) => match synac.write(&Packet::UserUpdate(common::UserUpdate {
+                        admin: None,
+                        ban: Some(false),
+                        channel_mode: None,
+                        id: user_id
+                    })) {
+                        Ok(()) => sent = true,
+                        Err(err) => alert(&panel_clone.window, MessageType::Error, &format!("failed to send: {}", err))
+                    },
+                    Err(_) => alert(&panel_clone.window, MessageType::Error, "Not connected to this server")
+                });
+                if sent {
+                    log_moderation(&panel_clone.app, panel_clone.id, Action::Unban, &name, "");
+                    fill_log(panel_clone);
+                }
+            });
+        });
+        row.add(&unban);
+
+        panel.bans.add(&row);
+    }
+    panel.bans.show_all();
+}
+
+fn fill_log(panel: &Rc<Panel>) {
+    for child in panel.log.get_children() {
+        panel.log.remove(&child);
+    }
+
+    let entries = match moderation::list(&panel.app.db, None) {
+        Ok(entries) => entries,
+        Err(err) => {
+            alert(&panel.window, MessageType::Error, &format!("failed to read the moderation log: {}", err));
+            Vec::new()
+        }
+    };
+
+    for (column, heading) in ["Time", "Server", "Action", "Target", "Details"].iter().enumerate() {
+        let heading = Label::new(*heading);
+        add_class(&heading, "bold");
+        heading.set_xalign(0.0);
+        panel.log.attach(&heading, column as i32, 0, 1, 1);
+    }
+    for (row, entry) in entries.iter().enumerate() {
+        let row = row as i32 + 1;
+
+        let mut time = String::new();
+        messages::format_timestamp(&mut time, entry.timestamp);
+
+        for (column, text) in [&time, &entry.server, &entry.action, &entry.target, &entry.detail].iter().enumerate() {
+            let label = Label::new(&***text);
+            label.set_xalign(0.0);
+            if column == 4 {
+                label.set_hexpand(true);
+                label.set_line_wrap(true);
+            }
+            panel.log.attach(&label, column as i32, row, 1, 1);
+        }
+    }
+
+    *panel.log_entries.borrow_mut() = entries;
+    panel.log.show_all();
+}
+
+/// Build the clean up page, for the current channel
+fn cleanup_page(panel: &Rc<Panel>, user: Option<usize>) -> GtkBox {
+    let container = GtkBox::new(Orientation::Vertical, 6);
+    container.set_property_margin(10);
+
+    let mut channel = None;
+    let mut users = Vec::new();
+    panel.app.connections.execute(panel.id, |result| if let Ok(synac) = result {
+        let channel_id = match synac.current_channel {
+            Some(channel_id) => channel_id,
+            None => return
+        };
+        if let Some(me) = synac.state.users.get(&synac.user) {
+            if let Some(inner) = synac.state.channels.get(&channel_id) {
+                if synac::get_mode(inner, me) & common::PERM_MANAGE_MESSAGES == common::PERM_MANAGE_MESSAGES {
+                    channel = Some((channel_id, inner.name.clone()));
+                }
+            }
+        }
+        users = synac.state.users.values()
+            .filter(|user| user.id != synac.user)
+            .map(|user| (user.id, user.name.clone()))
+            .collect();
+    });
+    users.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let (channel, channel_name) = match channel {
+        Some(channel) => channel,
+        None => {
+            add_text(&container, "Open a channel where you can manage messages first.");
+            return container;
+        }
+    };
+
+    add_text(&container, &format!(
+        "Delete a user's last messages in #{}. Only messages already loaded count, \
+         so scroll up first to reach older ones.",
+        channel_name
+    ));
+
+    let row = GtkBox::new(Orientation::Horizontal, 6);
+
+    let picker = ComboBoxText::new();
+    for &(_, ref name) in &users {
+        picker.append_text(name);
+    }
+    let selected = user.and_then(|user| users.iter().position(|&(id, _)| id == user));
+    picker.set_active(selected.unwrap_or(0) as i32);
+    picker.set_hexpand(true);
+    row.add(&picker);
+
+    row.add(&Label::new("last"));
+    let count = SpinButton::new_with_range(1.0, common::LIMIT_BULK as f64, 1.0);
+    count.set_value(10.0);
+    row.add(&count);
+    row.add(&Label::new("messages"));
+    container.add(&row);
+
+    let delete = Button::new_with_mnemonic("_Delete messages");
+    delete.set_halign(Align::End);
+    let panel_clone = Rc::clone(panel);
+    delete.connect_clicked(move |_| {
+        let (user_id, name) = match users.get(picker.get_active() as usize) {
+            Some(&(user_id, ref name)) => (user_id, name),
+            None => return
+        };
+        let mut ids = Vec::new();
+        panel_clone.app.connections.execute(panel_clone.id, |result| if let Ok(synac) = result {
+            ids = moderation::last_messages_by(synac.messages.get(channel), user_id, count.get_value_as_int() as usize);
+        });
+        if ids.is_empty() {
+            alert(&panel_clone.window, MessageType::Info, &format!("No loaded messages by {} in #{}", name, channel_name));
+            return;
+        }
+
+        let panel_clone2 = Rc::clone(&panel_clone);
+        let text = format!("Delete {} message(s) by {} in #{}?", ids.len(), name, channel_name);
+        confirm(&panel_clone.window, &text, move || {
+            let panel_clone = &panel_clone2;
+            if let Some(err) = delete_messages(&panel_clone.app, panel_clone.id, channel, &ids) {
+                alert(&panel_clone.window, MessageType::Error, &format!("failed to delete messages: {}", err));
+            }
+            fill_log(panel_clone);
+        });
+    });
+    container.add(&delete);
+
+    container
+}
+
+/// Open the moderation panel. With a user, it starts at cleaning up their messages.
+pub(crate) fn show(app: &Rc<App>, id: ServerId, user: Option<usize>) {
+    let mut admin = false;
+    app.connections.execute(id, |result| if let Ok(synac) = result {
+        admin = synac.state.users.get(&synac.user).map(|user| user.admin).unwrap_or(false);
+    });
+
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Moderation");
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(650, 450);
+
+    let panel = Rc::new(Panel {
+        app: Rc::clone(app),
+        id: id,
+        window: window.clone(),
+
+        bans: GtkBox::new(Orientation::Vertical, 2),
+        bans_search: Entry::new(),
+        log: Grid::new(),
+        log_entries: RefCell::new(Vec::new())
+    });
+
+    let notebook = Notebook::new();
+    notebook.set_vexpand(true);
+
+    if admin {
+        let container = GtkBox::new(Orientation::Vertical, 6);
+        container.set_property_margin(10);
+
+        panel.bans_search.set_placeholder_text("Search banned users...");
+        let panel_clone = Rc::clone(&panel);
+        panel.bans_search.connect_changed(move |_| fill_bans(&panel_clone));
+        container.add(&panel.bans_search);
+
+        let scroll = ScrolledWindow::new(None, None);
+        scroll.set_vexpand(true);
+        scroll.add(&panel.bans);
+        container.add(&scroll);
+
+        let refresh = Button::new_with_mnemonic("_Refresh");
+        refresh.set_halign(Align::End);
+        let panel_clone = Rc::clone(&panel);
+        refresh.connect_clicked(move |_| fill_bans(&panel_clone));
+        container.add(&refresh);
+
+        fill_bans(&panel);
+        notebook.append_page(&container, Some(&Label::new("Bans")));
+    }
+
+    let cleanup = cleanup_page(&panel, user);
+    let cleanup_index = notebook.append_page(&cleanup, Some(&Label::new("Clean up")));
+
+    let container = GtkBox::new(Orientation::Vertical, 6);
+    container.set_property_margin(10);
+
+    add_text(&container, "Moderation done from this device, on every server. It's only stored locally.");
+
+    panel.log.set_row_spacing(2);
+    panel.log.set_column_spacing(10);
+    let scroll = ScrolledWindow::new(None, None);
+    scroll.set_vexpand(true);
+    scroll.add(&panel.log);
+    container.add(&scroll);
+
+    let controls = GtkBox::new(Orientation::Horizontal, 2);
+    controls.set_halign(Align::End);
+
+    let refresh = Button::new_with_mnemonic("Re_fresh");
+    let panel_clone = Rc::clone(&panel);
+    refresh.connect_clicked(move |_| fill_log(&panel_clone));
+    controls.add(&refresh);
+
+    let export = Button::new_with_mnemonic("_Export...");
+    let panel_clone = Rc::clone(&panel);
+    export.connect_clicked(move |_| {
+        let dialog = FileChooserDialog::new(Some("Export moderation log"), Some(&panel_clone.window), FileChooserAction::Save);
+        dialog.add_button("_Cancel", ResponseType::Cancel.into());
+        dialog.add_button("_Save", ResponseType::Accept.into());
+        dialog.set_do_overwrite_confirmation(true);
+        dialog.set_current_name("moderation.csv");
+
+        if dialog.run() == ResponseType::Accept.into() {
+            if let Some(path) = dialog.get_filename() {
+                let csv = moderation::to_csv(&panel_clone.log_entries.borrow());
+                if let Err(err) = fs::write(&path, csv) {
+                    alert(&panel_clone.window, MessageType::Error, &format!("failed to export the log: {}", err));
+                }
+            }
+        }
+        dialog.destroy();
+    });
+    controls.add(&export);
+    container.add(&controls);
+
+    fill_log(&panel);
+    notebook.append_page(&container, Some(&Label::new("Log")));
+
+    let outer = GtkBox::new(Orientation::Vertical, 6);
+    outer.add(&notebook);
+
+    let close = Button::new_with_mnemonic("_Close");
+    close.set_halign(Align::End);
+    close.set_property_margin(10);
+    close.set_margin_top(0);
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    outer.add(&close);
+
+    window.add(&outer);
+    window.show_all();
+
+    if user.is_some() {
+        notebook.set_current_page(Some(cleanup_index));
+    }
+}
@@ -0,0 +1,195 @@
+use ::*;
+use gtk::SpinButton;
+use notifications::{Schedule, DAYS, EVERY_DAY};
+
+struct Editor {
+    app: Rc<App>,
+    window: Window,
+    keywords: GtkBox,
+    schedules: GtkBox
+}
+
+/// A row with some text and a button to remove it
+fn add_removable<F>(container: &GtkBox, text: &str, remove: F)
+    where F: Fn() + 'static
+{
+    let row = GtkBox::new(Orientation::Horizontal, 10);
+
+    let label = Label::new(text);
+    label.set_xalign(0.0);
+    label.set_hexpand(true);
+    row.add(&label);
+
+    let button = Button::new_with_label("Remove");
+    button.connect_clicked(move |_| remove());
+    row.add(&button);
+
+    container.add(&row);
+}
+/// Hours and minutes, as two spin buttons
+fn time_picker(container: &GtkBox, label: &str, hour: u32) -> (SpinButton, SpinButton) {
+    container.add(&Label::new(label));
+    let hours = SpinButton::new_with_range(0.0, 23.0, 1.0);
+    hours.set_value(hour as f64);
+    container.add(&hours);
+    container.add(&Label::new(":"));
+    let minutes = SpinButton::new_with_range(0.0, 59.0, 5.0);
+    container.add(&minutes);
+    (hours, minutes)
+}
+
+fn fill(editor: &Rc<Editor>) {
+    for child in editor.keywords.get_children() {
+        editor.keywords.remove(&child);
+    }
+    for child in editor.schedules.get_children() {
+        editor.schedules.remove(&child);
+    }
+
+    // Every change ends up here, so this is also what keeps notifications going by the new rules
+    match Rules::load(&editor.app.db) {
+        Ok(rules) => *editor.app.rules.borrow_mut() = rules,
+        Err(err) => alert(&editor.window, MessageType::Error, &format!("failed to load notification rules: {}", err))
+    }
+    let rules = editor.app.rules.borrow().clone();
+
+    if rules.keywords.is_empty() {
+        add_text(&editor.keywords, "No keywords.");
+    }
+    for keyword in rules.keywords {
+        let editor_clone = Rc::clone(editor);
+        let text = keyword.clone();
+        add_removable(&editor.keywords, &text, move || {
+            if let Err(err) = notifications::remove_keyword(&editor_clone.app.db, &keyword) {
+                alert(&editor_clone.window, MessageType::Error, &format!("failed to remove keyword: {}", err));
+            }
+            fill(&editor_clone);
+        });
+    }
+
+    if rules.schedules.is_empty() {
+        add_text(&editor.schedules, "No schedules.");
+    }
+    for (id, schedule) in rules.schedules {
+        let editor_clone = Rc::clone(editor);
+        add_removable(&editor.schedules, &schedule.describe(), move || {
+            if let Err(err) = notifications::remove_schedule(&editor_clone.app.db, id) {
+                alert(&editor_clone.window, MessageType::Error, &format!("failed to remove schedule: {}", err));
+            }
+            fill(&editor_clone);
+        });
+    }
+
+    editor.keywords.show_all();
+    editor.schedules.show_all();
+}
+
+/// Edit the notification rules that apply everywhere: keywords and do not disturb schedules.
+/// Levels and mutes are in the menus of servers, channels and users.
+pub(crate) fn show(app: &Rc<App>) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Notification rules");
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(450, 0);
+
+    let container = GtkBox::new(Orientation::Vertical, 6);
+    container.set_property_margin(10);
+
+    add_text(&container, "Right click a server, channel or user to choose what it notifies about.");
+
+    let editor = Rc::new(Editor {
+        app: Rc::clone(app),
+        window: window.clone(),
+        keywords: GtkBox::new(Orientation::Vertical, 2),
+        schedules: GtkBox::new(Orientation::Vertical, 2)
+    });
+
+    add_heading(&container, "Keywords");
+    add_text(&container, "Where only mentions notify, these words do too.");
+    container.add(&editor.keywords);
+
+    let row = GtkBox::new(Orientation::Horizontal, 6);
+    let keyword = Entry::new();
+    keyword.set_placeholder_text("Keyword...");
+    keyword.set_hexpand(true);
+    row.add(&keyword);
+    let add = Button::new_with_mnemonic("_Add");
+    row.add(&add);
+    container.add(&row);
+
+    let editor_clone = Rc::clone(&editor);
+    let keyword_clone = keyword.clone();
+    let add_keyword = move || {
+        let text = keyword_clone.get_text().unwrap_or_default();
+        if text.trim().is_empty() {
+            return;
+        }
+        if let Err(err) = notifications::add_keyword(&editor_clone.app.db, &text) {
+            alert(&editor_clone.window, MessageType::Error, &format!("failed to add keyword: {}", err));
+        }
+        keyword_clone.set_text("");
+        fill(&editor_clone);
+    };
+    let add_keyword = Rc::new(add_keyword);
+    let add_keyword_clone = Rc::clone(&add_keyword);
+    add.connect_clicked(move |_| (*add_keyword_clone)());
+    keyword.connect_activate(move |_| (*add_keyword)());
+
+    add_heading(&container, "Do not disturb");
+    add_text(&container, "Nothing notifies during these times. A schedule ending before it starts goes on past midnight.");
+    container.add(&editor.schedules);
+
+    let days = GtkBox::new(Orientation::Horizontal, 2);
+    let checks: Vec<_> = DAYS.iter().enumerate().map(|(i, day)| {
+        let check = CheckButton::new_with_label(*day);
+        // Weeknights by default
+        check.set_active(i < 5);
+        days.add(&check);
+        check
+    }).collect();
+    container.add(&days);
+
+    let row = GtkBox::new(Orientation::Horizontal, 4);
+    let (start_hours, start_minutes) = time_picker(&row, "From", 22);
+    let (end_hours, end_minutes) = time_picker(&row, "to", 7);
+    container.add(&row);
+
+    let add = Button::new_with_mnemonic("Add _schedule");
+    add.set_halign(Align::End);
+    let editor_clone = Rc::clone(&editor);
+    add.connect_clicked(move |_| {
+        let mut days = 0;
+        for (i, check) in checks.iter().enumerate() {
+            if check.get_active() {
+                days |= 1 << i;
+            }
+        }
+        if days & EVERY_DAY == 0 {
+            alert(&editor_clone.window, MessageType::Info, "Pick at least one day");
+            return;
+        }
+        let schedule = Schedule {
+            days: days,
+            start: start_hours.get_value_as_int() as u32 * 60 + start_minutes.get_value_as_int() as u32,
+            end: end_hours.get_value_as_int() as u32 * 60 + end_minutes.get_value_as_int() as u32
+        };
+        if let Err(err) = notifications::add_schedule(&editor_clone.app.db, &schedule) {
+            alert(&editor_clone.window, MessageType::Error, &format!("failed to add schedule: {}", err));
+        }
+        fill(&editor_clone);
+    });
+    container.add(&add);
+
+    let close = Button::new_with_mnemonic("_Close");
+    close.set_halign(Align::End);
+    close.set_margin_top(10);
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    container.add(&close);
+
+    fill(&editor);
+
+    window.add(&container);
+    window.show_all();
+}
@@ -0,0 +1,325 @@
+//! Notification rules: which new messages deserve a desktop notification.
+//! Levels and user mutes are keyed by server address, like closed conversations.
+
+use chrono::prelude::*;
+use failure::Error;
+use rusqlite::Connection as SqlConnection;
+use std::collections::{HashMap, HashSet};
+
+/// Every day of the week, for schedules
+pub const EVERY_DAY: u8 = 0b111_1111;
+/// Short day names, Monday first like the bits of `Schedule::days`
+pub const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    All,
+    /// Only private channels, mentions of your name, and keywords
+    Mentions,
+    Nothing
+}
+impl Level {
+    pub fn parse(input: &str) -> Option<Self> {
+        match input {
+            "all" => Some(Level::All),
+            "mentions" => Some(Level::Mentions),
+            "nothing" => Some(Level::Nothing),
+            _ => None
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Level::All => "all",
+            Level::Mentions => "mentions",
+            Level::Nothing => "nothing"
+        }
+    }
+    pub fn describe(&self) -> &'static str {
+        match *self {
+            Level::All => "All messages",
+            Level::Mentions => "Mentions only",
+            Level::Nothing => "Nothing"
+        }
+    }
+}
+
+/// A do not disturb period. `days` is a bit mask, Monday first,
+/// and times are in minutes since local midnight.
+/// A schedule that ends before it starts goes on past midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    pub days: u8,
+    pub start: u32,
+    pub end: u32
+}
+impl Schedule {
+    fn on(&self, weekday: u32) -> bool {
+        self.days & (1 << (weekday % 7)) != 0
+    }
+    /// `weekday` counts from Monday
+    pub fn is_active(&self, weekday: u32, minute: u32) -> bool {
+        if self.start < self.end {
+            self.on(weekday) && minute >= self.start && minute < self.end
+        } else if self.start == self.end {
+            // All day
+            self.on(weekday)
+        } else {
+            // The evening of a day in the schedule, or the morning after one
+            (self.on(weekday) && minute >= self.start) || (self.on(weekday + 6) && minute < self.end)
+        }
+    }
+    /// Like "Mon, Tue 22:00 - 07:00"
+    pub fn describe(&self) -> String {
+        let days: Vec<_> = DAYS.iter().enumerate()
+            .filter(|&(i, _)| self.on(i as u32))
+            .map(|(_, day)| *day)
+            .collect();
+        let days = if self.days & EVERY_DAY == EVERY_DAY { String::from("Every day") } else { days.join(", ") };
+        format!(
+            "{} {:02}:{:02} - {:02}:{:02}",
+            days, self.start / 60, self.start % 60, self.end / 60, self.end % 60
+        )
+    }
+}
+
+/// The local day of the week, counting from Monday, and minutes since midnight
+pub fn local_time() -> (u32, u32) {
+    let now = Local::now();
+    (now.weekday().num_days_from_monday(), now.hour() * 60 + now.minute())
+}
+
+/// Whether `word` appears in `text` on its own, ignoring case
+fn contains_word(text: &str, word: &str) -> bool {
+    let text = text.to_lowercase();
+    let word = word.trim().to_lowercase();
+    if word.is_empty() {
+        return false;
+    }
+    text.match_indices(&*word).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + word.len()..].chars().next();
+        !before.map_or(false, char::is_alphanumeric) && !after.map_or(false, char::is_alphanumeric)
+    })
+}
+
+/// A new message from someone else
+#[derive(Clone, Copy, Debug)]
+pub struct Incoming<'a> {
+    pub server: &'a str,
+    pub channel: usize,
+    pub private: bool,
+    pub author: usize,
+    pub text: &'a str,
+    /// The name of whoever it's shown to, to find mentions
+    pub me: &'a str
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    servers: HashMap<String, Level>,
+    channels: HashMap<(String, usize), Level>,
+    muted_users: HashSet<(String, usize)>,
+    pub keywords: Vec<String>,
+    pub schedules: Vec<(i64, Schedule)>
+}
+impl Rules {
+    pub fn load(db: &SqlConnection) -> Result<Self, Error> {
+        let mut rules = Rules::default();
+
+        let mut stmt = db.prepare_cached("SELECT server, level FROM notify_servers")?;
+        for row in stmt.query_map(&[], |row| (row.get::<_, String>(0), row.get::<_, String>(1)))? {
+            let (server, level) = row?;
+            if let Some(level) = Level::parse(&level) {
+                rules.servers.insert(server, level);
+            }
+        }
+
+        let mut stmt = db.prepare_cached("SELECT server, channel, level FROM notify_channels")?;
+        for row in stmt.query_map(&[], |row| (row.get::<_, String>(0), row.get::<_, i64>(1), row.get::<_, String>(2)))? {
+            let (server, channel, level) = row?;
+            if let Some(level) = Level::parse(&level) {
+                rules.channels.insert((server, channel as usize), level);
+            }
+        }
+
+        let mut stmt = db.prepare_cached("SELECT server, user FROM notify_muted_users")?;
+        for row in stmt.query_map(&[], |row| (row.get::<_, String>(0), row.get::<_, i64>(1)))? {
+            let (server, user) = row?;
+            rules.muted_users.insert((server, user as usize));
+        }
+
+        let mut stmt = db.prepare_cached("SELECT keyword FROM notify_keywords ORDER BY keyword")?;
+        for row in stmt.query_map(&[], |row| row.get(0))? {
+            rules.keywords.push(row?);
+        }
+
+        let mut stmt = db.prepare_cached("SELECT id, days, start_minute, end_minute FROM notify_schedules ORDER BY id")?;
+        for row in stmt.query_map(&[], |row| (row.get::<_, i64>(0), Schedule {
+            days: row.get::<_, i64>(1) as u8,
+            start: row.get::<_, i64>(2) as u32,
+            end: row.get::<_, i64>(3) as u32
+        }))? {
+            rules.schedules.push(row?);
+        }
+
+        Ok(rules)
+    }
+    pub fn server_level(&self, server: &str) -> Option<Level> {
+        self.servers.get(server).cloned()
+    }
+    pub fn channel_level(&self, server: &str, channel: usize) -> Option<Level> {
+        self.channels.get(&(server.to_string(), channel)).cloned()
+    }
+    /// The level that applies: the channel's own, or the server's, or everything
+    pub fn level(&self, server: &str, channel: usize) -> Level {
+        self.channel_level(server, channel)
+            .or_else(|| self.server_level(server))
+            .unwrap_or(Level::All)
+    }
+    pub fn is_user_muted(&self, server: &str, user: usize) -> bool {
+        self.muted_users.contains(&(server.to_string(), user))
+    }
+    pub fn is_dnd(&self, weekday: u32, minute: u32) -> bool {
+        self.schedules.iter().any(|&(_, schedule)| schedule.is_active(weekday, minute))
+    }
+    /// Whether a message is worth a notification at this local time
+    pub fn should_notify(&self, msg: &Incoming, weekday: u32, minute: u32) -> bool {
+        if self.is_user_muted(msg.server, msg.author) || self.is_dnd(weekday, minute) {
+            return false;
+        }
+        match self.level(msg.server, msg.channel) {
+            Level::All => true,
+            Level::Mentions => {
+                msg.private
+                    || contains_word(msg.text, msg.me)
+                    || self.keywords.iter().any(|keyword| contains_word(msg.text, keyword))
+            },
+            Level::Nothing => false
+        }
+    }
+}
+
+/// Set a server's level, or `None` to notify everything
+pub fn set_server_level(db: &SqlConnection, server: &str, level: Option<Level>) -> Result<(), Error> {
+    match level {
+        Some(level) => db.execute("REPLACE INTO notify_servers (server, level) VALUES (?, ?)", &[&server, &level.as_str()])?,
+        None => db.execute("DELETE FROM notify_servers WHERE server = ?", &[&server])?
+    };
+    Ok(())
+}
+/// Set a channel's level, or `None` to use the server's
+pub fn set_channel_level(db: &SqlConnection, server: &str, channel: usize, level: Option<Level>) -> Result<(), Error> {
+    let channel = channel as i64;
+    match level {
+        Some(level) => db.execute(
+            "REPLACE INTO notify_channels (server, channel, level) VALUES (?, ?, ?)",
+            &[&server, &channel, &level.as_str()]
+        )?,
+        None => db.execute("DELETE FROM notify_channels WHERE server = ? AND channel = ?", &[&server, &channel])?
+    };
+    Ok(())
+}
+pub fn set_user_muted(db: &SqlConnection, server: &str, user: usize, muted: bool) -> Result<(), Error> {
+    let user = user as i64;
+    if muted {
+        db.execute("INSERT OR IGNORE INTO notify_muted_users (server, user) VALUES (?, ?)", &[&server, &user])?;
+    } else {
+        db.execute("DELETE FROM notify_muted_users WHERE server = ? AND user = ?", &[&server, &user])?;
+    }
+    Ok(())
+}
+pub fn add_keyword(db: &SqlConnection, keyword: &str) -> Result<(), Error> {
+    db.execute("INSERT OR IGNORE INTO notify_keywords (keyword) VALUES (?)", &[&keyword.trim()])?;
+    Ok(())
+}
+pub fn remove_keyword(db: &SqlConnection, keyword: &str) -> Result<(), Error> {
+    db.execute("DELETE FROM notify_keywords WHERE keyword = ?", &[&keyword])?;
+    Ok(())
+}
+pub fn add_schedule(db: &SqlConnection, schedule: &Schedule) -> Result<i64, Error> {
+    db.execute(
+        "INSERT INTO notify_schedules (days, start_minute, end_minute) VALUES (?, ?, ?)",
+        &[&(schedule.days as i64), &(schedule.start as i64), &(schedule.end as i64)]
+    )?;
+    Ok(db.last_insert_rowid())
+}
+pub fn remove_schedule(db: &SqlConnection, id: i64) -> Result<(), Error> {
+    db.execute("DELETE FROM notify_schedules WHERE id = ?", &[&id])?;
+    Ok(())
+}
+/// Drop the rules of a server address, unless another saved account still uses it
+pub fn forget(db: &SqlConnection, server: &str) -> Result<(), Error> {
+    for table in &["notify_servers", "notify_channels", "notify_muted_users"] {
+        db.execute(
+            &format!("DELETE FROM {} WHERE server = ? AND NOT EXISTS (SELECT 1 FROM servers WHERE ip = ?)", table),
+            &[&server, &server]
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut db = SqlConnection::open_in_memory().unwrap();
+    ::migrations::migrate(&mut db, None).unwrap();
+
+    let msg = |server, channel, author, text| Incoming {
+        server: server,
+        channel: channel,
+        private: false,
+        author: author,
+        text: text,
+        me: "Bob"
+    };
+    // A Wednesday afternoon
+    let (wed, afternoon) = (2, 15 * 60);
+
+    let rules = Rules::load(&db).unwrap();
+    assert!(rules.should_notify(&msg("a", 1, 2, "hi"), wed, afternoon));
+
+    set_server_level(&db, "a", Some(Level::Mentions)).unwrap();
+    set_channel_level(&db, "a", 2, Some(Level::All)).unwrap();
+    set_channel_level(&db, "a", 3, Some(Level::Nothing)).unwrap();
+    set_user_muted(&db, "a", 5, true).unwrap();
+    add_keyword(&db, "release").unwrap();
+
+    let rules = Rules::load(&db).unwrap();
+    assert_eq!(rules.level("a", 1), Level::Mentions);
+    assert_eq!(rules.level("a", 2), Level::All);
+    assert_eq!(rules.level("b", 1), Level::All);
+
+    assert!(!rules.should_notify(&msg("a", 1, 2, "hi"), wed, afternoon));
+    assert!(rules.should_notify(&msg("a", 1, 2, "hi @bob!"), wed, afternoon));
+    assert!(!rules.should_notify(&msg("a", 1, 2, "hi Bobby"), wed, afternoon));
+    assert!(rules.should_notify(&msg("a", 1, 2, "Release is out"), wed, afternoon));
+    assert!(rules.should_notify(&Incoming { private: true, ..msg("a", 1, 2, "hi") }, wed, afternoon));
+    assert!(rules.should_notify(&msg("a", 2, 2, "hi"), wed, afternoon));
+    assert!(!rules.should_notify(&msg("a", 3, 2, "@Bob"), wed, afternoon));
+    assert!(!rules.should_notify(&msg("a", 2, 5, "hi"), wed, afternoon));
+    assert!(rules.should_notify(&msg("b", 1, 5, "hi"), wed, afternoon));
+
+    // Weeknights, from 22:00 to 07:00
+    let night = Schedule { days: 0b001_1111, start: 22 * 60, end: 7 * 60 };
+    assert_eq!(night.describe(), "Mon, Tue, Wed, Thu, Fri 22:00 - 07:00");
+    assert!(night.is_active(wed, 23 * 60));
+    assert!(night.is_active(wed, 6 * 60));
+    assert!(!night.is_active(wed, afternoon));
+    // Saturday morning is still Friday night, but Monday morning isn't
+    assert!(night.is_active(5, 60));
+    assert!(!night.is_active(0, 60));
+
+    let id = add_schedule(&db, &night).unwrap();
+    let rules = Rules::load(&db).unwrap();
+    assert_eq!(rules.schedules, vec![(id, night)]);
+    assert!(!rules.should_notify(&msg("b", 1, 2, "hi"), wed, 23 * 60));
+    assert!(rules.should_notify(&msg("b", 1, 2, "hi"), wed, afternoon));
+
+    remove_schedule(&db, id).unwrap();
+    remove_keyword(&db, "release").unwrap();
+    forget(&db, "a").unwrap();
+    let rules = Rules::load(&db).unwrap();
+    assert!(rules.schedules.is_empty() && rules.keywords.is_empty());
+    assert_eq!(rules.level("a", 3), Level::All);
+    assert!(!rules.is_user_muted("a", 5));
+}
@@ -0,0 +1,292 @@
+//! Local channel organisation: favorites, custom order and folders.
+//! Everything is keyed by server address and channel id, so it survives renames.
+
+use failure::Error;
+use rusqlite::Connection as SqlConnection;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Folder {
+    pub id: i64,
+    pub name: String,
+    pub collapsed: bool
+}
+
+/// Where one channel goes. Channels without one are unfiled, by name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Placement {
+    pub favorite: bool,
+    pub folder: Option<i64>,
+    pub position: Option<i64>
+}
+
+/// A group of channels in the list
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Section {
+    Favorites(Vec<usize>),
+    Folder(Folder, Vec<usize>),
+    Other(Vec<usize>)
+}
+impl Section {
+    pub fn channels(&self) -> &[usize] {
+        match *self {
+            Section::Favorites(ref channels) |
+            Section::Folder(_, ref channels) |
+            Section::Other(ref channels) => channels
+        }
+    }
+}
+
+/// One line of the channel list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Row<'a> {
+    Heading(&'a Section),
+    Channel(usize)
+}
+
+/// The channel list from top to bottom: each section's heading, then its channels unless it's a collapsed folder.
+/// Headings only get in the way if there's nothing to organise, so one section gets none.
+pub fn rows(sections: &[Section]) -> Vec<Row> {
+    let headings = sections.len() > 1;
+    let mut rows = Vec::new();
+    for section in sections {
+        if headings {
+            rows.push(Row::Heading(section));
+        }
+        if let Section::Folder(ref folder, _) = *section {
+            if folder.collapsed {
+                continue;
+            }
+        }
+        rows.extend(section.channels().iter().map(|&channel| Row::Channel(channel)));
+    }
+    rows
+}
+
+#[derive(Debug, Default)]
+pub struct Organizer {
+    /// In display order
+    pub folders: Vec<Folder>,
+    pub channels: HashMap<usize, Placement>
+}
+impl Organizer {
+    pub fn load(db: &SqlConnection, server: &str) -> Result<Self, Error> {
+        let mut organizer = Organizer::default();
+
+        let mut stmt = db.prepare_cached(
+            "SELECT id, name, collapsed FROM channel_folders WHERE server = ? ORDER BY position, id"
+        )?;
+        for row in stmt.query_map(&[&server], |row| Folder {
+            id: row.get(0),
+            name: row.get(1),
+            collapsed: row.get(2)
+        })? {
+            organizer.folders.push(row?);
+        }
+
+        let mut stmt = db.prepare_cached(
+            "SELECT channel, favorite, folder, position FROM channel_layout WHERE server = ?"
+        )?;
+        for row in stmt.query_map(&[&server], |row| (row.get::<_, i64>(0) as usize, Placement {
+            favorite: row.get(1),
+            folder: row.get(2),
+            position: row.get(3)
+        }))? {
+            let (channel, placement) = row?;
+            organizer.channels.insert(channel, placement);
+        }
+
+        Ok(organizer)
+    }
+    /// Save where each channel goes. Folders are saved as they're changed.
+    pub fn save(&self, db: &SqlConnection, server: &str) -> Result<(), Error> {
+        db.execute("DELETE FROM channel_layout WHERE server = ?", &[&server])?;
+        let mut stmt = db.prepare_cached(
+            "INSERT INTO channel_layout (server, channel, favorite, folder, position) VALUES (?, ?, ?, ?, ?)"
+        )?;
+        for (&channel, placement) in &self.channels {
+            if *placement != Placement::default() {
+                stmt.execute(&[&server, &(channel as i64), &placement.favorite, &placement.folder, &placement.position])?;
+            }
+        }
+        Ok(())
+    }
+    pub fn placement(&self, channel: usize) -> Placement {
+        self.channels.get(&channel).cloned().unwrap_or_default()
+    }
+    /// Group `channels`, given with their names: favorites first, then each folder, then the rest.
+    /// Within a group, channels that were moved come first in their order, then the others by name.
+    pub fn arrange(&self, channels: &[(usize, &str)]) -> Vec<Section> {
+        let mut channels = channels.to_vec();
+        channels.sort_by_key(|&(channel, name)| {
+            let position = self.placement(channel).position;
+            (position.is_none(), position, name, channel)
+        });
+
+        let mut favorites = Vec::new();
+        let mut folders: Vec<_> = self.folders.iter().map(|folder| (folder, Vec::new())).collect();
+        let mut other = Vec::new();
+        for (channel, _) in channels {
+            let placement = self.placement(channel);
+            if placement.favorite {
+                favorites.push(channel);
+                continue;
+            }
+            match folders.iter_mut().find(|entry| Some(entry.0.id) == placement.folder) {
+                Some(entry) => entry.1.push(channel),
+                // The folder is gone
+                None => other.push(channel)
+            }
+        }
+
+        let mut sections = Vec::with_capacity(folders.len() + 2);
+        if !favorites.is_empty() {
+            sections.push(Section::Favorites(favorites));
+        }
+        for (folder, inner) in folders {
+            sections.push(Section::Folder(folder.clone(), inner));
+        }
+        sections.push(Section::Other(other));
+        sections
+    }
+    pub fn set_favorite(&mut self, channel: usize, favorite: bool) {
+        let placement = self.channels.entry(channel).or_insert_with(Placement::default);
+        placement.favorite = favorite;
+        placement.position = None;
+    }
+    pub fn set_folder(&mut self, channel: usize, folder: Option<i64>) {
+        let placement = self.channels.entry(channel).or_insert_with(Placement::default);
+        placement.favorite = false;
+        placement.folder = folder;
+        placement.position = None;
+    }
+    /// Put `channel` in the same group as `target`, just before it,
+    /// and fix the order of that whole group.
+    pub fn move_before(&mut self, channels: &[(usize, &str)], channel: usize, target: usize) {
+        if channel == target {
+            return;
+        }
+        let target_placement = self.placement(target);
+        let sections = self.arrange(channels);
+        let group = match sections.iter().find(|section| section.channels().contains(&target)) {
+            Some(section) => section.channels(),
+            None => return
+        };
+
+        let mut order: Vec<_> = group.iter().cloned().filter(|&other| other != channel).collect();
+        let i = order.iter().position(|&other| other == target).unwrap_or(order.len());
+        order.insert(i, channel);
+
+        for (position, &other) in order.iter().enumerate() {
+            let placement = self.channels.entry(other).or_insert_with(Placement::default);
+            placement.favorite = target_placement.favorite;
+            if !target_placement.favorite {
+                placement.folder = target_placement.folder;
+            }
+            placement.position = Some(position as i64);
+        }
+    }
+}
+
+pub fn create_folder(db: &SqlConnection, server: &str, name: &str) -> Result<i64, Error> {
+    db.execute(
+        "INSERT INTO channel_folders (server, name, position)
+         VALUES (?1, ?2, (SELECT IFNULL(MAX(position), -1) + 1 FROM channel_folders WHERE server = ?1))",
+        &[&server, &name]
+    )?;
+    Ok(db.last_insert_rowid())
+}
+pub fn rename_folder(db: &SqlConnection, folder: i64, name: &str) -> Result<(), Error> {
+    db.execute("UPDATE channel_folders SET name = ? WHERE id = ?", &[&name, &folder])?;
+    Ok(())
+}
+pub fn set_collapsed(db: &SqlConnection, folder: i64, collapsed: bool) -> Result<(), Error> {
+    db.execute("UPDATE channel_folders SET collapsed = ? WHERE id = ?", &[&collapsed, &folder])?;
+    Ok(())
+}
+/// Delete a folder. Its channels become unfiled.
+pub fn delete_folder(db: &SqlConnection, folder: i64) -> Result<(), Error> {
+    db.execute("UPDATE channel_layout SET folder = NULL, position = NULL WHERE folder = ?", &[&folder])?;
+    db.execute("DELETE FROM channel_folders WHERE id = ?", &[&folder])?;
+    Ok(())
+}
+/// Drop the folders and layout of a server address, unless another saved account still uses it
+pub fn forget(db: &SqlConnection, server: &str) -> Result<(), Error> {
+    for table in &["channel_layout", "channel_folders"] {
+        db.execute(
+            &format!("DELETE FROM {} WHERE server = ? AND NOT EXISTS (SELECT 1 FROM servers WHERE ip = ?)", table),
+            &[&server, &server]
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut db = SqlConnection::open_in_memory().unwrap();
+    ::migrations::migrate(&mut db, None).unwrap();
+
+    let work = create_folder(&db, "a", "work").unwrap();
+    let fun = create_folder(&db, "a", "fun").unwrap();
+    create_folder(&db, "b", "elsewhere").unwrap();
+
+    let channels = [(1, "general"), (2, "random"), (3, "dev"), (4, "ops"), (5, "memes")];
+
+    let mut organizer = Organizer::load(&db, "a").unwrap();
+    assert_eq!(organizer.folders.iter().map(|folder| &*folder.name).collect::<Vec<_>>(), vec!["work", "fun"]);
+    assert_eq!(organizer.arrange(&channels), vec![
+        Section::Folder(organizer.folders[0].clone(), vec![]),
+        Section::Folder(organizer.folders[1].clone(), vec![]),
+        Section::Other(vec![3, 1, 5, 4, 2])
+    ]);
+
+    organizer.set_favorite(1, true);
+    organizer.set_folder(3, Some(work));
+    organizer.set_folder(4, Some(work));
+    organizer.set_folder(5, Some(fun));
+    // Drag ops above dev
+    organizer.move_before(&channels, 4, 3);
+    // Drag random onto general, into the favorites
+    organizer.move_before(&channels, 2, 1);
+
+    let sections = organizer.arrange(&channels);
+    assert_eq!(sections.iter().map(|section| section.channels().to_vec()).collect::<Vec<_>>(), vec![
+        vec![2, 1],
+        vec![4, 3],
+        vec![5],
+        vec![]
+    ]);
+
+    // Survives a reload, and a rename
+    organizer.save(&db, "a").unwrap();
+    assert_eq!(rows(&sections), vec![
+        Row::Heading(&sections[0]), Row::Channel(2), Row::Channel(1),
+        Row::Heading(&sections[1]), Row::Channel(4), Row::Channel(3),
+        Row::Heading(&sections[2]), Row::Channel(5),
+        Row::Heading(&sections[3])
+    ]);
+    let organizer = Organizer::load(&db, "a").unwrap();
+    let renamed = [(1, "general"), (2, "random"), (3, "zzz"), (4, "ops"), (5, "memes")];
+    assert_eq!(organizer.arrange(&renamed), sections);
+
+    rename_folder(&db, work, "job").unwrap();
+    set_collapsed(&db, fun, true).unwrap();
+    delete_folder(&db, work).unwrap();
+    let organizer = Organizer::load(&db, "a").unwrap();
+    assert_eq!(organizer.folders, vec![Folder { id: fun, name: String::from("fun"), collapsed: true }]);
+    let sections = organizer.arrange(&channels);
+    assert_eq!(sections.last(), Some(&Section::Other(vec![3, 4])));
+    // The collapsed folder keeps its heading only
+    assert_eq!(rows(&sections), vec![
+        Row::Heading(&sections[0]), Row::Channel(2), Row::Channel(1),
+        Row::Heading(&sections[1]),
+        Row::Heading(&sections[2]), Row::Channel(3), Row::Channel(4)
+    ]);
+    assert_eq!(rows(&[Section::Other(vec![1, 2])]), vec![Row::Channel(1), Row::Channel(2)]);
+
+    forget(&db, "a").unwrap();
+    let organizer = Organizer::load(&db, "a").unwrap();
+    assert!(organizer.folders.is_empty() && organizer.channels.is_empty());
+    assert_eq!(Organizer::load(&db, "b").unwrap().folders.len(), 1);
+}
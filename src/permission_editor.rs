@@ -0,0 +1,130 @@
+use ::*;
+use gtk::{Grid, RadioButton};
+use permissions::{ModeEdit, State, PERMISSIONS};
+use std::cell::Cell;
+
+enum Control {
+    Check(CheckButton),
+    Tristate {
+        inherit: RadioButton,
+        allow: RadioButton,
+        deny: RadioButton
+    }
+}
+
+/// Edits a mode one permission at a time. Each control is bound to its bit,
+/// so the order of the widgets doesn't matter.
+pub(crate) struct PermissionEditor {
+    grid: Grid,
+    controls: Vec<(u8, Control)>,
+    edit: Rc<RefCell<ModeEdit>>,
+    default: Cell<u8>
+}
+impl PermissionEditor {
+    /// With `tristate`, each permission can also be taken from the channel default.
+    /// Unless every permission is, the default is copied into the user's own mode once it's saved.
+    pub fn new(tristate: bool) -> Self {
+        let grid = Grid::new();
+        grid.set_row_spacing(2);
+        grid.set_column_spacing(10);
+
+        let edit = Rc::new(RefCell::new(ModeEdit::default()));
+        let mut controls = Vec::with_capacity(PERMISSIONS.len());
+
+        for (row, permission) in PERMISSIONS.iter().enumerate() {
+            let row = row as i32;
+            let bit = permission.bit;
+
+            let control = if tristate {
+                let name = Label::new(permission.name);
+                name.set_xalign(0.0);
+                name.set_tooltip_text(permission.description);
+                grid.attach(&name, 0, row, 1, 1);
+
+                let inherit = RadioButton::new_with_label("Inherit");
+                let allow = RadioButton::new_with_label_from_widget(&inherit, "Allow");
+                let deny = RadioButton::new_with_label_from_widget(&inherit, "Deny");
+                allow.set_tooltip_text(permission.description);
+                deny.set_tooltip_text(permission.description);
+
+                for (column, &(ref radio, state)) in [(&inherit, State::Inherit), (&allow, State::Allow), (&deny, State::Deny)]
+                        .iter().enumerate() {
+                    let edit = Rc::clone(&edit);
+                    radio.connect_toggled(move |radio| if radio.get_active() {
+                        edit.borrow_mut().set(bit, state);
+                    });
+                    grid.attach(*radio, 1 + column as i32, row, 1, 1);
+                }
+
+                Control::Tristate {
+                    inherit: inherit,
+                    allow: allow,
+                    deny: deny
+                }
+            } else {
+                let check = CheckButton::new_with_label(permission.name);
+                check.set_tooltip_text(permission.description);
+
+                let edit = Rc::clone(&edit);
+                check.connect_toggled(move |check| {
+                    edit.borrow_mut().set(bit, if check.get_active() { State::Allow } else { State::Deny });
+                });
+                grid.attach(&check, 0, row, 1, 1);
+
+                Control::Check(check)
+            };
+            controls.push((bit, control));
+        }
+
+        PermissionEditor {
+            grid: grid,
+            controls: controls,
+            edit: edit,
+            default: Cell::new(0)
+        }
+    }
+    pub fn widget(&self) -> &Grid {
+        &self.grid
+    }
+    fn load(&self, edit: ModeEdit) {
+        *self.edit.borrow_mut() = edit;
+
+        let default = self.default.get();
+        for &(bit, ref control) in &self.controls {
+            match *control {
+                Control::Check(ref check) => check.set_active(edit.get(bit) == State::Allow),
+                Control::Tristate { ref inherit, ref allow, ref deny } => {
+                    let current = if default & bit == bit { "allowed" } else { "denied" };
+                    inherit.set_tooltip_text(&*format!("Copy the channel default, currently {}", current));
+
+                    match edit.get(bit) {
+                        State::Inherit => inherit.set_active(true),
+                        State::Allow => allow.set_active(true),
+                        State::Deny => deny.set_active(true)
+                    }
+                }
+            }
+        }
+    }
+    /// Show a complete mode, like a channel default
+    pub fn set_mode(&self, mode: u8) {
+        self.default.set(mode);
+        self.load(ModeEdit::from_mode(mode));
+    }
+    pub fn mode(&self) -> u8 {
+        self.edit.borrow().to_mode(self.default.get())
+    }
+    /// Show a user's own mode, on top of the channel default that applies to them
+    pub fn set_override(&self, own: Option<u8>, default: u8) {
+        self.default.set(default);
+        self.load(ModeEdit::from_override(own, default));
+    }
+    /// Inherit every permission from the channel default
+    pub fn reset(&self) {
+        self.load(ModeEdit::from_override(None, self.default.get()));
+    }
+    /// The user's own mode, or `None` if everything is inherited
+    pub fn get_override(&self) -> Option<u8> {
+        self.edit.borrow().to_override(self.default.get())
+    }
+}
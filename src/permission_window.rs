@@ -0,0 +1,290 @@
+use ::*;
+use gtk::{ComboBoxText, Grid};
+use permissions::{self, Changes, Source, PERMISSIONS};
+use std::collections::HashSet;
+use synac::common::{Channel, User};
+
+struct Matrix {
+    app: Rc<App>,
+    id: ServerId,
+    channel: usize,
+    changes: RefCell<Changes>,
+    selected: RefCell<HashSet<usize>>,
+
+    grid: Grid,
+    status: Label
+}
+
+/// Apply a change to each of `users`, then redraw
+fn edit<F>(matrix: &Rc<Matrix>, users: &[usize], f: F)
+    where F: Fn(&mut Changes, &Channel, &User)
+{
+    matrix.app.connections.execute(matrix.id, |result| {
+        if let Ok(synac) = result {
+            if let Some(channel) = synac.state.channels.get(&matrix.channel) {
+                let mut changes = matrix.changes.borrow_mut();
+                for user in users.iter().filter_map(|id| synac.state.users.get(id)) {
+                    // Admins can do everything anyway
+                    if !user.admin {
+                        f(&mut changes, channel, user);
+                    }
+                }
+            }
+        }
+    });
+    fill(matrix);
+}
+
+fn fill(matrix: &Rc<Matrix>) {
+    for child in matrix.grid.get_children() {
+        matrix.grid.remove(&child);
+    }
+
+    let mut channel = None;
+    let mut users = Vec::new();
+    matrix.app.connections.execute(matrix.id, |result| {
+        if let Ok(synac) = result {
+            channel = synac.state.channels.get(&matrix.channel).cloned();
+            users = synac.state.users.values().cloned().collect();
+        }
+    });
+    let channel = match channel {
+        Some(channel) => channel,
+        None => {
+            matrix.status.set_text("This channel doesn't exist anymore.");
+            return;
+        }
+    };
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let user_heading = Label::new("User");
+    add_class(&user_heading, "bold");
+    user_heading.set_xalign(0.0);
+    matrix.grid.attach(&user_heading, 1, 0, 1, 1);
+    for (i, permission) in PERMISSIONS.iter().enumerate() {
+        let heading = Label::new(permission.name);
+        add_class(&heading, "bold");
+        heading.set_tooltip_text(permission.description);
+        matrix.grid.attach(&heading, 2 + i as i32, 0, 1, 1);
+    }
+    let source_heading = Label::new("From");
+    add_class(&source_heading, "bold");
+    source_heading.set_tooltip_text("Where the mode comes from");
+    matrix.grid.attach(&source_heading, 2 + PERMISSIONS.len() as i32, 0, 1, 1);
+
+    let changes = matrix.changes.borrow();
+    let selected = matrix.selected.borrow();
+
+    for (row, user) in users.iter().enumerate() {
+        let row = row as i32 + 1;
+        let user_id = user.id;
+        let changed = changes.is_changed(user_id);
+
+        let select = CheckButton::new();
+        select.set_active(selected.contains(&user_id));
+        select.set_sensitive(!user.admin);
+        let matrix_clone = Rc::clone(matrix);
+        select.connect_toggled(move |select| {
+            let mut selected = matrix_clone.selected.borrow_mut();
+            if select.get_active() {
+                selected.insert(user_id);
+            } else {
+                selected.remove(&user_id);
+            }
+        });
+        matrix.grid.attach(&select, 0, row, 1, 1);
+
+        let mut name = user.name.clone();
+        if user.bot {
+            name.push_str(" (bot)");
+        }
+        if user.ban {
+            name.push_str(" (banned)");
+        }
+        let name = Label::new(&*name);
+        name.set_xalign(0.0);
+        if changed {
+            add_class(&name, "bold");
+        }
+        matrix.grid.attach(&name, 1, row, 1, 1);
+
+        let source = if changed { "changed, not applied" } else { Source::of(&channel, user).describe() };
+        let mode = changes.mode(&channel, user);
+        for (i, permission) in PERMISSIONS.iter().enumerate() {
+            let bit = permission.bit;
+            let allowed = mode & bit == bit;
+
+            let check = CheckButton::new();
+            check.set_active(allowed);
+            check.set_sensitive(!user.admin);
+            check.set_halign(Align::Center);
+            check.set_tooltip_text(&format!(
+                "{} {}: {}",
+                if allowed { "Allowed" } else { "Denied" },
+                permission.name.to_lowercase(),
+                source
+            ));
+
+            let matrix_clone = Rc::clone(matrix);
+            check.connect_toggled(move |check| {
+                let on = check.get_active();
+                edit(&matrix_clone, &[user_id], |changes, channel, user| changes.toggle(channel, user, bit, on));
+            });
+            matrix.grid.attach(&check, 2 + i as i32, row, 1, 1);
+        }
+
+        let source = Label::new(source);
+        source.set_xalign(0.0);
+        matrix.grid.attach(&source, 2 + PERMISSIONS.len() as i32, row, 1, 1);
+
+        let reset = Button::new_with_label("Reset");
+        reset.set_tooltip_text("Use the channel default");
+        reset.set_sensitive(!user.admin && changes.own_mode(&channel, user).is_some());
+        let matrix_clone = Rc::clone(matrix);
+        reset.connect_clicked(move |_| {
+            edit(&matrix_clone, &[user_id], |changes, channel, user| changes.set(channel, user, None));
+        });
+        matrix.grid.attach(&reset, 3 + PERMISSIONS.len() as i32, row, 1, 1);
+    }
+
+    matrix.status.set_text(&format!(
+        "Defaults in #{}: users {}, bots {}. Admins can do everything.",
+        channel.name,
+        permissions::describe(channel.default_mode_user),
+        permissions::describe(channel.default_mode_bot)
+    ));
+    matrix.grid.show_all();
+}
+
+fn apply(matrix: &Rc<Matrix>) {
+    let packets = matrix.changes.borrow().packets(matrix.channel);
+    if packets.is_empty() {
+        return;
+    }
+
+    let mut err = None;
+    matrix.app.connections.execute(matrix.id, |result| match result {
+        Ok(synac) => for packet in &packets {
+            if let Err(inner) = synac.write(packet) {
+                err = Some(inner.to_string());
+                break;
+            }
+        },
+        Err(inner) => err = Some(inner.to_string())
+    });
+
+    match err {
+        Some(err) => alert(&matrix.app.window, MessageType::Error, &format!("failed to send changes: {}", err)),
+        None => {
+            matrix.changes.borrow_mut().clear();
+            fill(matrix);
+            matrix.status.set_text(&format!("Sent {} change(s). Refresh once the server has applied them.", packets.len()));
+        }
+    }
+}
+
+pub(crate) fn show(app: &Rc<App>, id: ServerId, channel: usize) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Permissions");
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(750, 450);
+
+    let matrix = Rc::new(Matrix {
+        app: Rc::clone(app),
+        id: id,
+        channel: channel,
+        changes: RefCell::new(Changes::new()),
+        selected: RefCell::new(HashSet::new()),
+
+        grid: Grid::new(),
+        status: Label::new("")
+    });
+    matrix.grid.set_row_spacing(2);
+    matrix.grid.set_column_spacing(10);
+    matrix.status.set_xalign(0.0);
+    matrix.status.set_line_wrap(true);
+
+    let container = GtkBox::new(Orientation::Vertical, 6);
+    container.set_property_margin(10);
+    container.add(&matrix.status);
+
+    let scroll = ScrolledWindow::new(None, None);
+    scroll.set_vexpand(true);
+    scroll.add(&matrix.grid);
+    container.add(&scroll);
+
+    let bulk = GtkBox::new(Orientation::Horizontal, 2);
+    bulk.add(&Label::new("Selected users:"));
+
+    let permission = ComboBoxText::new();
+    for entry in PERMISSIONS {
+        permission.append_text(entry.name);
+    }
+    permission.set_active(0);
+    bulk.add(&permission);
+
+    for &(label, on) in &[("_Grant", true), ("_Revoke", false)] {
+        let button = Button::new_with_mnemonic(label);
+        let matrix_clone = Rc::clone(&matrix);
+        let permission = permission.clone();
+        button.connect_clicked(move |_| {
+            let bit = match PERMISSIONS.get(permission.get_active() as usize) {
+                Some(permission) => permission.bit,
+                None => return
+            };
+            let users: Vec<_> = matrix_clone.selected.borrow().iter().cloned().collect();
+            edit(&matrix_clone, &users, |changes, channel, user| changes.toggle(channel, user, bit, on));
+        });
+        bulk.add(&button);
+    }
+
+    let reset = Button::new_with_mnemonic("Use _default");
+    let matrix_clone = Rc::clone(&matrix);
+    reset.connect_clicked(move |_| {
+        let users: Vec<_> = matrix_clone.selected.borrow().iter().cloned().collect();
+        edit(&matrix_clone, &users, |changes, channel, user| changes.set(channel, user, None));
+    });
+    bulk.add(&reset);
+    container.add(&bulk);
+
+    let controls = GtkBox::new(Orientation::Horizontal, 2);
+    controls.set_halign(Align::End);
+
+    let refresh = Button::new_with_mnemonic("Re_fresh");
+    let matrix_clone = Rc::clone(&matrix);
+    refresh.connect_clicked(move |_| fill(&matrix_clone));
+    controls.add(&refresh);
+
+    let revert = Button::new_with_mnemonic("Re_vert");
+    let matrix_clone = Rc::clone(&matrix);
+    revert.connect_clicked(move |_| {
+        matrix_clone.changes.borrow_mut().clear();
+        fill(&matrix_clone);
+    });
+    controls.add(&revert);
+
+    let apply_button = Button::new_with_mnemonic("_Apply");
+    let matrix_clone = Rc::clone(&matrix);
+    apply_button.connect_clicked(move |_| apply(&matrix_clone));
+    controls.add(&apply_button);
+
+    let close = Button::new_with_mnemonic("_Close");
+    let window_clone = window.clone();
+    let matrix_clone = Rc::clone(&matrix);
+    close.connect_clicked(move |_| {
+        if matrix_clone.changes.borrow().is_empty() {
+            window_clone.destroy();
+            return;
+        }
+        let window_clone = window_clone.clone();
+        confirm(&matrix_clone.app.window, "Discard changes that weren't applied?", move || window_clone.destroy());
+    });
+    controls.add(&close);
+    container.add(&controls);
+
+    fill(&matrix);
+
+    window.add(&container);
+    window.show_all();
+}
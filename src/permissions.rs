@@ -0,0 +1,393 @@
+//! What each permission bit means, and where a user's mode in a channel comes from.
+
+use std::collections::HashMap;
+use synac::common::{self, Channel, Packet, User};
+
+pub struct Permission {
+    pub bit: u8,
+    pub name: &'static str,
+    pub description: &'static str
+}
+
+/// Every permission bit, in display order
+pub const PERMISSIONS: &[Permission] = &[
+    Permission {
+        bit: common::PERM_READ,
+        name: "Read",
+        description: "Read messages in the channel"
+    },
+    Permission {
+        bit: common::PERM_WRITE,
+        name: "Write",
+        description: "Send messages to the channel"
+    },
+    Permission {
+        bit: common::PERM_MANAGE_CHANNELS,
+        name: "Manage channel",
+        description: "Rename the channel, change its default modes or delete it"
+    },
+    Permission {
+        bit: common::PERM_MANAGE_MESSAGES,
+        name: "Manage messages",
+        description: "Delete messages by others"
+    },
+    Permission {
+        bit: common::PERM_MANAGE_MODES,
+        name: "Manage modes",
+        description: "Change the modes of other users in the channel"
+    }
+];
+
+/// Where a user's mode in a channel comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Admins can do everything, everywhere
+    Admin,
+    /// The user has their own mode in this channel
+    Override,
+    /// The channel's default for bots
+    BotDefault,
+    /// The channel's default for users
+    UserDefault
+}
+impl Source {
+    pub fn of(channel: &Channel, user: &User) -> Self {
+        if user.admin {
+            Source::Admin
+        } else if user.modes.contains_key(&channel.id) {
+            Source::Override
+        } else if user.bot {
+            Source::BotDefault
+        } else {
+            Source::UserDefault
+        }
+    }
+    pub fn describe(&self) -> &'static str {
+        match *self {
+            Source::Admin => "admin",
+            Source::Override => "own mode",
+            Source::BotDefault => "bot default",
+            Source::UserDefault => "user default"
+        }
+    }
+}
+
+/// The effective mode, the same as `synac::get_mode`
+pub fn mode(channel: &Channel, user: &User) -> u8 {
+    match Source::of(channel, user) {
+        Source::Admin => common::PERM_ALL,
+        Source::Override => user.modes[&channel.id],
+        Source::BotDefault => channel.default_mode_bot,
+        Source::UserDefault => channel.default_mode_user
+    }
+}
+/// List the permissions in a mode, like "read, write"
+pub fn describe(mode: u8) -> String {
+    let names: Vec<_> = PERMISSIONS.iter()
+        .filter(|permission| mode & permission.bit == permission.bit)
+        .map(|permission| permission.name.to_lowercase())
+        .collect();
+    if names.is_empty() {
+        String::from("nothing")
+    } else {
+        names.join(", ")
+    }
+}
+/// Explain step by step how `mode` comes to be, ending with what the user can and can't do
+pub fn explain(channel: &Channel, user: &User) -> Vec<String> {
+    let mut steps = Vec::new();
+    if user.ban {
+        // Nothing else matters, not even being an admin
+        steps.push(format!("{} is banned, so they can't use the server at all.", user.name));
+        for permission in PERMISSIONS {
+            steps.push(format!("✘ {} can't: {}", user.name, permission.description.to_lowercase()));
+        }
+        return steps;
+    }
+
+    let kind = if user.bot { "bots" } else { "users" };
+    match Source::of(channel, user) {
+        Source::Admin => steps.push(format!(
+            "{} is an admin, so they have every permission, whatever their own mode or the defaults are.",
+            user.name
+        )),
+        Source::Override => {
+            steps.push(format!(
+                "{} has their own mode in #{}: {}.",
+                user.name, channel.name, describe(user.modes[&channel.id])
+            ));
+            steps.push(format!(
+                "It replaces the channel's default for {} ({}) completely.",
+                kind, describe(default_mode(channel, user))
+            ));
+        },
+        Source::BotDefault | Source::UserDefault => {
+            steps.push(format!("{} has no mode of their own in #{}.", user.name, channel.name));
+            steps.push(format!(
+                "So the channel's default for {} applies: {}.",
+                kind, describe(default_mode(channel, user))
+            ));
+        }
+    }
+
+    let mode = mode(channel, user);
+    for permission in PERMISSIONS {
+        let (can, verb) = if mode & permission.bit == permission.bit { ("✔", "can") } else { ("✘", "can't") };
+        steps.push(format!("{} {} {}: {}", can, user.name, verb, permission.description.to_lowercase()));
+    }
+    steps
+}
+/// The channel default that applies to a user, ignoring admin and their own mode
+pub fn default_mode(channel: &Channel, user: &User) -> u8 {
+    if user.bot { channel.default_mode_bot } else { channel.default_mode_user }
+}
+
+/// Every bit in `PERMISSIONS`
+pub fn known_bits() -> u8 {
+    PERMISSIONS.iter().fold(0, |bits, permission| bits | permission.bit)
+}
+
+/// The state of one bit in a mode that's being edited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Take the bit from the channel default
+    Inherit,
+    Allow,
+    Deny
+}
+
+/// A mode being edited bit by bit.
+/// Bits not in `PERMISSIONS`, say from a newer server, are kept as they were.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ModeEdit {
+    allow: u8,
+    deny: u8,
+    unknown: u8
+}
+impl ModeEdit {
+    /// A complete mode, with every bit either allowed or denied
+    pub fn from_mode(mode: u8) -> Self {
+        let known = known_bits();
+        ModeEdit {
+            allow: mode & known,
+            deny: !mode & known,
+            unknown: mode & !known
+        }
+    }
+    /// A user's own mode, with every bit inherited if they don't have one
+    pub fn from_override(own: Option<u8>, default: u8) -> Self {
+        match own {
+            Some(mode) => ModeEdit::from_mode(mode),
+            None => ModeEdit {
+                allow: 0,
+                deny: 0,
+                unknown: default & !known_bits()
+            }
+        }
+    }
+    pub fn get(&self, bit: u8) -> State {
+        if self.allow & bit == bit {
+            State::Allow
+        } else if self.deny & bit == bit {
+            State::Deny
+        } else {
+            State::Inherit
+        }
+    }
+    pub fn set(&mut self, bit: u8, state: State) {
+        self.allow &= !bit;
+        self.deny &= !bit;
+        match state {
+            State::Inherit => (),
+            State::Allow => self.allow |= bit,
+            State::Deny => self.deny |= bit
+        }
+    }
+    /// True if every bit is inherited
+    pub fn is_inherited(&self) -> bool {
+        self.allow | self.deny == 0
+    }
+    /// The resulting mode, taking inherited bits from `default`
+    pub fn to_mode(&self, default: u8) -> u8 {
+        (default & known_bits() & !self.deny) | self.allow | self.unknown
+    }
+    /// The resulting own mode, or `None` to use the channel default
+    pub fn to_override(&self, default: u8) -> Option<u8> {
+        if self.is_inherited() {
+            None
+        } else {
+            Some(self.to_mode(default))
+        }
+    }
+}
+
+/// Mode changes for one channel, not sent yet.
+/// Each user maps to their new own mode, or `None` to use the channel default again.
+#[derive(Debug, Default)]
+pub struct Changes {
+    modes: HashMap<usize, Option<u8>>
+}
+impl Changes {
+    pub fn new() -> Self {
+        Changes::default()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.modes.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.modes.clear();
+    }
+    pub fn is_changed(&self, user: usize) -> bool {
+        self.modes.contains_key(&user)
+    }
+    /// The user's own mode in the channel, with changes applied
+    pub fn own_mode(&self, channel: &Channel, user: &User) -> Option<u8> {
+        match self.modes.get(&user.id) {
+            Some(&mode) => mode,
+            None => user.modes.get(&channel.id).cloned()
+        }
+    }
+    /// The effective mode, with changes applied
+    pub fn mode(&self, channel: &Channel, user: &User) -> u8 {
+        match self.modes.get(&user.id) {
+            Some(&own) if !user.admin => own.unwrap_or_else(|| default_mode(channel, user)),
+            _ => mode(channel, user)
+        }
+    }
+    /// Set the user's own mode. Setting it back to what it was drops the change.
+    pub fn set(&mut self, channel: &Channel, user: &User, mode: Option<u8>) {
+        if user.modes.get(&channel.id).cloned() == mode {
+            self.modes.remove(&user.id);
+        } else {
+            self.modes.insert(user.id, mode);
+        }
+    }
+    /// Turn one bit on or off, giving the user their own mode if needed
+    pub fn toggle(&mut self, channel: &Channel, user: &User, bit: u8, on: bool) {
+        let mode = self.mode(channel, user);
+        let mode = if on { mode | bit } else { mode & !bit };
+        self.set(channel, user, Some(mode));
+    }
+    /// One `UserUpdate` per changed user, sorted by user id
+    pub fn packets(&self, channel: usize) -> Vec<Packet> {
+        let mut users: Vec<_> = self.modes.iter().collect();
+        users.sort_by_key(|&(&user, _)| user);
+        users.into_iter()
+            .map(|(&user, &mode)| Packet::UserUpdate(common::UserUpdate {
+                admin: None,
+                ban: None,
+                channel_mode: Some((channel, mode)),
+                id: user
+            }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use synac;
+
+    let channel = Channel {
+        default_mode_bot: common::PERM_READ,
+        default_mode_user: common::PERM_READ | common::PERM_WRITE,
+        id: 1,
+        name: String::from("general"),
+        private: false
+    };
+    let user = |id, admin, bot, own: Option<u8>| {
+        let mut modes = HashMap::new();
+        if let Some(own) = own {
+            modes.insert(channel.id, own);
+        }
+        User {
+            admin: admin,
+            ban: false,
+            bot: bot,
+            id: id,
+            modes: modes,
+            name: format!("user {}", id)
+        }
+    };
+
+    let users = [
+        user(1, false, false, None),
+        user(2, false, true, None),
+        user(3, false, false, Some(0)),
+        user(4, true, false, Some(0))
+    ];
+    assert_eq!(
+        users.iter().map(|user| Source::of(&channel, user)).collect::<Vec<_>>(),
+        vec![Source::UserDefault, Source::BotDefault, Source::Override, Source::Admin]
+    );
+    for user in &users {
+        assert_eq!(mode(&channel, user), synac::get_mode(&channel, user));
+    }
+
+    let mut changes = Changes::new();
+    changes.toggle(&channel, &users[0], common::PERM_MANAGE_MESSAGES, true);
+    assert_eq!(changes.mode(&channel, &users[0]), common::PERM_READ | common::PERM_WRITE | common::PERM_MANAGE_MESSAGES);
+    changes.toggle(&channel, &users[2], common::PERM_READ, true);
+    changes.set(&channel, &users[1], None);
+    assert!(!changes.is_changed(users[1].id));
+
+    // Going back to the original mode drops the change
+    changes.toggle(&channel, &users[2], common::PERM_READ, false);
+    assert!(!changes.is_changed(users[2].id));
+
+    changes.set(&channel, &users[2], None);
+    assert_eq!(changes.mode(&channel, &users[2]), channel.default_mode_user);
+
+    assert_eq!(describe(changes.mode(&channel, &users[3])), "read, write, manage channel, manage messages, manage modes");
+    assert_eq!(describe(0), "nothing");
+
+    let steps = explain(&channel, &users[1]);
+    assert_eq!(steps[1], "So the channel's default for bots applies: read.");
+    assert_eq!(steps.len(), 2 + PERMISSIONS.len());
+    assert!(steps[2].starts_with("✔"));
+    assert!(steps[3].starts_with("✘"));
+    assert!(explain(&channel, &users[3])[0].contains("admin"));
+
+    let banned = User { ban: true, ..users[3].clone() };
+    let steps = explain(&channel, &banned);
+    assert!(steps[0].contains("banned"));
+    assert_eq!(steps.len(), 1 + PERMISSIONS.len());
+    assert!(steps[1..].iter().all(|step| step.starts_with("✘")));
+
+    // Every permission has its own bit, and together they're everything
+    assert_eq!(known_bits(), common::PERM_ALL);
+    assert_eq!(PERMISSIONS.iter().map(|permission| permission.bit.count_ones()).sum::<u32>(), common::PERM_ALL.count_ones());
+
+    for mode in 0..=255u8 {
+        let edit = ModeEdit::from_mode(mode);
+        assert!(!edit.is_inherited());
+        for &default in &[0, common::PERM_READ, common::PERM_ALL, 255] {
+            assert_eq!(edit.to_mode(default), mode);
+            assert_eq!(ModeEdit::from_override(Some(mode), default).to_override(default), Some(mode));
+            assert_eq!(ModeEdit::from_override(None, mode).to_override(default), None);
+            assert_eq!(ModeEdit::from_override(None, mode).to_mode(mode), mode);
+        }
+        for permission in PERMISSIONS {
+            let allowed = mode & permission.bit == permission.bit;
+            assert_eq!(edit.get(permission.bit), if allowed { State::Allow } else { State::Deny });
+        }
+    }
+
+    let mut edit = ModeEdit::from_override(None, channel.default_mode_user);
+    assert_eq!(edit.get(common::PERM_WRITE), State::Inherit);
+    edit.set(common::PERM_MANAGE_MESSAGES, State::Allow);
+    edit.set(common::PERM_WRITE, State::Deny);
+    assert_eq!(edit.to_override(channel.default_mode_user), Some(common::PERM_READ | common::PERM_MANAGE_MESSAGES));
+    // Inherited bits follow the default
+    assert_eq!(edit.to_mode(0), common::PERM_MANAGE_MESSAGES);
+    edit.set(common::PERM_MANAGE_MESSAGES, State::Inherit);
+    edit.set(common::PERM_WRITE, State::Inherit);
+    assert_eq!(edit.to_override(channel.default_mode_user), None);
+
+    let packets = changes.packets(channel.id);
+    assert_eq!(packets.len(), 2);
+    match packets[1] {
+        Packet::UserUpdate(ref update) => assert_eq!((update.id, update.channel_mode), (3, Some((1, None)))),
+        _ => panic!("expected a user update")
+    }
+}
@@ -0,0 +1,191 @@
+use ::*;
+use gtk::{ComboBoxText, Grid, SpinButton};
+use settings::Theme;
+
+fn add_row<W: IsA<gtk::Widget>>(grid: &Grid, row: &mut i32, label: &str, widget: &W) {
+    let label = Label::new(label);
+    label.set_xalign(0.0);
+    grid.attach(&label, 0, *row, 1, 1);
+    grid.attach(widget, 1, *row, 1, 1);
+    *row += 1;
+}
+/// The saved value of a setting, to put a widget back when a new value is rejected
+fn stored(app: &Rc<App>, key: &str) -> String {
+    app.settings.borrow().get(key).unwrap_or_default()
+}
+fn add_check(app: &Rc<App>, grid: &Grid, row: &mut i32, key: &'static str, label: &str, active: bool) {
+    let check = CheckButton::new_with_label(label);
+    check.set_active(active);
+
+    let app = Rc::clone(app);
+    check.connect_toggled(move |check| {
+        if !apply_setting(&app, key, if check.get_active() { "1" } else { "0" }) {
+            let active = stored(&app, key) == "1";
+            check.set_active(active);
+        }
+    });
+
+    grid.attach(&check, 0, *row, 2, 1);
+    *row += 1;
+}
+fn add_grid_heading(grid: &Grid, row: &mut i32, text: &str) {
+    let label = Label::new(text);
+    add_class(&label, "bold");
+    label.set_xalign(0.0);
+    label.set_margin_top(10);
+    grid.attach(&label, 0, *row, 2, 1);
+    *row += 1;
+}
+
+pub(crate) fn show(app: &Rc<App>) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Preferences");
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(450, 0);
+
+    let settings = app.settings.borrow().clone();
+
+    let grid = Grid::new();
+    grid.set_property_margin(10);
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(10);
+
+    let mut row = 0;
+
+    add_grid_heading(&grid, &mut row, "Notifications");
+    add_check(app, &grid, &mut row, "notifications", "Show desktop notifications", settings.notifications);
+    add_check(app, &grid, &mut row, "notification_body", "Include the message text", settings.notification_body);
+
+    let rules = Button::new_with_mnemonic("Keywords and _quiet hours...");
+    rules.set_halign(Align::Start);
+    let app_clone = Rc::clone(app);
+    rules.connect_clicked(move |_| notification_window::show(&app_clone));
+    add_row(&grid, &mut row, "Rules:", &rules);
+
+    add_grid_heading(&grid, &mut row, "Messages");
+
+    let layout = ComboBoxText::new();
+    layout.append_text("Cozy");
+    layout.append_text("Compact (IRC style)");
+    layout.set_active(match settings.layout.layout {
+        messages::Layout::Cozy => 0,
+        messages::Layout::Compact => 1
+    });
+
+    let app_clone = Rc::clone(app);
+    layout.connect_changed(move |layout| {
+        let value = if layout.get_active() == 1 {
+            messages::Layout::Compact
+        } else {
+            messages::Layout::Cozy
+        };
+        if !apply_setting(&app_clone, "layout", value.as_str()) {
+            let compact = stored(&app_clone, "layout") == messages::Layout::Compact.as_str();
+            layout.set_active(if compact { 1 } else { 0 });
+        }
+    });
+    add_row(&grid, &mut row, "Layout:", &layout);
+
+    add_check(app, &grid, &mut row, "time_on_hover", "Show time when hovering any message", settings.layout.time_on_hover);
+
+    let group_window = SpinButton::new_with_range(0.0, (settings::MAX_GROUP_WINDOW / 60) as f64, 1.0);
+    group_window.set_value((settings.layout.group_window / 60) as f64);
+    group_window.set_tooltip_text("Messages by the same author are grouped if sent within this many minutes. 0 disables grouping.");
+
+    let app_clone = Rc::clone(app);
+    group_window.connect_value_changed(move |spin| {
+        let seconds = spin.get_value_as_int() as i64 * 60;
+        if !apply_setting(&app_clone, "group_window", &seconds.to_string()) {
+            let seconds: i64 = stored(&app_clone, "group_window").parse().unwrap_or_default();
+            spin.set_value((seconds / 60) as f64);
+        }
+    });
+    add_row(&grid, &mut row, "Group messages within (minutes):", &group_window);
+
+    add_grid_heading(&grid, &mut row, "Appearance");
+
+    let theme = ComboBoxText::new();
+    theme.append_text("Follow GTK+ theme");
+    theme.append_text("Dark");
+    theme.append_text("Light");
+    theme.set_active(match settings.theme {
+        Theme::Auto => 0,
+        Theme::Dark => 1,
+        Theme::Light => 2
+    });
+
+    let app_clone = Rc::clone(app);
+    theme.connect_changed(move |theme| {
+        let value = match theme.get_active() {
+            1 => Theme::Dark,
+            2 => Theme::Light,
+            _ => Theme::Auto
+        };
+        if !apply_setting(&app_clone, "theme", value.as_str()) {
+            let active = match Theme::parse(&stored(&app_clone, "theme")) {
+                Some(Theme::Dark) => 1,
+                Some(Theme::Light) => 2,
+                _ => 0
+            };
+            theme.set_active(active);
+        }
+    });
+    add_row(&grid, &mut row, "Theme:", &theme);
+
+    let css_path = Entry::new();
+    css_path.set_text(&settings.css_path);
+    css_path.set_placeholder_text("Path to a custom stylesheet...");
+    css_path.set_hexpand(true);
+    css_path.set_tooltip_text("Press enter to apply. Leave empty to use the theme above.");
+
+    let app_clone = Rc::clone(app);
+    css_path.connect_activate(move |input| {
+        let text = input.get_text().unwrap_or_default();
+        if !apply_setting(&app_clone, "css_path", &text) {
+            input.set_text(&stored(&app_clone, "css_path"));
+        }
+    });
+    add_row(&grid, &mut row, "Custom CSS:", &css_path);
+
+    add_grid_heading(&grid, &mut row, "Network");
+
+    let proxy = Entry::new();
+    proxy.set_text(&settings.proxy);
+    proxy.set_placeholder_text("socks5://localhost:9050");
+    proxy.set_hexpand(true);
+    proxy.set_tooltip_text("Press enter to apply. Used for servers without their own proxy, \
+                            as socks5://[user:password@]host[:port] or http://host[:port]. \
+                            Leave empty to connect directly.");
+
+    let app_clone = Rc::clone(app);
+    proxy.connect_activate(move |input| {
+        let text = input.get_text().unwrap_or_default();
+        if !apply_setting(&app_clone, "proxy", &text) {
+            input.set_text(&stored(&app_clone, "proxy"));
+        }
+    });
+    add_row(&grid, &mut row, "Proxy:", &proxy);
+
+    add_grid_heading(&grid, &mut row, "Logging");
+    add_check(app, &grid, &mut row, "log_message_text", "Include message text in logs", settings.log_message_text);
+
+    add_grid_heading(&grid, &mut row, "Keyboard");
+
+    let shortcuts = Button::new_with_mnemonic("Edit _shortcuts...");
+    shortcuts.set_halign(Align::Start);
+    let app_clone = Rc::clone(app);
+    shortcuts.connect_clicked(move |_| shortcut_window::editor(&app_clone));
+    add_row(&grid, &mut row, "Shortcuts:", &shortcuts);
+
+    let close = Button::new_with_mnemonic("_Close");
+    close.set_halign(Align::End);
+    close.set_margin_top(10);
+
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    grid.attach(&close, 1, row, 1, 1);
+
+    window.add(&grid);
+    window.show_all();
+}
@@ -0,0 +1,246 @@
+use failure::Error;
+use messages::{Layout, LayoutSettings};
+use proxy::Proxy;
+use rusqlite::Connection as SqlConnection;
+use shortcuts::{self, Action, Binding, Shortcuts};
+use std::path::Path;
+
+#[derive(Debug, Fail)]
+pub enum SettingsError {
+    #[fail(display = "unknown setting: {}", _0)]
+    UnknownKey(String),
+    #[fail(display = "invalid value for {}: {:?}", _0, _1)]
+    InvalidValue(String, String)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Theme {
+    Auto,
+    Dark,
+    Light
+}
+impl Theme {
+    pub fn parse(input: &str) -> Option<Self> {
+        match input {
+            "auto" => Some(Theme::Auto),
+            "dark" => Some(Theme::Dark),
+            "light" => Some(Theme::Light),
+            _ => None
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Theme::Auto => "auto",
+            Theme::Dark => "dark",
+            Theme::Light => "light"
+        }
+    }
+}
+
+/// Every key `Settings` knows about, as stored in the `data` table.
+/// Shortcuts come on top of these, see `is_key`.
+pub const KEYS: &[&str] = &[
+    "notifications",
+    "notification_body",
+    "layout",
+    "time_on_hover",
+    "group_window",
+    "theme",
+    "css_path",
+    "log_message_text",
+    "proxy"
+];
+
+/// The longest grouping window allowed, in seconds.
+pub const MAX_GROUP_WINDOW: i64 = 60*60*24;
+
+/// Whether `Settings` knows about a key
+pub fn is_key(key: &str) -> bool {
+    KEYS.contains(&key) || shortcut_key(key).is_some()
+}
+/// The action of a shortcut setting, like "shortcut.search"
+fn shortcut_key(key: &str) -> Option<Action> {
+    if key.starts_with(shortcuts::PREFIX) {
+        Action::parse(&key[shortcuts::PREFIX.len()..])
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub notifications: bool,
+    pub notification_body: bool,
+    pub layout: LayoutSettings,
+    pub theme: Theme,
+    pub css_path: String,
+    pub log_message_text: bool,
+    /// The proxy URL for servers without their own, empty to connect directly
+    pub proxy: String,
+    pub shortcuts: Shortcuts
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            notifications: true,
+            notification_body: true,
+            layout: LayoutSettings::default(),
+            theme: Theme::Auto,
+            css_path: String::new(),
+            log_message_text: false,
+            proxy: String::new(),
+            shortcuts: Shortcuts::default()
+        }
+    }
+}
+
+fn parse_bool(input: &str) -> Option<bool> {
+    match input {
+        "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None
+    }
+}
+fn format_bool(input: bool) -> String {
+    String::from(if input { "1" } else { "0" })
+}
+
+impl Settings {
+    /// Load all settings from the database, falling back to the default
+    /// for anything that is missing or doesn't validate.
+    pub fn load(db: &SqlConnection) -> Self {
+        let mut settings = Self::default();
+
+        let mut stmt = db.prepare("SELECT key, value FROM data").unwrap();
+        let mut rows = stmt.query(&[]).unwrap();
+
+        while let Some(row) = rows.next() {
+            let row = row.unwrap();
+            let key: String = row.get(0);
+            let value: String = row.get(1);
+
+            if !is_key(&key) {
+                continue;
+            }
+            if let Err(err) = settings.set(&key, &value) {
+                warn!("ignoring setting: {}", err);
+            }
+        }
+
+        settings
+    }
+    /// Validate and set a single setting, without saving it.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingsError> {
+        let invalid = || SettingsError::InvalidValue(key.to_string(), value.to_string());
+
+        match key {
+            "notifications" => self.notifications = parse_bool(value).ok_or_else(invalid)?,
+            "notification_body" => self.notification_body = parse_bool(value).ok_or_else(invalid)?,
+            "layout" => self.layout.layout = Layout::parse(value).ok_or_else(invalid)?,
+            "time_on_hover" => self.layout.time_on_hover = parse_bool(value).ok_or_else(invalid)?,
+            "group_window" => {
+                let window: i64 = value.parse().map_err(|_| invalid())?;
+                if window < 0 || window > MAX_GROUP_WINDOW {
+                    return Err(invalid());
+                }
+                self.layout.group_window = window;
+            },
+            "theme" => self.theme = Theme::parse(value).ok_or_else(invalid)?,
+            "css_path" => {
+                if !value.is_empty() && !Path::new(value).is_file() {
+                    return Err(invalid());
+                }
+                self.css_path = value.to_string();
+            },
+            "log_message_text" => self.log_message_text = parse_bool(value).ok_or_else(invalid)?,
+            "proxy" => {
+                Proxy::parse(value).map_err(|_| invalid())?;
+                self.proxy = value.trim().to_string();
+            },
+            _ => match shortcut_key(key) {
+                // Empty to turn a shortcut off
+                Some(action) => self.shortcuts.set(action, if value.is_empty() {
+                    None
+                } else {
+                    Some(Binding::parse(value).ok_or_else(invalid)?)
+                }),
+                None => return Err(SettingsError::UnknownKey(key.to_string()))
+            }
+        }
+        Ok(())
+    }
+    pub fn get(&self, key: &str) -> Option<String> {
+        Some(match key {
+            "notifications" => format_bool(self.notifications),
+            "notification_body" => format_bool(self.notification_body),
+            "layout" => self.layout.layout.as_str().to_string(),
+            "time_on_hover" => format_bool(self.layout.time_on_hover),
+            "group_window" => self.layout.group_window.to_string(),
+            "theme" => self.theme.as_str().to_string(),
+            "css_path" => self.css_path.clone(),
+            "log_message_text" => format_bool(self.log_message_text),
+            "proxy" => self.proxy.clone(),
+            _ => {
+                let action = shortcut_key(key)?;
+                self.shortcuts.get(action).map(|binding| binding.to_string()).unwrap_or_default()
+            }
+        })
+    }
+    /// Validate, set and save a single setting, the way `get` would write it.
+    /// Nothing changes unless it's saved.
+    pub fn update(&mut self, db: &SqlConnection, key: &str, value: &str) -> Result<(), Error> {
+        let mut updated = self.clone();
+        updated.set(key, value)?;
+        let value = updated.get(key).unwrap_or_else(|| value.to_string());
+        db.execute("REPLACE INTO data (key, value) VALUES (?, ?)", &[&key, &value])?;
+        *self = updated;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut db = SqlConnection::open_in_memory().unwrap();
+    ::migrations::migrate(&mut db, None).unwrap();
+    db.execute("INSERT INTO data (key, value) VALUES ('nick', 'test'), ('layout', 'compact'), ('group_window', '-1')", &[]).unwrap();
+
+    let mut settings = Settings::load(&db);
+    assert_eq!(settings.layout.layout, Layout::Compact);
+    assert_eq!(settings.layout.group_window, LayoutSettings::default().group_window);
+
+    assert!(settings.set("theme", "purple").is_err());
+    assert!(settings.set("group_window", "86401").is_err());
+    assert!(settings.set("no_such_key", "1").is_err());
+    assert!(settings.set("css_path", "/definitely/not/a/file.css").is_err());
+    assert!(settings.set("proxy", "localhost:9050").is_err());
+
+    settings.update(&db, "notifications", "0").unwrap();
+    settings.update(&db, "theme", "dark").unwrap();
+    assert!(settings.update(&db, "theme", "purple").is_err());
+    assert_eq!(settings.theme, Theme::Dark);
+    settings.update(&db, "proxy", " socks5://localhost:9050 ").unwrap();
+    let stored: String = db.query_row("SELECT value FROM data WHERE key = 'proxy'", &[], |row| row.get(0)).unwrap();
+    assert_eq!(stored, "socks5://localhost:9050");
+
+    let settings = Settings::load(&db);
+    assert!(!settings.notifications);
+    assert_eq!(settings.theme, Theme::Dark);
+
+    for key in KEYS {
+        let value = settings.get(key).unwrap();
+        assert!(Settings::default().set(key, &value).is_ok(), "{} doesn't round-trip", key);
+    }
+
+    let mut settings = Settings::load(&db);
+    assert!(settings.set("shortcut.search", "Shift+f").is_err());
+    assert!(settings.set("shortcut.nothing", "Ctrl+f").is_err());
+    settings.update(&db, "shortcut.search", "Alt+s").unwrap();
+    settings.update(&db, "shortcut.quick_switcher", "").unwrap();
+
+    let settings = Settings::load(&db);
+    assert_eq!(settings.get("shortcut.search"), Some(String::from("Alt+s")));
+    assert_eq!(settings.shortcuts.get(Action::QuickSwitcher), None);
+    assert_eq!(settings.get("shortcut.quick_switcher"), Some(String::new()));
+    assert_eq!(settings.get("shortcut.cheat_sheet"), Some(String::from("Ctrl+Shift+question")));
+}
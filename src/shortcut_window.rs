@@ -0,0 +1,253 @@
+use ::*;
+use gdk::enums::key;
+use gtk::Grid;
+use shortcuts::{Action as Shortcut, Binding, ACTIONS};
+use std::cell::Cell;
+
+/// The shortcut a key press would be, if it isn't just a modifier
+pub(crate) fn binding(event: &gdk::EventKey) -> Option<Binding> {
+    let name = gdk::keyval_name(gdk::keyval_to_lower(event.get_keyval()))?;
+    let modifier = ["Shift", "Control", "Alt", "Meta", "Super", "ISO_Level3"].iter()
+        .any(|prefix| name.starts_with(prefix));
+    if modifier {
+        return None;
+    }
+    let state = event.get_state();
+    Some(Binding {
+        ctrl: state.contains(ModifierType::CONTROL_MASK),
+        alt: state.contains(ModifierType::MOD1_MASK),
+        shift: state.contains(ModifierType::SHIFT_MASK),
+        key: name
+    })
+}
+/// A binding the way GTK+ shows it, like "Ctrl+Shift+?"
+fn label(binding: Option<&Binding>) -> String {
+    let binding = match binding {
+        Some(binding) => binding,
+        None => return String::from("Disabled")
+    };
+    let mut mods = ModifierType::empty();
+    if binding.ctrl {
+        mods |= ModifierType::CONTROL_MASK;
+    }
+    if binding.alt {
+        mods |= ModifierType::MOD1_MASK;
+    }
+    if binding.shift {
+        mods |= ModifierType::SHIFT_MASK;
+    }
+    gtk::accelerator_get_label(gdk::keyval_from_name(&binding.key), mods)
+        .unwrap_or_else(|| binding.to_string())
+}
+fn setting(action: Shortcut) -> String {
+    format!("{}{}", shortcuts::PREFIX, action.as_str())
+}
+
+/// List every shortcut
+pub(crate) fn cheat_sheet(app: &Rc<App>) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Keyboard shortcuts");
+    window.set_transient_for(&app.window);
+    window.set_modal(true);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+
+    let grid = Grid::new();
+    grid.set_property_margin(10);
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(20);
+
+    let shortcuts = app.settings.borrow().shortcuts.clone();
+    for (row, &action) in ACTIONS.iter().enumerate() {
+        let name = Label::new(action.describe());
+        name.set_xalign(0.0);
+        grid.attach(&name, 0, row as i32, 1, 1);
+
+        let keys = Label::new(&*label(shortcuts.get(action)));
+        keys.set_xalign(0.0);
+        add_class(&keys, "bold");
+        grid.attach(&keys, 1, row as i32, 1, 1);
+    }
+    // Handled by the message box itself, so it can't be changed
+    let row = ACTIONS.len() as i32;
+    let name = Label::new("Edit your last message, from the message box");
+    name.set_xalign(0.0);
+    grid.attach(&name, 0, row, 1, 1);
+    let keys = Label::new(&*gtk::accelerator_get_label(key::Up, ModifierType::empty()).unwrap_or_else(|| String::from("Up")));
+    keys.set_xalign(0.0);
+    add_class(&keys, "bold");
+    grid.attach(&keys, 1, row, 1, 1);
+
+    let controls = GtkBox::new(Orientation::Horizontal, 2);
+    controls.set_halign(Align::End);
+    controls.set_margin_top(10);
+
+    let edit = Button::new_with_mnemonic("_Change...");
+    let app_clone = Rc::clone(app);
+    let window_clone = window.clone();
+    edit.connect_clicked(move |_| {
+        window_clone.destroy();
+        editor(&app_clone);
+    });
+    controls.add(&edit);
+
+    let close = Button::new_with_mnemonic("_Close");
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    controls.add(&close);
+    grid.attach(&controls, 0, row + 1, 2, 1);
+
+    window.connect_key_press_event(|window, event| {
+        if event.get_keyval() == key::Escape {
+            window.destroy();
+            return Inhibit(true);
+        }
+        Inhibit(false)
+    });
+
+    window.add(&grid);
+    window.show_all();
+}
+
+struct Editor {
+    app: Rc<App>,
+    buttons: Vec<(Shortcut, Button)>,
+    message: Label,
+    /// The action waiting for a key press
+    waiting: Cell<Option<Shortcut>>
+}
+impl Editor {
+    fn refresh(&self) {
+        let shortcuts = self.app.settings.borrow().shortcuts.clone();
+        for &(action, ref button) in &self.buttons {
+            button.set_label(&label(shortcuts.get(action)));
+        }
+    }
+    fn key_press(&self, event: &gdk::EventKey) -> Inhibit {
+        let action = match self.waiting.get() {
+            Some(action) => action,
+            None => return Inhibit(false)
+        };
+
+        let binding = match binding(event) {
+            Some(binding) => binding,
+            None => return Inhibit(true)
+        };
+        let bare = !binding.ctrl && !binding.alt && !binding.shift;
+        let value = if bare && binding.key == "BackSpace" {
+            String::new()
+        } else if bare && binding.key == "Escape" {
+            self.waiting.set(None);
+            self.message.set_text("");
+            self.refresh();
+            return Inhibit(true);
+        } else {
+            // Check it the same way as when it's loaded
+            match Binding::parse(&binding.to_string()) {
+                Some(binding) => binding.to_string(),
+                None => {
+                    self.message.set_text("That key needs Ctrl or Alt, so it doesn't get in the way of typing.");
+                    return Inhibit(true);
+                }
+            }
+        };
+        self.waiting.set(None);
+
+        // A key can only do one thing
+        let taken = Binding::parse(&value).and_then(|binding| self.app.settings.borrow().shortcuts.find(&binding));
+        let mut message = String::new();
+        match taken {
+            Some(other) if other != action => if apply_setting(&self.app, &setting(other), "") {
+                message = format!("\"{}\" no longer has a shortcut.", other.describe());
+            },
+            _ => ()
+        }
+        apply_setting(&self.app, &setting(action), &value);
+
+        self.message.set_text(&message);
+        self.refresh();
+        Inhibit(true)
+    }
+}
+
+/// Change the keyboard shortcuts
+pub(crate) fn editor(app: &Rc<App>) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Edit keyboard shortcuts");
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(450, 0);
+
+    let container = GtkBox::new(Orientation::Vertical, 6);
+    container.set_property_margin(10);
+
+    let help = Label::new("Click a shortcut and press the new keys. Backspace turns it off, Escape cancels.");
+    help.set_xalign(0.0);
+    help.set_line_wrap(true);
+    container.add(&help);
+
+    let grid = Grid::new();
+    grid.set_row_spacing(2);
+    grid.set_column_spacing(10);
+
+    let mut buttons = Vec::with_capacity(ACTIONS.len());
+    for (row, &action) in ACTIONS.iter().enumerate() {
+        let name = Label::new(action.describe());
+        name.set_xalign(0.0);
+        name.set_hexpand(true);
+        grid.attach(&name, 0, row as i32, 1, 1);
+
+        let button = Button::new();
+        grid.attach(&button, 1, row as i32, 1, 1);
+        buttons.push((action, button));
+    }
+    container.add(&grid);
+
+    let message = Label::new("");
+    message.set_xalign(0.0);
+    message.set_line_wrap(true);
+    container.add(&message);
+
+    let editor = Rc::new(Editor {
+        app: Rc::clone(app),
+        buttons: buttons,
+        message: message,
+        waiting: Cell::new(None)
+    });
+    editor.refresh();
+
+    for &(action, ref button) in &editor.buttons {
+        let editor_clone = Rc::clone(&editor);
+        button.connect_clicked(move |button| {
+            editor_clone.refresh();
+            editor_clone.waiting.set(Some(action));
+            button.set_label("Press a key...");
+        });
+    }
+
+    let editor_clone = Rc::clone(&editor);
+    window.connect_key_press_event(move |_, event| editor_clone.key_press(event));
+
+    let controls = GtkBox::new(Orientation::Horizontal, 2);
+    controls.set_halign(Align::End);
+
+    let reset = Button::new_with_mnemonic("_Reset to defaults");
+    let editor_clone = Rc::clone(&editor);
+    reset.connect_clicked(move |_| {
+        for &action in ACTIONS {
+            apply_setting(&editor_clone.app, &setting(action), &action.default_binding().to_string());
+        }
+        editor_clone.waiting.set(None);
+        editor_clone.message.set_text("");
+        editor_clone.refresh();
+    });
+    controls.add(&reset);
+
+    let close = Button::new_with_mnemonic("_Close");
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    controls.add(&close);
+    container.add(&controls);
+
+    window.add(&container);
+    window.show_all();
+}
@@ -0,0 +1,233 @@
+//! Keyboard shortcuts: what they do, their default keys, and parsing the ones that are saved.
+//! Keys are named like GDK does, such as "k", "Down" or "question", with lowercase letters.
+//! Without Ctrl or Alt, only function keys and Escape can be bound.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Settings keys for shortcuts start with this, followed by the action
+pub const PREFIX: &str = "shortcut.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    NextChannel,
+    PreviousChannel,
+    NextUnread,
+    NextServer,
+    FocusComposer,
+    ToggleUsers,
+    EditLast,
+    MarkAllRead,
+    Search,
+    QuickSwitcher,
+    CheatSheet
+}
+
+/// Every action, in the order they're listed
+pub const ACTIONS: &[Action] = &[
+    Action::NextChannel,
+    Action::PreviousChannel,
+    Action::NextUnread,
+    Action::NextServer,
+    Action::FocusComposer,
+    Action::ToggleUsers,
+    Action::EditLast,
+    Action::MarkAllRead,
+    Action::Search,
+    Action::QuickSwitcher,
+    Action::CheatSheet
+];
+
+impl Action {
+    pub fn parse(input: &str) -> Option<Self> {
+        ACTIONS.iter().cloned().find(|action| action.as_str() == input)
+    }
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Action::NextChannel => "next_channel",
+            Action::PreviousChannel => "previous_channel",
+            Action::NextUnread => "next_unread",
+            Action::NextServer => "next_server",
+            Action::FocusComposer => "focus_composer",
+            Action::ToggleUsers => "toggle_users",
+            Action::EditLast => "edit_last",
+            Action::MarkAllRead => "mark_all_read",
+            Action::Search => "search",
+            Action::QuickSwitcher => "quick_switcher",
+            Action::CheatSheet => "cheat_sheet"
+        }
+    }
+    pub fn describe(&self) -> &'static str {
+        match *self {
+            Action::NextChannel => "Next channel",
+            Action::PreviousChannel => "Previous channel",
+            Action::NextUnread => "Next channel with unread messages",
+            Action::NextServer => "Next server",
+            Action::FocusComposer => "Write a message",
+            Action::ToggleUsers => "Show or hide the user list",
+            Action::EditLast => "Edit your last message",
+            Action::MarkAllRead => "Mark everything on this server as read",
+            Action::Search => "Search messages in this channel",
+            Action::QuickSwitcher => "Jump to a channel",
+            Action::CheatSheet => "Show keyboard shortcuts"
+        }
+    }
+    pub fn default_binding(&self) -> Binding {
+        let (ctrl, alt, shift, key) = match *self {
+            Action::NextChannel => (false, true, false, "Down"),
+            Action::PreviousChannel => (false, true, false, "Up"),
+            Action::NextUnread => (false, true, true, "Down"),
+            Action::NextServer => (true, false, false, "Tab"),
+            Action::FocusComposer => (true, false, false, "l"),
+            Action::ToggleUsers => (true, false, false, "u"),
+            Action::EditLast => (true, false, false, "e"),
+            Action::MarkAllRead => (false, false, true, "Escape"),
+            Action::Search => (true, false, false, "f"),
+            Action::QuickSwitcher => (true, false, false, "k"),
+            Action::CheatSheet => (true, false, true, "question")
+        };
+        Binding {
+            ctrl: ctrl,
+            alt: alt,
+            shift: shift,
+            key: key.to_string()
+        }
+    }
+}
+
+/// A key with modifiers, written like "Ctrl+Shift+question"
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub key: String
+}
+impl Binding {
+    /// Parse a binding. Without Ctrl or Alt, only keys that don't type anything are allowed,
+    /// so shortcuts don't get in the way of writing messages.
+    pub fn parse(input: &str) -> Option<Self> {
+        let mut binding = Binding {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            key: String::new()
+        };
+        let mut parts = input.trim().split('+').collect::<Vec<_>>();
+        let key = parts.pop()?;
+        for modifier in parts {
+            match modifier {
+                "Ctrl" => binding.ctrl = true,
+                "Alt" => binding.alt = true,
+                "Shift" => binding.shift = true,
+                _ => return None
+            }
+        }
+        if key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return None;
+        }
+        binding.key = if key.chars().count() == 1 { key.to_lowercase() } else { key.to_string() };
+
+        if !binding.ctrl && !binding.alt && !binding.is_special() {
+            return None;
+        }
+        Some(binding)
+    }
+    /// Keys that don't type text, like Escape or F5
+    fn is_special(&self) -> bool {
+        let function = self.key.starts_with('F') && self.key.len() > 1 && self.key[1..].chars().all(|c| c.is_digit(10));
+        function || self.key == "Escape"
+    }
+}
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        if self.alt {
+            write!(f, "Alt+")?;
+        }
+        if self.shift {
+            write!(f, "Shift+")?;
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+/// The key for each action. Actions can also have no key at all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shortcuts {
+    bindings: HashMap<Action, Option<Binding>>
+}
+impl Default for Shortcuts {
+    fn default() -> Self {
+        Shortcuts {
+            bindings: ACTIONS.iter().map(|&action| (action, Some(action.default_binding()))).collect()
+        }
+    }
+}
+impl Shortcuts {
+    pub fn get(&self, action: Action) -> Option<&Binding> {
+        self.bindings.get(&action).and_then(|binding| binding.as_ref())
+    }
+    pub fn set(&mut self, action: Action, binding: Option<Binding>) {
+        self.bindings.insert(action, binding);
+    }
+    /// Which action a key press is for
+    pub fn find(&self, binding: &Binding) -> Option<Action> {
+        ACTIONS.iter().cloned().find(|&action| self.get(action) == Some(binding))
+    }
+}
+
+/// The item after `current`, or before it going backwards, wrapping around.
+/// Without a current item, it's the first or last one.
+pub fn cycle<T: Copy + PartialEq>(items: &[T], current: Option<T>, forward: bool) -> Option<T> {
+    if items.is_empty() {
+        return None;
+    }
+    let len = items.len();
+    let i = match current.and_then(|current| items.iter().position(|&item| item == current)) {
+        Some(i) if forward => (i + 1) % len,
+        Some(i) => (i + len - 1) % len,
+        None if forward => 0,
+        None => len - 1
+    };
+    Some(items[i])
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let binding = Binding::parse("Ctrl+Shift+question").unwrap();
+    assert!(binding.ctrl && binding.shift && !binding.alt);
+    assert_eq!(binding.key, "question");
+    assert_eq!(binding.to_string(), "Ctrl+Shift+question");
+    assert_eq!(Binding::parse("Alt+K").unwrap().to_string(), "Alt+k");
+    assert_eq!(Binding::parse("F5").unwrap().to_string(), "F5");
+
+    assert_eq!(Binding::parse(""), None);
+    assert_eq!(Binding::parse("Ctrl+"), None);
+    assert_eq!(Binding::parse("Super+k"), None);
+    assert_eq!(Binding::parse("Shift+k"), None);
+    assert_eq!(Binding::parse("Fancy"), None);
+
+    for &action in ACTIONS {
+        assert_eq!(Action::parse(action.as_str()), Some(action));
+        let binding = action.default_binding();
+        assert_eq!(Binding::parse(&binding.to_string()), Some(binding), "{:?} default doesn't parse", action);
+    }
+
+    let mut shortcuts = Shortcuts::default();
+    assert_eq!(shortcuts.find(&Binding::parse("Ctrl+k").unwrap()), Some(Action::QuickSwitcher));
+    shortcuts.set(Action::QuickSwitcher, None);
+    assert_eq!(shortcuts.find(&Binding::parse("Ctrl+k").unwrap()), None);
+    assert_eq!(shortcuts.get(Action::QuickSwitcher), None);
+
+    let items = [3, 1, 2];
+    assert_eq!(cycle(&items, Some(1), true), Some(2));
+    assert_eq!(cycle(&items, Some(2), true), Some(3));
+    assert_eq!(cycle(&items, Some(3), false), Some(2));
+    assert_eq!(cycle(&items, None, true), Some(3));
+    assert_eq!(cycle(&items, Some(7), false), Some(2));
+    assert_eq!(cycle::<usize>(&[], None, true), None);
+}
@@ -0,0 +1,145 @@
+//! Fuzzy search over every channel on every connected server, for the quick switcher.
+
+use connections::ServerId;
+
+/// How many channels are remembered as recently used
+const RECENT_MAX: usize = 50;
+/// Added to the score of channels with unread messages
+const UNREAD_BONUS: i64 = 8;
+/// Added to the score of the last channel used, one less for each one before it
+const RECENT_BONUS: i64 = 12;
+
+/// Recently used channels, latest first
+#[derive(Debug, Default)]
+pub struct Recent {
+    channels: Vec<(ServerId, usize)>
+}
+impl Recent {
+    pub fn new() -> Self {
+        Recent::default()
+    }
+    pub fn touch(&mut self, server: ServerId, channel: usize) {
+        self.channels.retain(|&entry| entry != (server, channel));
+        self.channels.insert(0, (server, channel));
+        self.channels.truncate(RECENT_MAX);
+    }
+    /// How many other channels were used since, if it was used at all
+    pub fn get(&self, server: ServerId, channel: usize) -> Option<usize> {
+        self.channels.iter().position(|&entry| entry == (server, channel))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub server: ServerId,
+    pub channel: usize,
+    /// Like "#general" or "someone"
+    pub name: String,
+    pub server_name: String,
+    pub unread: usize,
+    pub recent: Option<usize>
+}
+
+/// How well `query` matches `text`, or `None` if it doesn't.
+/// Every character of the query must appear in order, ignoring case.
+/// Matches at the start of words and runs of matches score higher.
+pub fn score(query: &str, text: &str) -> Option<i64> {
+    let mut query = query.chars().flat_map(char::to_lowercase).filter(|c| !c.is_whitespace()).peekable();
+    let mut score = 0;
+    let mut previous: Option<char> = None;
+    let mut run = false;
+
+    for c in text.chars() {
+        let wanted = match query.peek() {
+            Some(&wanted) => wanted,
+            None => break
+        };
+        let word_start = previous.map(|previous| !previous.is_alphanumeric()).unwrap_or(true);
+        previous = Some(c);
+
+        if c.to_lowercase().any(|c| c == wanted) {
+            query.next();
+            score += 1;
+            if word_start {
+                score += 5;
+            }
+            if run {
+                score += 3;
+            }
+            run = true;
+        } else {
+            run = false;
+        }
+    }
+
+    if query.peek().is_some() {
+        None
+    } else {
+        Some(score)
+    }
+}
+
+/// Filter and sort channels for `query`, best first.
+/// Without a query, it's just the recently used ones first, then the unread ones.
+pub fn rank(query: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+    let mut ranked: Vec<_> = candidates.into_iter()
+        .filter_map(|candidate| {
+            let text = format!("{} {}", candidate.name, candidate.server_name);
+            score(query, &text).map(|score| {
+                let mut score = score;
+                if candidate.unread > 0 {
+                    score += UNREAD_BONUS;
+                }
+                if let Some(recent) = candidate.recent {
+                    score += (RECENT_BONUS - recent as i64).max(0);
+                }
+                (score, candidate)
+            })
+        })
+        .collect();
+
+    ranked.sort_by(|&(score_a, ref a), &(score_b, ref b)| {
+        score_b.cmp(&score_a)
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.server_name.cmp(&b.server_name))
+    });
+    ranked.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    assert_eq!(score("", "#general"), Some(0));
+    assert_eq!(score("gnrl", "#general"), Some(9));
+    assert_eq!(score("GEN", "#general"), Some(14));
+    assert_eq!(score("lg", "#general"), None);
+    assert!(score("dev", "#dev") > score("dev", "#dependencies-overview"));
+
+    let mut recent = Recent::new();
+    recent.touch(1, 1);
+    recent.touch(2, 5);
+    recent.touch(1, 1);
+    assert_eq!((recent.get(1, 1), recent.get(2, 5), recent.get(1, 2)), (Some(0), Some(1), None));
+
+    let candidate = |server, channel, name: &str, unread| Candidate {
+        server: server,
+        channel: channel,
+        name: name.to_string(),
+        server_name: format!("server {}", server),
+        unread: unread,
+        recent: recent.get(server, channel)
+    };
+    let candidates = vec![
+        candidate(1, 1, "#general", 0),
+        candidate(1, 2, "#random", 3),
+        candidate(2, 5, "#general", 0),
+        candidate(2, 6, "someone", 0)
+    ];
+
+    let names = |ranked: Vec<Candidate>| ranked.into_iter().map(|c| (c.server, c.channel)).collect::<Vec<_>>();
+    assert_eq!(names(rank("", candidates.clone())), vec![(1, 1), (2, 5), (1, 2), (2, 6)]);
+    assert_eq!(names(rank("gen", candidates.clone())), vec![(1, 1), (2, 5)]);
+    // Searching the server name works too
+    assert_eq!(names(rank("gen 2", candidates.clone())), vec![(2, 5)]);
+    assert_eq!(names(rank("xyz", candidates)), vec![]);
+}
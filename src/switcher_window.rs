@@ -0,0 +1,148 @@
+use ::*;
+use gdk::enums::key;
+use switcher::{self, Candidate};
+
+/// Every channel and private channel on every connected server
+fn candidates(app: &Rc<App>) -> Vec<Candidate> {
+    let mut server_names = HashMap::new();
+    match app.db.prepare("SELECT id, name, account FROM servers") {
+        Ok(mut stmt) => {
+            let rows = stmt.query_map(&[], |row| {
+                (row.get::<_, ServerId>(0), server_label(&row.get::<_, String>(1), &row.get::<_, String>(2)))
+            });
+            if let Ok(rows) = rows {
+                server_names.extend(rows.filter_map(|row| row.ok()));
+            }
+        },
+        Err(err) => warn!("failed to load server names: {}", err)
+    }
+
+    let recent = app.recent.borrow();
+    let mut candidates = Vec::new();
+    app.connections.foreach(|synac| {
+        let server_name = server_names.get(&synac.id).cloned().unwrap_or_else(|| synac.host.clone());
+        let mut add = |channel: usize, name: String| candidates.push(Candidate {
+            server: synac.id,
+            channel: channel,
+            name: name,
+            server_name: server_name.clone(),
+            unread: synac.unread.get(channel),
+            recent: recent.get(synac.id, channel)
+        });
+
+        for channel in synac.state.channels.values().filter(|channel| !channel.private) {
+            add(channel.id, format!("#{}", channel.name));
+        }
+        for dm in dms::list(&synac.state, &synac.messages, &synac.unread, synac.user) {
+            add(dm.channel, dm.name);
+        }
+    });
+    candidates
+}
+
+/// Show a channel, switching server first if needed
+fn open(app: &Rc<App>, server: ServerId, channel: usize) {
+    if *app.connections.current_server.lock().unwrap() != Some(server) {
+        select_server(app, server);
+    }
+    app.connections.execute(server, |result| if let Ok(synac) = result {
+        let private = synac.state.channels.get(&channel).map(|channel| channel.private).unwrap_or(false);
+        if private {
+            if let Err(err) = dms::unhide(&app.db, &synac.host, channel) {
+                warn!("failed to reopen conversation: {}", err);
+            }
+        }
+        select_channel(app, synac, channel);
+    });
+    app.dirty.borrow_mut().mark(server, &Render::Channels);
+}
+
+fn fill(app: &Rc<App>, window: &Window, results: &GtkBox, query: &str, candidates: &[Candidate]) {
+    for child in results.get_children() {
+        results.remove(&child);
+    }
+
+    let ranked = switcher::rank(query, candidates.to_vec());
+    if ranked.is_empty() {
+        let label = Label::new("Nothing matches.");
+        label.set_xalign(0.0);
+        results.add(&label);
+    }
+    for candidate in ranked {
+        let mut text = format!("{}  ({})", candidate.name, candidate.server_name);
+        if candidate.unread > 0 {
+            write!(text, " - {} unread", candidate.unread).unwrap();
+        }
+
+        let button = Button::new_with_label(&text);
+        button.set_relief(ReliefStyle::None);
+        if let Some(label) = button.get_child() {
+            label.set_halign(Align::Start);
+        }
+        let app_clone = Rc::clone(app);
+        let window_clone = window.clone();
+        button.connect_clicked(move |_| {
+            window_clone.destroy();
+            open(&app_clone, candidate.server, candidate.channel);
+        });
+        results.add(&button);
+    }
+    results.show_all();
+}
+
+/// Open the quick switcher, to jump to any channel on any connected server
+pub(crate) fn show(app: &Rc<App>) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Switch channel");
+    window.set_transient_for(&app.window);
+    window.set_modal(true);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(450, 350);
+
+    let container = GtkBox::new(Orientation::Vertical, 6);
+    container.set_property_margin(10);
+
+    let search = Entry::new();
+    search.set_placeholder_text("Jump to a channel...");
+    container.add(&search);
+
+    let results = GtkBox::new(Orientation::Vertical, 0);
+    let scroll = ScrolledWindow::new(None, None);
+    scroll.set_vexpand(true);
+    scroll.add(&results);
+    container.add(&scroll);
+
+    let candidates = Rc::new(candidates(app));
+    fill(app, &window, &results, "", &candidates);
+
+    let app_clone = Rc::clone(app);
+    let window_clone = window.clone();
+    let results_clone = results.clone();
+    let candidates_clone = Rc::clone(&candidates);
+    search.connect_changed(move |search| {
+        let query = search.get_text().unwrap_or_default();
+        fill(&app_clone, &window_clone, &results_clone, &query, &candidates_clone);
+    });
+
+    // Enter picks the best match
+    let app_clone = Rc::clone(app);
+    let window_clone = window.clone();
+    search.connect_activate(move |search| {
+        let query = search.get_text().unwrap_or_default();
+        if let Some(best) = switcher::rank(&query, candidates.to_vec()).into_iter().next() {
+            window_clone.destroy();
+            open(&app_clone, best.server, best.channel);
+        }
+    });
+
+    window.connect_key_press_event(|window, event| {
+        if event.get_keyval() == key::Escape {
+            window.destroy();
+            return Inhibit(true);
+        }
+        Inhibit(false)
+    });
+
+    window.add(&container);
+    window.show_all();
+}
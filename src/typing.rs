@@ -21,6 +21,10 @@ impl Typing {
         if self.last_checked.elapsed() < typing_check {
             return None;
         }
+        Some(self.text(channel, state))
+    }
+    /// Like `check`, but without waiting for the next second
+    pub fn text(&mut self, channel: Option<usize>, state: &State) -> String {
         self.last_checked = Instant::now();
         let typing_timeout = Duration::from_secs(common::TYPING_TIMEOUT as u64); // TODO: const fn
 
@@ -36,7 +40,7 @@ impl Typing {
             })
             .collect();
 
-        Some(match people.len() {
+        match people.len() {
             n if n > 500 => String::from("(╯°□°）╯︵ ┻━┻"),
             n if n > 100 => String::from("A crap ton of people are typing"),
             n if n > 50 => String::from("Over 50 people are typing"),
@@ -46,6 +50,6 @@ impl Typing {
             2 => format!("{} and {} are typing", people[0], people[1]),
             1 => format!("{} is typing", people[0]),
             _ => String::new()
-        })
+        }
     }
 }
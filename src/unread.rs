@@ -0,0 +1,36 @@
+//! Unread message counts per channel, for one server.
+
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct Unread {
+    counts: HashMap<usize, usize>
+}
+impl Unread {
+    pub fn new() -> Self {
+        Unread::default()
+    }
+    /// Count a new message from someone else, in a channel that isn't shown
+    pub fn add(&mut self, channel: usize) {
+        *self.counts.entry(channel).or_insert(0) += 1;
+    }
+    pub fn clear(&mut self, channel: usize) {
+        self.counts.remove(&channel);
+    }
+    pub fn get(&self, channel: usize) -> usize {
+        self.counts.get(&channel).cloned().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut unread = Unread::new();
+    unread.add(1);
+    unread.add(1);
+    unread.add(2);
+    assert_eq!((unread.get(1), unread.get(2), unread.get(3)), (2, 1, 0));
+
+    unread.clear(1);
+    assert_eq!(unread.get(1), 0);
+}
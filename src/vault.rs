@@ -0,0 +1,279 @@
+//! Optional encrypted storage for login tokens.
+//! Tokens are sealed with AES-256-GCM, with a key derived from a passphrase using scrypt.
+//! The key is only kept in memory while the vault is unlocked.
+
+use connections::ServerId;
+use failure::Error;
+use openssl::pkcs5;
+use openssl::rand;
+use openssl::symm::{self, Cipher};
+use rusqlite::Connection as SqlConnection;
+use std::ptr;
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+// Roughly 32 MiB and a tenth of a second per unlock
+const SCRYPT_N: u64 = 1 << 15;
+const SCRYPT_R: u64 = 8;
+const SCRYPT_P: u64 = 1;
+const SCRYPT_MAXMEM: u64 = 64 * 1024 * 1024;
+
+/// Sealed with the key when the vault is set up, to tell a wrong passphrase apart
+const VERIFIER: &[u8] = b"synac vault";
+
+#[derive(Debug, Fail)]
+pub enum VaultError {
+    #[fail(display = "the vault is locked")]
+    Locked,
+    #[fail(display = "the vault isn't set up")]
+    NotEnabled,
+    #[fail(display = "the vault is already set up")]
+    AlreadyEnabled,
+    #[fail(display = "wrong passphrase")]
+    WrongPassphrase,
+    #[fail(display = "corrupt vault data")]
+    Corrupt
+}
+
+/// A derived key, wiped from memory when dropped
+struct Key([u8; KEY_LEN]);
+impl Drop for Key {
+    fn drop(&mut self) {
+        for byte in self.0.iter_mut() {
+            unsafe { ptr::write_volatile(byte, 0); }
+        }
+    }
+}
+impl Key {
+    fn derive(passphrase: &str, salt: &[u8]) -> Result<Self, Error> {
+        let mut key = Key([0; KEY_LEN]);
+        pkcs5::scrypt(passphrase.as_bytes(), salt, SCRYPT_N, SCRYPT_R, SCRYPT_P, SCRYPT_MAXMEM, &mut key.0)?;
+        Ok(key)
+    }
+    /// Encrypt `data`, bound to `context` so it can't be moved elsewhere.
+    /// The output is the nonce, the ciphertext and then the tag.
+    fn seal(&self, context: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0; NONCE_LEN];
+        rand::rand_bytes(&mut nonce)?;
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = symm::encrypt_aead(Cipher::aes_256_gcm(), &self.0, Some(&nonce[..]), context, data, &mut tag)?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len() + TAG_LEN);
+        sealed.extend(&nonce);
+        sealed.extend(ciphertext);
+        sealed.extend(&tag);
+        Ok(sealed)
+    }
+    fn open(&self, context: &[u8], sealed: &[u8]) -> Result<Vec<u8>, VaultError> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err(VaultError::Corrupt);
+        }
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        symm::decrypt_aead(Cipher::aes_256_gcm(), &self.0, Some(nonce), context, ciphertext, tag)
+            .map_err(|_| VaultError::WrongPassphrase)
+    }
+}
+
+fn token_context(id: ServerId) -> Vec<u8> {
+    format!("token {}", id).into_bytes()
+}
+/// Run `f` in a transaction. `Connection::transaction` needs a mutable connection,
+/// which the shared one isn't.
+fn transaction<F>(db: &SqlConnection, f: F) -> Result<(), Error>
+    where F: FnOnce() -> Result<(), Error>
+{
+    db.execute_batch("BEGIN")?;
+    match f() {
+        Ok(()) => Ok(db.execute_batch("COMMIT")?),
+        Err(err) => {
+            db.execute_batch("ROLLBACK")?;
+            Err(err)
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Vault {
+    /// The salt the key is derived with, if the vault is set up
+    salt: Option<Vec<u8>>,
+    key: Option<Key>,
+    /// The user chose not to unlock it, so don't keep asking this session
+    skipped: bool
+}
+impl Vault {
+    /// Load the vault from the database. It always starts out locked.
+    pub fn load(db: &SqlConnection) -> Result<Self, Error> {
+        let mut stmt = db.prepare("SELECT salt FROM vault")?;
+        let mut rows = stmt.query(&[])?;
+
+        let salt = match rows.next() {
+            Some(row) => Some(row?.get(0)),
+            None => None
+        };
+        Ok(Vault {
+            salt: salt,
+            key: None,
+            skipped: false
+        })
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.salt.is_some()
+    }
+    pub fn is_unlocked(&self) -> bool {
+        self.key.is_some()
+    }
+    /// Whether connecting to a server with a sealed token should ask for the passphrase
+    pub fn should_ask(&self) -> bool {
+        self.is_enabled() && !self.is_unlocked() && !self.skipped
+    }
+    /// Stop asking for the passphrase on connect, falling back to passwords
+    pub fn skip(&mut self) {
+        self.skipped = true;
+    }
+    /// Set up the vault with a passphrase, encrypting all saved tokens.
+    /// The vault is left unlocked.
+    pub fn enable(&mut self, db: &SqlConnection, passphrase: &str) -> Result<(), Error> {
+        if self.is_enabled() {
+            return Err(VaultError::AlreadyEnabled.into());
+        }
+        let mut salt = vec![0; SALT_LEN];
+        rand::rand_bytes(&mut salt)?;
+        let key = Key::derive(passphrase, &salt)?;
+        let verifier = key.seal(b"verifier", VERIFIER)?;
+
+        transaction(db, || {
+            db.execute("INSERT INTO vault (id, salt, verifier) VALUES (1, ?, ?)", &[&salt, &verifier])?;
+
+            let mut stmt = db.prepare("SELECT id, token FROM servers WHERE token IS NOT NULL")?;
+            let rows = stmt.query_map(&[], |row| (row.get::<_, ServerId>(0), row.get::<_, String>(1)))?;
+            for row in rows {
+                let (id, token) = row?;
+                let sealed = key.seal(&token_context(id), token.as_bytes())?;
+                db.execute("UPDATE servers SET token = NULL, token_sealed = ? WHERE id = ?", &[&sealed, &id])?;
+            }
+            Ok(())
+        })?;
+
+        self.salt = Some(salt);
+        self.key = Some(key);
+        Ok(())
+    }
+    /// Decrypt all tokens back into plain text and remove the vault.
+    pub fn disable(&mut self, db: &SqlConnection) -> Result<(), Error> {
+        {
+            let key = self.key.as_ref().ok_or(VaultError::Locked)?;
+
+            transaction(db, || {
+                let mut stmt = db.prepare("SELECT id, token_sealed FROM servers WHERE token_sealed IS NOT NULL")?;
+                let rows = stmt.query_map(&[], |row| (row.get::<_, ServerId>(0), row.get::<_, Vec<u8>>(1)))?;
+                for row in rows {
+                    let (id, sealed) = row?;
+                    let token = key.open(&token_context(id), &sealed)?;
+                    let token = String::from_utf8(token).map_err(|_| VaultError::Corrupt)?;
+                    db.execute("UPDATE servers SET token = ?, token_sealed = NULL WHERE id = ?", &[&token, &id])?;
+                }
+                db.execute("DELETE FROM vault", &[])?;
+                Ok(())
+            })?;
+        }
+
+        self.salt = None;
+        self.key = None;
+        Ok(())
+    }
+    pub fn unlock(&mut self, db: &SqlConnection, passphrase: &str) -> Result<(), Error> {
+        let key = {
+            let salt = self.salt.as_ref().ok_or(VaultError::NotEnabled)?;
+            Key::derive(passphrase, salt)?
+        };
+        let verifier: Vec<u8> = db.query_row("SELECT verifier FROM vault", &[], |row| row.get(0))?;
+        if key.open(b"verifier", &verifier)? != VERIFIER {
+            return Err(VaultError::Corrupt.into());
+        }
+
+        self.key = Some(key);
+        Ok(())
+    }
+    /// Forget the key. Tokens can't be decrypted until the vault is unlocked again.
+    pub fn lock(&mut self) {
+        self.key = None;
+    }
+    /// Decrypt the sealed token of a server
+    pub fn open_token(&self, id: ServerId, sealed: &[u8]) -> Result<String, VaultError> {
+        let key = self.key.as_ref().ok_or(VaultError::Locked)?;
+        let token = key.open(&token_context(id), sealed).map_err(|_| VaultError::Corrupt)?;
+        String::from_utf8(token).map_err(|_| VaultError::Corrupt)
+    }
+    /// Save a server's token, encrypted if the vault is set up.
+    /// Fails rather than saving in plain text if the vault is locked.
+    pub fn save_token(&self, db: &SqlConnection, id: ServerId, token: &str) -> Result<(), Error> {
+        match self.key {
+            Some(ref key) => {
+                let sealed = key.seal(&token_context(id), token.as_bytes())?;
+                db.execute("UPDATE servers SET token = NULL, token_sealed = ? WHERE id = ?", &[&sealed, &id])?;
+            },
+            None if self.is_enabled() => return Err(VaultError::Locked.into()),
+            None => {
+                db.execute("UPDATE servers SET token = ?, token_sealed = NULL WHERE id = ?", &[&token, &id])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut db = SqlConnection::open_in_memory().unwrap();
+    ::migrations::migrate(&mut db, None).unwrap();
+    db.execute_batch("INSERT INTO servers (ip, name, hash, token) VALUES
+        ('a', 'A', 'hash', 'token a'),
+        ('b', 'B', 'hash', NULL)").unwrap();
+
+    let mut vault = Vault::load(&db).unwrap();
+    assert!(!vault.is_enabled());
+    vault.save_token(&db, 2, "token b").unwrap();
+
+    // Existing tokens are moved into the vault
+    vault.enable(&db, "correct horse").unwrap();
+    let plain: i64 = db.query_row("SELECT COUNT(*) FROM servers WHERE token IS NOT NULL", &[], |row| row.get(0)).unwrap();
+    assert_eq!(plain, 0);
+    assert!(vault.enable(&db, "again").is_err());
+
+    let sealed = |id: ServerId| -> Vec<u8> {
+        db.query_row("SELECT token_sealed FROM servers WHERE id = ?", &[&id], |row| row.get(0)).unwrap()
+    };
+
+    let mut vault = Vault::load(&db).unwrap();
+    assert!(vault.is_enabled());
+    assert!(!vault.is_unlocked());
+    assert!(vault.should_ask());
+    vault.skip();
+    assert!(!vault.should_ask());
+    assert!(vault.open_token(1, &sealed(1)).is_err());
+    assert!(vault.save_token(&db, 1, "new token").is_err());
+
+    match vault.unlock(&db, "wrong").unwrap_err().downcast::<VaultError>() {
+        Ok(VaultError::WrongPassphrase) => (),
+        other => panic!("expected wrong passphrase, got {:?}", other)
+    }
+    vault.unlock(&db, "correct horse").unwrap();
+    assert_eq!(vault.open_token(1, &sealed(1)).unwrap(), "token a");
+    assert_eq!(vault.open_token(2, &sealed(2)).unwrap(), "token b");
+    // Tokens are bound to their server
+    assert!(vault.open_token(2, &sealed(1)).is_err());
+
+    vault.lock();
+    assert!(vault.open_token(1, &sealed(1)).is_err());
+    assert!(vault.disable(&db).is_err());
+
+    vault.unlock(&db, "correct horse").unwrap();
+    vault.disable(&db).unwrap();
+    assert!(!Vault::load(&db).unwrap().is_enabled());
+    let token: String = db.query_row("SELECT token FROM servers WHERE id = 2", &[], |row| row.get(0)).unwrap();
+    assert_eq!(token, "token b");
+}
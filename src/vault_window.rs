@@ -0,0 +1,169 @@
+use ::*;
+
+/// Ask for the vault passphrase until `try_unlock` accepts it or the user skips.
+/// Returns whether the vault got unlocked.
+pub(crate) fn unlock<F>(window: &Window, mut try_unlock: F) -> bool
+    where F: FnMut(&str) -> Result<(), Error>
+{
+    let dialog = Dialog::new_with_buttons(
+        Some("Synac: Unlock vault"),
+        Some(window),
+        DialogFlags::MODAL,
+        &[("_Skip", ResponseType::Cancel.into()), ("_Unlock", ResponseType::Ok.into())]
+    );
+    dialog.set_default_response(ResponseType::Ok.into());
+
+    let content = dialog.get_content_area();
+    content.set_property_margin(10);
+    content.set_spacing(6);
+    content.add(&Label::new("Your login tokens are encrypted. Enter the passphrase to unlock them,\n\
+                             or skip to log in with passwords instead."));
+
+    let entry = password_entry("Passphrase...");
+    entry.set_activates_default(true);
+    content.add(&entry);
+
+    let error = Label::new("");
+    content.add(&error);
+
+    dialog.show_all();
+    let unlocked = loop {
+        if dialog.run() != ResponseType::Ok.into() {
+            break false;
+        }
+        let passphrase = entry.get_text().unwrap_or_default();
+        match try_unlock(&passphrase) {
+            Ok(()) => break true,
+            Err(err) => {
+                error.set_text(&err.to_string());
+                entry.set_text("");
+                entry.grab_focus();
+            }
+        }
+    };
+    dialog.destroy();
+    unlocked
+}
+
+fn fill(app: &Rc<App>, window: &Window, container: &GtkBox) {
+    for child in container.get_children() {
+        container.remove(&child);
+    }
+
+    let (enabled, unlocked) = {
+        let vault = app.connections.vault.lock().unwrap();
+        (vault.is_enabled(), vault.is_unlocked())
+    };
+
+    let status = Label::new(match (enabled, unlocked) {
+        (false, _) => "Login tokens are saved in plain text. Anyone who can read your \
+                       data directory can log in as you.\n\
+                       Choose a passphrase to encrypt them. It's asked for on startup.",
+        (true, false) => "The vault is locked. Saved tokens can't be used until it's unlocked.",
+        (true, true) => "The vault is unlocked.\n\
+                         Locking it doesn't disconnect any servers, but new connections \
+                         need the passphrase or a password."
+    });
+    status.set_xalign(0.0);
+    status.set_line_wrap(true);
+    container.add(&status);
+
+    let controls = GtkBox::new(Orientation::Horizontal, 2);
+    controls.set_halign(Align::End);
+    controls.set_margin_top(10);
+
+    if !enabled {
+        let passphrase = password_entry("Passphrase...");
+        container.add(&passphrase);
+        let confirmation = password_entry("Confirm passphrase...");
+        container.add(&confirmation);
+
+        let enable = Button::new_with_mnemonic("_Encrypt tokens");
+        let app_clone = Rc::clone(app);
+        let window_clone = window.clone();
+        let container_clone = container.clone();
+        enable.connect_clicked(move |_| {
+            let text = passphrase.get_text().unwrap_or_default();
+            if text.is_empty() {
+                alert(&window_clone, MessageType::Error, "The passphrase can't be empty");
+                return;
+            }
+            if confirmation.get_text().unwrap_or_default() != text {
+                alert(&window_clone, MessageType::Error, "The passphrases don't match");
+                return;
+            }
+            if let Err(err) = app_clone.connections.vault.lock().unwrap().enable(&app_clone.db, &text) {
+                alert(&window_clone, MessageType::Error, &format!("failed to set up vault: {}", err));
+                return;
+            }
+            fill(&app_clone, &window_clone, &container_clone);
+        });
+        controls.add(&enable);
+    } else if !unlocked {
+        let unlock_button = Button::new_with_mnemonic("_Unlock...");
+        let app_clone = Rc::clone(app);
+        let window_clone = window.clone();
+        let container_clone = container.clone();
+        unlock_button.connect_clicked(move |_| {
+            // Only lock the vault while checking a passphrase, not while the dialog runs
+            let unlocked = unlock(&window_clone, |passphrase| {
+                app_clone.connections.vault.lock().unwrap().unlock(&app_clone.db, passphrase)
+            });
+            if unlocked {
+                fill(&app_clone, &window_clone, &container_clone);
+            }
+        });
+        controls.add(&unlock_button);
+    } else {
+        let disable = Button::new_with_mnemonic("_Decrypt tokens");
+        disable.set_tooltip_text("Remove the vault and save tokens in plain text again");
+        let app_clone = Rc::clone(app);
+        let window_clone = window.clone();
+        let container_clone = container.clone();
+        disable.connect_clicked(move |_| {
+            let app_clone = Rc::clone(&app_clone);
+            let window_clone2 = window_clone.clone();
+            let container_clone = container_clone.clone();
+            confirm(&window_clone, "Save all tokens in plain text again?", move || {
+                if let Err(err) = app_clone.connections.vault.lock().unwrap().disable(&app_clone.db) {
+                    alert(&window_clone2, MessageType::Error, &format!("failed to remove vault: {}", err));
+                }
+                fill(&app_clone, &window_clone2, &container_clone);
+            });
+        });
+        controls.add(&disable);
+
+        let lock = Button::new_with_mnemonic("_Lock");
+        let app_clone = Rc::clone(app);
+        let window_clone = window.clone();
+        let container_clone = container.clone();
+        lock.connect_clicked(move |_| {
+            app_clone.connections.vault.lock().unwrap().lock();
+            fill(&app_clone, &window_clone, &container_clone);
+        });
+        controls.add(&lock);
+    }
+
+    let close = Button::new_with_mnemonic("_Close");
+    let window_clone = window.clone();
+    close.connect_clicked(move |_| window_clone.destroy());
+    controls.add(&close);
+
+    container.add(&controls);
+    container.show_all();
+}
+
+pub(crate) fn show(app: &Rc<App>) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Synac: Token vault");
+    window.set_transient_for(&app.window);
+    window.set_position(gtk::WindowPosition::CenterOnParent);
+    window.set_default_size(450, 0);
+
+    let container = GtkBox::new(Orientation::Vertical, 6);
+    container.set_property_margin(10);
+    fill(app, &window, &container);
+
+    window.add(&container);
+    window.show_all();
+}